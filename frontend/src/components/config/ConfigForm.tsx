@@ -113,6 +113,16 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
           label={t("config.sections.network.rproxy")}
           placeholder={t("config.sections.network.rproxyPlaceholder")}
         />
+
+        <FormInput
+          id="anthropic_api_base"
+          name="anthropic_api_base"
+          type="text"
+          value={config.anthropic_api_base || ""}
+          onChange={onChange}
+          label={t("config.sections.network.anthropicApiBase")}
+          placeholder="https://api.anthropic.com"
+        />
       </ConfigSection>
 
       {/* Vertex Settings Section */}
@@ -179,6 +189,62 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
             onChange={onChange}
             label={t("config.sections.api.maxRetries")}
           />
+          <FormInput
+            id="structured_output_retries"
+            name="structured_output_retries"
+            type="number"
+            value={config.structured_output_retries.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.structuredOutputRetries")}
+          />
+          <FormInput
+            id="hedge_delay_ms"
+            name="hedge_delay_ms"
+            type="number"
+            value={config.hedge_delay_ms.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.hedgeDelayMs")}
+          />
+          <FormInput
+            id="max_hedged_requests"
+            name="max_hedged_requests"
+            type="number"
+            value={config.max_hedged_requests.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.maxHedgedRequests")}
+          />
+          <FormInput
+            id="fetch_max_size"
+            name="fetch_max_size"
+            type="number"
+            value={config.fetch_max_size.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.fetchMaxSize")}
+          />
+          <FormInput
+            id="fetch_timeout"
+            name="fetch_timeout"
+            type="number"
+            value={config.fetch_timeout.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.fetchTimeout")}
+          />
+          <FormInput
+            id="image_max_dimension"
+            name="image_max_dimension"
+            type="number"
+            value={config.image_max_dimension.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.imageMaxDimension")}
+          />
+          <FormInput
+            id="image_max_size"
+            name="image_max_size"
+            type="number"
+            value={config.image_max_size.toString()}
+            onChange={onChange}
+            label={t("config.sections.api.imageMaxSize")}
+          />
         </div>
         <div className="flex space-x-6">
           <ConfigCheckbox
@@ -195,6 +261,20 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
             label={t("config.sections.api.preserveChats")}
           />
 
+          <ConfigCheckbox
+            name="reuse_conversation"
+            checked={config.reuse_conversation}
+            onChange={onChange}
+            label={t("config.sections.api.reuseConversation")}
+          />
+
+          <ConfigCheckbox
+            name="stream_failover"
+            checked={config.stream_failover}
+            onChange={onChange}
+            label={t("config.sections.api.streamFailover")}
+          />
+
           <ConfigCheckbox
             name="web_search"
             checked={config.web_search}
@@ -286,6 +366,45 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
           label={t("config.sections.prompt.realRoles")}
         />
 
+        <div className="flex space-x-6">
+          <ConfigCheckbox
+            name="prefill"
+            checked={config.prefill}
+            onChange={onChange}
+            label={t("config.sections.prompt.prefill")}
+          />
+
+          <ConfigCheckbox
+            name="prefill_prepend"
+            checked={config.prefill_prepend}
+            onChange={onChange}
+            label={t("config.sections.prompt.prefillPrepend")}
+          />
+
+          <ConfigCheckbox
+            name="last_turn_in_prompt"
+            checked={config.last_turn_in_prompt}
+            onChange={onChange}
+            label={t("config.sections.prompt.lastTurnInPrompt")}
+          />
+
+          <ConfigCheckbox
+            name="keep_thinking"
+            checked={config.keep_thinking}
+            onChange={onChange}
+            label={t("config.sections.prompt.keepThinking")}
+          />
+        </div>
+
+        <FormInput
+          id="attachment_chunk_tokens"
+          name="attachment_chunk_tokens"
+          type="number"
+          value={config.attachment_chunk_tokens.toString()}
+          onChange={onChange}
+          label={t("config.sections.prompt.attachmentChunkTokens")}
+        />
+
         <FormInput
           id="custom_h"
           name="custom_h"
@@ -331,6 +450,42 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
           onChange={onChange}
           label={t("config.sections.prompt.padtxtLen")}
         />
+
+        <FormInput
+          id="pad_threshold"
+          name="pad_threshold"
+          type="number"
+          value={config.pad_threshold.toString()}
+          onChange={onChange}
+          label={t("config.sections.prompt.padThreshold")}
+        />
+
+        <FormInput
+          id="timezone"
+          name="timezone"
+          type="text"
+          value={config.timezone}
+          onChange={onChange}
+          label={t("config.sections.prompt.timezone")}
+        />
+
+        <FormInput
+          id="locale"
+          name="locale"
+          type="text"
+          value={config.locale || ""}
+          onChange={onChange}
+          label={t("config.sections.prompt.locale")}
+        />
+
+        <FormInput
+          id="template_file"
+          name="template_file"
+          type="text"
+          value={config.template_file || ""}
+          onChange={onChange}
+          label={t("config.sections.prompt.templateFile")}
+        />
       </ConfigSection>
     </div>
   );
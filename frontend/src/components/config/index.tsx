@@ -135,9 +135,14 @@ const ConfigTab: React.FC = () => {
       return;
     } // Handle empty strings for nullable fields
     if (
-      ["proxy", "rproxy", "custom_h", "custom_a", "padtxt_file"].includes(
-        name
-      ) &&
+      [
+        "proxy",
+        "rproxy",
+        "anthropic_api_base",
+        "custom_h",
+        "custom_a",
+        "padtxt_file",
+      ].includes(name) &&
       value === ""
     ) {
       setConfig({ ...config, [name]: null });
@@ -7,6 +7,9 @@ export interface ConfigData {
   // Vertex settings
   vertex: VertexConfig;
 
+  // User keys
+  user_keys: UserKey[];
+
   // App settings
   check_update: boolean;
   auto_update: boolean;
@@ -16,12 +19,29 @@ export interface ConfigData {
   admin_password: string;
   proxy: string | null;
   rproxy: string | null;
+  anthropic_api_base: string | null;
 
   // API settings
   max_retries: number;
   pass_params: boolean;
   preserve_chats: boolean;
+  reuse_conversation: boolean;
+  stream_failover: boolean;
+  hedge_models: string[];
+  hedge_delay_ms: number;
+  max_hedged_requests: number;
+  claude_retry: RetryPolicy;
+  gemini_retry: RetryPolicy;
+  anthropic_retry: RetryPolicy;
+  openai_retry: RetryPolicy;
+  model_upstreams: Record<string, Upstream>;
   web_search: boolean;
+  structured_output_retries: number;
+  fetch_allowlist: string[];
+  fetch_max_size: number;
+  fetch_timeout: number;
+  image_max_dimension: number;
+  image_max_size: number;
 
   // Cache settings
   cache_response: number;
@@ -41,8 +61,70 @@ export interface ConfigData {
   custom_h: string | null;
   custom_a: string | null;
   custom_prompt: string;
+  attachment_mode: "prompt" | "paste" | "chunked";
+  attachment_chunk_tokens: number;
+  last_turn_in_prompt: boolean;
+  keep_thinking: boolean;
+  timezone: string;
+  locale: string | null;
+  prefill: boolean;
+  prefill_prepend: boolean;
   padtxt_file: string | null;
   padtxt_len: number;
+  padtxt_files: string[];
+  pad_threshold: number;
+  pad_separator: string;
+  pad_seed: number | null;
+  pad_placement: "before_system" | "after_system" | "attachment";
+  template_file: string | null;
+  model_templates: Record<string, string>;
+  regex_rules: RegexRule[];
+  regex_window: number;
+  model_aliases: ModelAlias[];
+  profiles: Profile[];
+  model_profiles: Record<string, string>;
+}
+
+interface UserKey {
+  key: string;
+  template?: string;
+  profile?: string;
+  timezone?: string;
+  locale?: string;
+  upstream?: Upstream;
+}
+
+type Upstream = "cookie" | "api_key" | "openai" | "gemini";
+
+interface RegexRule {
+  pattern: string;
+  replacement: string;
+  scope: "input" | "output" | "both";
+  models?: string[];
+}
+
+interface ModelAlias {
+  pattern: string;
+  target: string;
+  thinking?: boolean;
+  max_tokens?: number;
+  template?: string;
+}
+
+interface Profile {
+  name: string;
+  project_uuid?: string;
+  knowledge?: string[];
+  instructions?: string;
+  style?: string;
+}
+
+interface RetryPolicy {
+  same_credential_retries: number;
+  base_delay_ms: number;
+  max_delay_ms: number;
+  deadline_secs: number;
+  cooldown_secs: number;
 }
 
 interface VertexConfig {
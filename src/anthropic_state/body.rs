@@ -0,0 +1,263 @@
+use serde_json::{Value, json};
+
+use crate::{error::ClewdrError, types::claude_message::CreateMessageParams};
+
+/// Smallest thinking budget accepted by the Anthropic API
+pub(crate) const MIN_THINKING_BUDGET: u64 = 1024;
+
+/// Tokens left for the answer when the thinking budget exceeds `max_tokens`
+const ANSWER_TOKENS: u32 = 4096;
+
+/// Server tool used for OpenAI style `web_search_options`
+const WEB_SEARCH_TOOL: &str = "web_search_20250305";
+
+/// Fields of the Messages API, others are extensions of ClewdR or OpenAI
+const API_FIELDS: [&str; 17] = [
+    "model",
+    "messages",
+    "max_tokens",
+    "system",
+    "stop_sequences",
+    "stream",
+    "temperature",
+    "top_k",
+    "top_p",
+    "thinking",
+    "tools",
+    "tool_choice",
+    "metadata",
+    "service_tier",
+    "container",
+    "mcp_servers",
+    "context_management",
+];
+
+/// Converts a client request into a request of the Anthropic API
+///
+/// The raw body is passed through, so fields ClewdR does not model, such as
+/// `cache_control` or `is_error`, reach the API. Only the extensions that
+/// ClewdR accepts on top of the Messages API are translated: system messages
+/// and `stop` of OpenAI clients, `-thinking` models, `response_format`,
+/// `web_search_options` and image URLs.
+///
+/// # Arguments
+/// * `body` - The raw client request
+/// * `p` - The client request as parsed and resolved by ClewdR
+///
+/// # Returns
+/// * `Result<Value, ClewdrError>` - Body of the Anthropic API request
+pub fn api_body(mut body: Value, p: &CreateMessageParams) -> Result<Value, ClewdrError> {
+    let Some(fields) = body.as_object_mut() else {
+        return Err(ClewdrError::BadRequest(
+            "Request body must be an object".to_string(),
+        ));
+    };
+    fields.retain(|k, _| API_FIELDS.contains(&k.as_str()));
+    // the model and its parameters may be resolved from an alias
+    body["model"] = json!(p.model);
+
+    // OpenAI clients send the system prompt as messages
+    let (system, messages): (Vec<_>, Vec<_>) = match body["messages"].take() {
+        Value::Array(messages) => messages.into_iter().partition(|m| m["role"] == "system"),
+        _ => (vec![], vec![]),
+    };
+    body["messages"] = Value::Array(messages);
+    let mut system_blocks = match body["system"].take() {
+        Value::String(s) if !s.is_empty() => vec![json!({ "type": "text", "text": s })],
+        Value::Array(blocks) => blocks,
+        _ => vec![],
+    };
+    for mut m in system {
+        match m["content"].take() {
+            Value::String(s) => system_blocks.push(json!({ "type": "text", "text": s })),
+            Value::Array(blocks) => {
+                system_blocks.extend(blocks.into_iter().filter(|b| b["type"] == "text"))
+            }
+            _ => {}
+        }
+    }
+    // the API has no structured output, ask for it in the system prompt
+    if let Some(instruction) = p.response_format.as_ref().and_then(|f| f.instruction()) {
+        system_blocks.push(json!({ "type": "text", "text": instruction }));
+    }
+    if system_blocks.is_empty() {
+        remove_field(&mut body, "system");
+    } else {
+        body["system"] = Value::Array(system_blocks);
+    }
+
+    let mut stop_sequences = p.stop_sequences.to_owned().unwrap_or_default();
+    stop_sequences.extend(p.stop.iter().flatten().cloned());
+    stop_sequences.sort();
+    stop_sequences.dedup();
+    if !stop_sequences.is_empty() {
+        body["stop_sequences"] = json!(stop_sequences);
+    }
+
+    // `-thinking` models enable thinking without a budget
+    let mut max_tokens = p.max_tokens;
+    match p.thinking.as_ref().filter(|t| t.is_enabled()) {
+        Some(t) => {
+            let mut budget = t.budget_tokens;
+            if budget == 0 {
+                budget = (max_tokens / 2) as u64;
+            }
+            let budget = budget.max(MIN_THINKING_BUDGET);
+            if max_tokens as u64 <= budget {
+                max_tokens = budget as u32 + ANSWER_TOKENS;
+            }
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+        }
+        None => remove_field(&mut body, "thinking"),
+    }
+    body["max_tokens"] = json!(max_tokens);
+
+    if p.web_search_options.is_some() && !p.tools.iter().flatten().any(|t| t.is_web_search()) {
+        let tool = json!({ "type": WEB_SEARCH_TOOL, "name": "web_search" });
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["tools"] = json!([tool]),
+        }
+    }
+    // the API only accepts a user id as metadata
+    match body["metadata"]["user_id"].take() {
+        Value::Null => remove_field(&mut body, "metadata"),
+        user_id => body["metadata"] = json!({ "user_id": user_id }),
+    }
+    for block in body["messages"]
+        .as_array_mut()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["content"].as_array_mut())
+        .flatten()
+    {
+        fix_image(block);
+    }
+    Ok(body)
+}
+
+/// Removes a field of the request body
+fn remove_field(body: &mut Value, field: &str) {
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove(field);
+    }
+}
+
+/// Rewrites an image block into the form accepted by the API
+///
+/// OpenAI `image_url` blocks become image blocks, and URL sources lose the
+/// empty fields of base64 sources.
+fn fix_image(block: &mut Value) {
+    match block["type"].as_str() {
+        Some("image_url") => {
+            let url = block["image_url"]["url"].as_str().unwrap_or_default();
+            let source = match url
+                .strip_prefix("data:")
+                .and_then(|d| d.split_once(";base64,"))
+            {
+                Some((media_type, data)) => {
+                    json!({ "type": "base64", "media_type": media_type, "data": data })
+                }
+                None => json!({ "type": "url", "url": url }),
+            };
+            *block = json!({ "type": "image", "source": source });
+        }
+        Some("image") if block["source"]["type"] == "url" => {
+            let url = block["source"]["url"].to_owned();
+            block["source"] = json!({ "type": "url", "url": url });
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn convert(raw: Value) -> Value {
+        let p = CreateMessageParams::deserialize(&raw).unwrap();
+        api_body(raw, &p).unwrap()
+    }
+
+    #[test]
+    fn passes_native_fields_through() {
+        let body = convert(json!({
+            "model": "claude-sonnet-4-0",
+            "max_tokens": 1024,
+            "system": [{ "type": "text", "text": "Be brief", "cache_control": { "type": "ephemeral" } }],
+            "messages": [
+                { "role": "user", "content": [{ "type": "text", "text": "Hi", "cache_control": { "type": "ephemeral" } }] },
+                { "role": "assistant", "content": [{ "type": "tool_use", "id": "t1", "name": "f", "input": {} }] },
+                { "role": "user", "content": [{
+                    "type": "tool_result",
+                    "tool_use_id": "t1",
+                    "is_error": true,
+                    "content": [{ "type": "text", "text": "failed" }],
+                }] },
+            ],
+            "tools": [{ "name": "f", "input_schema": { "type": "object" }, "cache_control": { "type": "ephemeral" } }],
+        }));
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+        assert_eq!(
+            body["messages"][0]["content"][0]["cache_control"]["type"],
+            "ephemeral"
+        );
+        assert_eq!(body["messages"][2]["content"][0]["is_error"], true);
+        assert_eq!(
+            body["messages"][2]["content"][0]["content"][0]["text"],
+            "failed"
+        );
+        assert_eq!(body["tools"][0]["cache_control"]["type"], "ephemeral");
+        assert!(body.get("thinking").is_none());
+        assert!(body.get("metadata").is_none());
+    }
+
+    #[test]
+    fn translates_openai_extensions() {
+        let raw = json!({
+            "model": "claude-sonnet-4-0",
+            "max_tokens": 1024,
+            "messages": [
+                { "role": "system", "content": "Be brief" },
+                { "role": "user", "content": [
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                    { "type": "text", "text": "What is this?" },
+                ] },
+            ],
+            "stop": ["END"],
+            "stop_sequences": ["STOP"],
+            "web_search_options": {},
+            "frequency_penalty": 0.5,
+            "metadata": { "user_id": "u1", "timezone": "UTC" },
+        });
+        let mut p = CreateMessageParams::deserialize(&raw).unwrap();
+        // resolved from a `-thinking` model
+        p.thinking = Some(Default::default());
+        let body = api_body(raw, &p).unwrap();
+        assert_eq!(
+            body["system"],
+            json!([{ "type": "text", "text": "Be brief" }])
+        );
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            body["messages"][0]["content"][0],
+            json!({ "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } })
+        );
+        assert_eq!(body["stop_sequences"], json!(["END", "STOP"]));
+        assert!(body.get("stop").is_none());
+        assert!(body.get("frequency_penalty").is_none());
+        assert!(body.get("web_search_options").is_none());
+        assert_eq!(body["tools"][0]["type"], WEB_SEARCH_TOOL);
+        assert_eq!(body["metadata"], json!({ "user_id": "u1" }));
+        assert_eq!(
+            body["thinking"],
+            json!({ "type": "enabled", "budget_tokens": MIN_THINKING_BUDGET })
+        );
+        assert_eq!(
+            body["max_tokens"],
+            MIN_THINKING_BUDGET + ANSWER_TOKENS as u64
+        );
+    }
+}
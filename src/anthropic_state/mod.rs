@@ -0,0 +1,290 @@
+use std::sync::LazyLock;
+
+use axum::{
+    body::Body,
+    http::{HeaderValue, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use colored::Colorize;
+use rquest::{Client, ClientBuilder, StatusCode, header::HeaderMap};
+use serde_json::Value;
+use tracing::{error, info};
+
+use crate::{
+    config::{AnthropicKey, CLEWDR_CONFIG, KeyStatus},
+    error::{CheckClaudeErr, ClewdrError, RetryClass},
+    services::key_manager::KeyEventSender,
+    types::claude_message::{CountMessageTokensResponse, CreateMessageParams},
+    utils::{print_out_json, retry::Backoff},
+};
+
+pub mod body;
+
+/// Version of the Anthropic API the requests are written for
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+static DUMMY_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+/// Limits of the API reporting their reset time in `anthropic-ratelimit-*-reset`
+const RATE_LIMITS: [&str; 4] = ["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Reads when a rate limit resets from the headers of a 429 response
+///
+/// `retry-after` gives the seconds to wait. Without it, the latest reset of
+/// the limits is used, as the headers do not tell which one was exceeded.
+///
+/// # Returns
+/// * `Option<i64>` - Unix timestamp of the reset, None if the headers have none
+fn rate_limit_reset(headers: &HeaderMap) -> Option<i64> {
+    let now = chrono::Utc::now().timestamp();
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(secs) = header("retry-after").and_then(|v| v.trim().parse::<i64>().ok()) {
+        return Some(now + secs);
+    }
+    RATE_LIMITS
+        .iter()
+        .filter_map(|l| header(&format!("anthropic-ratelimit-{}-reset", l)))
+        .filter_map(|v| chrono::DateTime::parse_from_rfc3339(v).ok())
+        .map(|t| t.timestamp())
+        .filter(|t| *t > now)
+        .max()
+}
+
+/// State of a request served with an Anthropic API key
+#[derive(Clone)]
+pub struct AnthropicState {
+    pub key: Option<KeyStatus<AnthropicKey>>,
+    pub event_sender: KeyEventSender<AnthropicKey>,
+    pub client: Client,
+    /// When the rate limit hit by the last request resets, if the API said so
+    pub reset_time: Option<i64>,
+}
+
+impl AnthropicState {
+    /// Create a new AnthropicState instance
+    pub fn new(tx: KeyEventSender<AnthropicKey>) -> Self {
+        AnthropicState {
+            key: None,
+            event_sender: tx,
+            client: DUMMY_CLIENT.to_owned(),
+            reset_time: None,
+        }
+    }
+
+    /// Requests a key from the pool
+    /// Builds a new client with the newest proxy configuration
+    pub async fn request_key(&mut self) -> Result<(), ClewdrError> {
+        let key = self.event_sender.request().await?;
+        self.key = Some(key);
+        let client = ClientBuilder::new();
+        let client = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
+            client.proxy(proxy)
+        } else {
+            client
+        };
+        self.client = client.build()?;
+        Ok(())
+    }
+
+    /// Sends a request to the Messages API
+    ///
+    /// # Arguments
+    /// * `body` - Body of the Anthropic API request
+    ///
+    /// # Returns
+    /// * `Result<rquest::Response, ClewdrError>` - The successful response
+    pub async fn send_chat(&mut self, body: &Value) -> Result<rquest::Response, ClewdrError> {
+        // a retry on the same key keeps it
+        if self.key.is_none() {
+            self.request_key().await?;
+        }
+        let Some(ref key) = self.key else {
+            return Err(ClewdrError::UnexpectedNone);
+        };
+        info!("[KEY] {}", key.key.ellipse().green());
+        let endpoint = format!("{}/v1/messages", CLEWDR_CONFIG.load().anthropic_endpoint());
+        let res = self
+            .client
+            .post(endpoint)
+            .header("x-api-key", key.key.inner.as_str())
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body)
+            .send()
+            .await?;
+        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+            self.reset_time = rate_limit_reset(res.headers());
+        }
+        let res = res.check_claude().await?;
+        Ok(res)
+    }
+
+    /// Counts the input tokens of a request with the API
+    ///
+    /// # Arguments
+    /// * `body` - Body of the token counting request, passed through
+    ///
+    /// # Returns
+    /// * `Result<CountMessageTokensResponse, ClewdrError>` - The token count of the API
+    pub async fn count_tokens(
+        &mut self,
+        body: &Value,
+    ) -> Result<CountMessageTokensResponse, ClewdrError> {
+        self.request_key().await?;
+        let Some(ref key) = self.key else {
+            return Err(ClewdrError::UnexpectedNone);
+        };
+        info!("[KEY] {}", key.key.ellipse().green());
+        let endpoint = format!(
+            "{}/v1/messages/count_tokens",
+            CLEWDR_CONFIG.load().anthropic_endpoint()
+        );
+        let res = self
+            .client
+            .post(endpoint)
+            .header("x-api-key", key.key.inner.as_str())
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body)
+            .send()
+            .await?
+            .check_claude()
+            .await?
+            .json()
+            .await?;
+        Ok(res)
+    }
+
+    /// Sends the request, retrying failures following `anthropic_retry`
+    ///
+    /// Transient errors are retried on the same key, rate limited keys cool down,
+    /// keys rejected as invalid are removed from the pool, and errors caused by
+    /// the request fail immediately.
+    ///
+    /// # Arguments
+    /// * `raw` - The raw client request
+    /// * `p` - The client request as parsed by ClewdR
+    ///
+    /// # Returns
+    /// * `Result<Response, ClewdrError>` - The response of the API, passed through
+    pub async fn try_chat(
+        &mut self,
+        raw: Value,
+        p: &CreateMessageParams,
+    ) -> Result<Response, ClewdrError> {
+        let body = body::api_body(raw, p)?;
+        print_out_json(&body, "anthropic_req.json");
+        let policy = CLEWDR_CONFIG.load().anthropic_retry.to_owned();
+        let mut backoff = Backoff::new(policy.to_owned());
+        // state holding the key to retry on, and the retries made on it
+        let mut same_key: Option<AnthropicState> = None;
+        let mut same_retries = 0;
+        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+            if i > 0 {
+                info!("[RETRY] attempt: {}", i.to_string().green());
+            }
+            let mut state = same_key.take().unwrap_or_else(|| self.to_owned());
+
+            match state.send_chat(&body).await {
+                Ok(res) => return Ok(state.transform_response(res)),
+                Err(e) => {
+                    if let Some(ref key) = state.key {
+                        error!("[{}] {}", key.key.ellipse().green(), e);
+                    } else {
+                        error!("{}", e);
+                    }
+                    match e.retry_class() {
+                        RetryClass::Fatal => return Err(e),
+                        RetryClass::SameCredential
+                            if same_retries < policy.same_credential_retries =>
+                        {
+                            same_retries += 1;
+                            same_key = Some(state);
+                        }
+                        _ => {
+                            same_retries = 0;
+                            if let Some(key) = state.key.take() {
+                                let invalid = matches!(
+                                    e,
+                                    ClewdrError::ClaudeHttpError(StatusCode::UNAUTHORIZED, _)
+                                );
+                                state.release_key(key, invalid, e.is_rate_limit()).await;
+                            }
+                        }
+                    }
+                    if !backoff.wait().await {
+                        error!("Retry deadline exceeded");
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        error!("Max retries exceeded");
+        Err(ClewdrError::TooManyRetries)
+    }
+
+    /// Returns a failed key to the pool, or removes it if it is invalid
+    ///
+    /// # Arguments
+    /// * `key` - The key the request failed with
+    /// * `invalid` - Whether the key was rejected as invalid
+    /// * `rate_limited` - Whether the key was rate limited
+    async fn release_key(&self, key: KeyStatus<AnthropicKey>, invalid: bool, rate_limited: bool) {
+        if invalid {
+            info!("Removing invalid key: {}", key.key.ellipse().red());
+            if let Err(e) = self.event_sender.delete_key(key).await {
+                error!("Failed to delete key: {}", e);
+            }
+            return;
+        }
+        let reset_time = rate_limited.then(|| {
+            self.reset_time
+                .unwrap_or_else(|| CLEWDR_CONFIG.load().anthropic_retry.cooldown_until())
+        });
+        self.event_sender
+            .return_key(key, reset_time)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to send key: {}", e);
+            });
+    }
+
+    /// Passes the response of the API through to the client
+    /// The body is streamed as it arrives, as SSE events or a JSON message
+    fn transform_response(&self, res: rquest::Response) -> Response {
+        let content_type = res
+            .headers()
+            .get("content-type")
+            .and_then(|v| HeaderValue::from_bytes(v.as_bytes()).ok());
+        let mut response = Body::from_stream(res.bytes_stream()).into_response();
+        if let Some(content_type) = content_type {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rquest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn reads_reset_from_headers() {
+        let now = chrono::Utc::now();
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_reset(&headers), None);
+
+        let at = |secs: i64| {
+            let t = now + chrono::Duration::seconds(secs);
+            HeaderValue::from_str(&t.to_rfc3339()).unwrap()
+        };
+        headers.insert("anthropic-ratelimit-requests-reset", at(30));
+        headers.insert("anthropic-ratelimit-tokens-reset", at(90));
+        headers.insert("anthropic-ratelimit-input-tokens-reset", at(-10));
+        assert_eq!(rate_limit_reset(&headers), Some(now.timestamp() + 90));
+
+        headers.insert("retry-after", HeaderValue::from_static("20"));
+        let reset = rate_limit_reset(&headers).unwrap();
+        assert!((now.timestamp() + 20..=now.timestamp() + 21).contains(&reset));
+    }
+}
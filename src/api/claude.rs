@@ -1,12 +1,21 @@
-use axum::{Extension, extract::State, response::Response};
+use axum::{Extension, Json, extract::State, http::HeaderMap, response::Response};
 use colored::Colorize;
 use scopeguard::defer;
+use serde_json::{Value, json};
 use tracing::info;
 
 use crate::{
+    anthropic_state::{AnthropicState, body::MIN_THINKING_BUDGET},
     claude_state::{ClaudeApiFormat, ClaudeState},
+    config::{CLEWDR_CONFIG, Upstream},
     error::ClewdrError,
-    middleware::claude::{ClaudeContext, ClaudePreprocess},
+    gemini_state::GeminiState,
+    middleware::{
+        claude::{ClaudeContext, ClaudePreprocess},
+        request_key,
+    },
+    openai_state::OpenAiState,
+    types::claude_message::{CountMessageTokensParams, CountMessageTokensResponse},
     utils::{enabled, print_out_json},
 };
 /// Axum handler for the API messages
@@ -16,30 +25,39 @@ use crate::{
 /// # Arguments
 /// * `XApiKey(_)` - API key authentication
 /// * `state` - Application state containing client information
+/// * `anthropic` - State of the Anthropic API key upstream
+/// * `gemini` - State of the Gemini upstream
 /// * `p` - Request body containing messages and configuration
 ///
 /// # Returns
 /// * `Response` - Stream or JSON response from Claude
 pub async fn api_claude(
     State(mut state): State<ClaudeState>,
-    ClaudePreprocess(p, f): ClaudePreprocess,
+    Extension(mut anthropic): Extension<AnthropicState>,
+    Extension(mut gemini): Extension<GeminiState>,
+    ClaudePreprocess(p, f, raw): ClaudePreprocess,
 ) -> (Extension<ClaudeContext>, Result<Response, ClewdrError>) {
     // Check if the request is a test message
     let stream = p.stream.unwrap_or_default();
     print_out_json(&p, "client_req.json");
     state.api_format = f.api_format;
     state.stream = stream;
+    state.user_key = f.user_key.to_owned();
+    state.template = f.template.to_owned();
+    state.timezone = f.timezone.to_owned();
+    state.locale = f.locale.to_owned();
     let format_display = match f.api_format {
         ClaudeApiFormat::Claude => f.api_format.to_string().green(),
         ClaudeApiFormat::OpenAI => f.api_format.to_string().yellow(),
     };
     info!(
-        "[REQ] stream: {}, msgs: {}, model: {}, think: {}, format: {}",
+        "[REQ] stream: {}, msgs: {}, model: {}, think: {}, format: {}, upstream: {}",
         enabled(stream),
         p.messages.len().to_string().green(),
         p.model.green(),
-        enabled(p.thinking.is_some()),
-        format_display
+        enabled(p.thinking.as_ref().is_some_and(|t| t.is_enabled())),
+        format_display,
+        f.upstream.to_string().blue()
     );
     let stopwatch = chrono::Utc::now();
     defer!(
@@ -49,5 +67,71 @@ pub async fn api_claude(
             format!("{}", elapsed.num_milliseconds() as f64 / 1000.0).green()
         );
     );
-    (Extension(f), state.try_chat(p).await)
+    let res = match f.upstream {
+        Upstream::Cookie => state.try_chat(p).await,
+        Upstream::ApiKey => match raw {
+            Some(raw) => anthropic.try_chat(raw, &p).await,
+            None => Err(ClewdrError::UnexpectedNone),
+        },
+        Upstream::OpenAi => match CLEWDR_CONFIG.load().provider_for(&p.model) {
+            Ok(provider) => {
+                info!("[PROVIDER] {}", provider.name.green());
+                OpenAiState::new(provider.to_owned(), stream)
+                    .try_chat(p)
+                    .await
+            }
+            Err(e) => Err(e),
+        },
+        Upstream::Gemini => gemini.try_claude(p).await,
+    };
+    (Extension(f), res)
+}
+
+/// Axum handler for counting the input tokens of a message request
+///
+/// The request is counted by the upstream serving the model: API keys use
+/// the count_tokens endpoint of the API, Gemini models use Gemini's `countTokens`.
+/// Requests served with cookies or OpenAI compatible providers get a local
+/// estimate, no request is sent.
+///
+/// # Arguments
+/// * `state` - Application state containing client information
+/// * `anthropic` - State of the Anthropic API key upstream
+/// * `gemini` - State of the Gemini upstream
+/// * `headers` - Request headers, used to find the user key
+/// * `body` - Request body containing messages, system prompt and tools
+///
+/// # Returns
+/// * `Result<Json<CountMessageTokensResponse>, ClewdrError>` - The input tokens
+pub async fn api_count_tokens(
+    State(mut state): State<ClaudeState>,
+    Extension(mut anthropic): Extension<AnthropicState>,
+    Extension(mut gemini): Extension<GeminiState>,
+    headers: HeaderMap,
+    Json(mut body): Json<Value>,
+) -> Result<Json<CountMessageTokensResponse>, ClewdrError> {
+    let mut p = serde_json::from_value::<CountMessageTokensParams>(body.to_owned())
+        .map_err(|e| ClewdrError::BadRequest(e.to_string()))?;
+    let config = CLEWDR_CONFIG.load();
+    state.user_key = request_key(&headers).and_then(|k| config.user_key(k).cloned());
+    if let Some((model, alias)) = config.model_alias(&p.model) {
+        p.model = model;
+        state.template = alias.template.to_owned();
+    }
+    if let Some(model) = p.model.strip_suffix("-thinking") {
+        p.model = model.to_string();
+        p.thinking.get_or_insert_default();
+        if body["thinking"].is_null() {
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": MIN_THINKING_BUDGET });
+        }
+    }
+    let input_tokens = match config.upstream_for(&p.model, state.user_key.as_ref()) {
+        Upstream::ApiKey => {
+            body["model"] = p.model.into();
+            return Ok(Json(anthropic.count_tokens(&body).await?));
+        }
+        Upstream::Gemini => gemini.count_claude_tokens(&p.into()).await?,
+        Upstream::Cookie | Upstream::OpenAi => state.count_tokens(p),
+    };
+    Ok(Json(CountMessageTokensResponse { input_tokens }))
 }
@@ -38,6 +38,8 @@ pub async fn api_get_config(
         obj.remove("cookie_array");
         obj.remove("wasted_cookie");
         obj.remove("gemini_keys");
+        obj.remove("anthropic_keys");
+        obj.remove("openai_providers");
     }
 
     Ok(Json(config_json))
@@ -72,6 +74,8 @@ pub async fn api_post_config(
         new_c.cookie_array = old_c.cookie_array.to_owned();
         new_c.wasted_cookie = old_c.wasted_cookie.to_owned();
         new_c.gemini_keys = old_c.gemini_keys.to_owned();
+        new_c.anthropic_keys = old_c.anthropic_keys.to_owned();
+        new_c.openai_providers = old_c.openai_providers.to_owned();
         new_c
     });
     if let Err(e) = CLEWDR_CONFIG.load().save() {
@@ -1,18 +1,21 @@
 use axum::{
+    Json,
     body::Body,
-    extract::State,
+    extract::{FromRequest, Path, Request, State},
     response::{IntoResponse, Response},
 };
 use colored::Colorize;
 use futures::{FutureExt, StreamExt};
 use serde::Serialize;
+use serde_json::Value;
 use tracing::info;
 
 use crate::{
+    config::CLEWDR_CONFIG,
     error::ClewdrError,
     gemini_state::{GeminiApiFormat, GeminiState},
     middleware::gemini::{GeminiContext, GeminiOaiPreprocess, GeminiPreprocess},
-    services::cache::GetHashKey,
+    services::{cache::GetHashKey, model_list::with_gemini_aliases},
     utils::enabled,
 };
 
@@ -61,9 +64,29 @@ async fn handle_gemini_request<T: Serialize + GetHashKey + Clone + Send + 'stati
 }
 
 pub async fn api_post_gemini(
-    State(state): State<GeminiState>,
-    GeminiPreprocess(body, ctx): GeminiPreprocess,
+    State(mut state): State<GeminiState>,
+    Path(path): Path<String>,
+    req: Request,
 ) -> Result<Response, ClewdrError> {
+    // token counting is proxied as is, without caching or retries
+    if path.ends_with(":countTokens") {
+        if req.uri().path().contains("vertex") {
+            return Err(ClewdrError::BadRequest(
+                "countTokens is not supported for Vertex".to_string(),
+            ));
+        }
+        let Json(body) = Json::<Value>::from_request(req, &()).await?;
+        let mut path = path;
+        if let Some(model) = path
+            .strip_prefix("models/")
+            .and_then(|p| p.strip_suffix(":countTokens"))
+            && let Some((target, _)) = CLEWDR_CONFIG.load().model_alias(model)
+        {
+            path = format!("models/{}:countTokens", target);
+        }
+        return Ok(Json(state.count_tokens(&path, body).await?).into_response());
+    }
+    let GeminiPreprocess(body, ctx) = GeminiPreprocess::from_request(req, &state).await?;
     handle_gemini_request(state, body, ctx).await
 }
 
@@ -73,3 +96,12 @@ pub async fn api_post_gemini_oai(
 ) -> Result<Response, ClewdrError> {
     handle_gemini_request(state, body, ctx).await
 }
+
+/// API endpoint to list the Gemini models in OpenAI format
+/// Proxies the listing of Google, cached across requests, with model aliases
+pub async fn api_get_gemini_models(
+    State(mut state): State<GeminiState>,
+) -> Result<Json<Value>, ClewdrError> {
+    let listing = state.list_models().await?;
+    Ok(Json(with_gemini_aliases(listing)))
+}
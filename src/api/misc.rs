@@ -6,10 +6,11 @@ use tracing::{error, info, warn};
 
 use crate::{
     VERSION_INFO,
-    config::{CLEWDR_CONFIG, CookieStatus, KeyStatus},
+    config::{CLEWDR_CONFIG, CookieStatus, KeyStatus, PoolKey},
     services::{
         cookie_manager::{CookieEventSender, CookieStatusInfo},
         key_manager::{KeyEventSender, KeyStatusInfo},
+        model_list::claude_models,
     },
 };
 
@@ -49,10 +50,19 @@ pub async fn api_post_cookie(
     }
 }
 
-pub async fn api_post_key(
-    State(s): State<KeyEventSender>,
+/// API endpoint to submit a new key to a key pool
+///
+/// # Arguments
+/// * `s` - Event sender of the key pool
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Key to be submitted
+///
+/// # Returns
+/// * `StatusCode` - HTTP status code indicating success or failure
+pub async fn api_post_key<K: PoolKey>(
+    State(s): State<KeyEventSender<K>>,
     AuthBearer(t): AuthBearer,
-    Json(c): Json<KeyStatus>,
+    Json(c): Json<KeyStatus<K>>,
 ) -> StatusCode {
     if !CLEWDR_CONFIG.load().admin_auth(&t) {
         return StatusCode::UNAUTHORIZED;
@@ -107,10 +117,18 @@ pub async fn api_get_cookies(
     }
 }
 
-pub async fn api_get_keys(
-    State(s): State<KeyEventSender>,
+/// API endpoint to retrieve all keys of a key pool
+///
+/// # Arguments
+/// * `s` - Event sender of the key pool
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<KeyStatusInfo>, (StatusCode, Json<serde_json::Value>)>` - Key status info or error
+pub async fn api_get_keys<K: PoolKey>(
+    State(s): State<KeyEventSender<K>>,
     AuthBearer(t): AuthBearer,
-) -> Result<Json<KeyStatusInfo>, (StatusCode, Json<serde_json::Value>)> {
+) -> Result<Json<KeyStatusInfo<K>>, (StatusCode, Json<serde_json::Value>)> {
     if !CLEWDR_CONFIG.load().admin_auth(&t) {
         return Err((
             StatusCode::UNAUTHORIZED,
@@ -183,10 +201,19 @@ pub async fn api_delete_cookie(
     }
 }
 
-pub async fn api_delete_key(
-    State(s): State<KeyEventSender>,
+/// API endpoint to delete a key from a key pool
+///
+/// # Arguments
+/// * `s` - Event sender of the key pool
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Key to be deleted
+///
+/// # Returns
+/// * `Result<StatusCode, (StatusCode, Json<serde_json::Value>)>` - Success status or error
+pub async fn api_delete_key<K: PoolKey>(
+    State(s): State<KeyEventSender<K>>,
     AuthBearer(t): AuthBearer,
-    Json(c): Json<KeyStatus>,
+    Json(c): Json<KeyStatus<K>>,
 ) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
     if !CLEWDR_CONFIG.load().admin_auth(&t) {
         return Err((
@@ -247,15 +274,10 @@ pub async fn api_auth(AuthBearer(t): AuthBearer) -> StatusCode {
     StatusCode::OK
 }
 
-const MODEL_LIST: [&str; 2] = [
-    "claude-3-7-sonnet-20250219",
-    "claude-3-7-sonnet-20250219-thinking",
-];
-
 /// API endpoint to get the list of available models
-/// Retrieves the list of models from the configuration
+/// Lists the models discovered from the accounts of the cookies
 pub async fn api_get_models() -> Json<Value> {
-    let data: Vec<Value> = MODEL_LIST
+    let data: Vec<Value> = claude_models()
         .iter()
         .map(|model| {
             json!({
@@ -9,10 +9,10 @@ mod gemini;
 mod misc;
 
 /// Message handling endpoints for creating and managing chat conversations
-pub use claude::api_claude;
+pub use claude::{api_claude, api_count_tokens};
 /// Configuration related endpoints for retrieving and updating Clewdr settings
 pub use config::{api_get_config, api_post_config};
-pub use gemini::{api_post_gemini, api_post_gemini_oai};
+pub use gemini::{api_get_gemini_models, api_post_gemini, api_post_gemini_oai};
 /// Miscellaneous endpoints for authentication, cookies, and version information
 pub use misc::{
     api_auth, api_delete_cookie, api_delete_key, api_get_cookies, api_get_keys, api_get_models,
@@ -0,0 +1,332 @@
+use serde_json::{Value, json};
+use std::{collections::HashMap, convert::Infallible};
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response, Sse, sse::Event},
+};
+use futures::{Stream, StreamExt};
+
+use crate::{
+    error::{ClaudeErrorBody, ClewdrError},
+    types::claude_message::{
+        ContentBlock, ContentBlockDelta, CreateMessageResponse, MessageDeltaContent,
+        MessageStartContent, Role, StopReason, StreamEvent, StreamUsage, Usage,
+    },
+};
+
+use super::response::to_sse_event;
+
+/// Kind of the content block being streamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BlockKind {
+    Text,
+    Thinking,
+    /// A tool call, with its identifier in the upstream response
+    Tool(String),
+}
+
+/// Builds Claude API stream events from the output of another API
+///
+/// Upstreams report text, thinking and tool call arguments as deltas,
+/// the builder opens and closes the content blocks around them.
+pub struct EventBuilder {
+    id: String,
+    model: String,
+    started: bool,
+    /// Index and kind of the open content block
+    open: Option<(usize, BlockKind)>,
+    next_index: usize,
+    stop_reason: Option<StopReason>,
+    usage: Usage,
+}
+
+impl EventBuilder {
+    /// Creates a builder for a message of the model
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            model: model.into(),
+            started: false,
+            open: None,
+            next_index: 0,
+            stop_reason: None,
+            usage: Usage::default(),
+        }
+    }
+
+    /// Emits `message_start` before the first event
+    fn start(&mut self, events: &mut Vec<StreamEvent>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        events.push(StreamEvent::MessageStart {
+            message: MessageStartContent {
+                id: self.id.to_owned(),
+                type_: "message".to_string(),
+                role: Role::Assistant,
+                model: self.model.to_owned(),
+                ..Default::default()
+            },
+        });
+    }
+
+    /// Closes the open block
+    fn close(&mut self, events: &mut Vec<StreamEvent>) {
+        if let Some((index, _)) = self.open.take() {
+            events.push(StreamEvent::ContentBlockStop { index });
+        }
+    }
+
+    /// Opens a block of the kind unless it is already open
+    ///
+    /// # Returns
+    /// The index of the block
+    fn open(
+        &mut self,
+        kind: BlockKind,
+        events: &mut Vec<StreamEvent>,
+        block: ContentBlock,
+    ) -> usize {
+        self.start(events);
+        if let Some((index, ref open)) = self.open
+            && *open == kind
+        {
+            return index;
+        }
+        self.close(events);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open = Some((index, kind));
+        events.push(StreamEvent::ContentBlockStart {
+            index,
+            content_block: block,
+        });
+        index
+    }
+
+    /// Appends text to the message
+    pub fn text(&mut self, text: &str) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        if text.is_empty() {
+            return events;
+        }
+        let index = self.open(BlockKind::Text, &mut events, ContentBlock::text(""));
+        events.push(StreamEvent::ContentBlockDelta {
+            index,
+            delta: ContentBlockDelta::TextDelta {
+                text: text.to_string(),
+            },
+        });
+        events
+    }
+
+    /// Appends thinking to the message
+    pub fn thinking(&mut self, thinking: &str) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        if thinking.is_empty() {
+            return events;
+        }
+        let block = ContentBlock::Thinking {
+            thinking: String::new(),
+            signature: String::new(),
+        };
+        let index = self.open(BlockKind::Thinking, &mut events, block);
+        events.push(StreamEvent::ContentBlockDelta {
+            index,
+            delta: ContentBlockDelta::ThinkingDelta {
+                thinking: thinking.to_string(),
+            },
+        });
+        events
+    }
+
+    /// Starts a tool call, or continues it if it is the open block
+    ///
+    /// # Arguments
+    /// * `key` - Identifier of the call in the upstream response
+    /// * `id` - Identifier of the tool use, generated if None
+    /// * `name` - Name of the called tool
+    pub fn tool_use(&mut self, key: &str, id: Option<&str>, name: &str) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        let block = ContentBlock::ToolUse {
+            id: id
+                .map(String::from)
+                .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple())),
+            name: name.to_string(),
+            input: Value::Object(Default::default()),
+        };
+        self.open(BlockKind::Tool(key.to_string()), &mut events, block);
+        events
+    }
+
+    /// Appends JSON arguments to a tool call
+    /// Arguments of a call that is not the open block are dropped
+    ///
+    /// # Arguments
+    /// * `key` - Identifier of the call in the upstream response
+    /// * `partial_json` - The next part of the arguments
+    pub fn tool_input(&mut self, key: &str, partial_json: &str) -> Vec<StreamEvent> {
+        match self.open {
+            Some((index, BlockKind::Tool(ref k))) if k == key && !partial_json.is_empty() => {
+                vec![StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ContentBlockDelta::InputJsonDelta {
+                        partial_json: partial_json.to_string(),
+                    },
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Sets the reason the generation stopped
+    pub fn stop(&mut self, reason: StopReason) {
+        self.stop_reason = Some(reason);
+    }
+
+    /// Sets the token usage of the message
+    pub fn usage(&mut self, input_tokens: u32, output_tokens: u32) {
+        self.usage = Usage {
+            input_tokens,
+            output_tokens,
+        };
+    }
+
+    /// Ends the message
+    ///
+    /// # Returns
+    /// The events closing the message, up to `message_stop`
+    pub fn finish(&mut self) -> Vec<StreamEvent> {
+        let mut events = vec![];
+        self.start(&mut events);
+        self.close(&mut events);
+        events.push(StreamEvent::MessageDelta {
+            delta: MessageDeltaContent {
+                stop_reason: Some(self.stop_reason.take().unwrap_or(StopReason::EndTurn)),
+                stop_sequence: None,
+            },
+            usage: Some(StreamUsage {
+                input_tokens: self.usage.input_tokens,
+                output_tokens: self.usage.output_tokens,
+            }),
+        });
+        events.push(StreamEvent::MessageStop);
+        events
+    }
+}
+
+/// Builds the complete message from its stream events
+///
+/// # Arguments
+/// * `events` - Events from `message_start` to `message_stop`
+///
+/// # Returns
+/// The message as returned by the non-streaming API
+pub fn events_to_message(events: Vec<StreamEvent>) -> CreateMessageResponse {
+    let mut message = CreateMessageResponse {
+        content: vec![],
+        id: String::new(),
+        model: String::new(),
+        role: Role::Assistant,
+        stop_reason: None,
+        stop_sequence: None,
+        type_: "message".to_string(),
+        usage: Usage::default(),
+    };
+    // partial JSON input of tool calls
+    let mut inputs: HashMap<usize, String> = HashMap::new();
+    for event in events {
+        match event {
+            StreamEvent::MessageStart { message: start } => {
+                message.id = start.id;
+                message.model = start.model;
+            }
+            StreamEvent::ContentBlockStart { content_block, .. } => {
+                message.content.push(content_block)
+            }
+            StreamEvent::ContentBlockDelta { index, delta } => {
+                match (message.content.get_mut(index), delta) {
+                    (
+                        Some(ContentBlock::Text { text, .. }),
+                        ContentBlockDelta::TextDelta { text: t },
+                    ) => {
+                        *text += t.as_str();
+                    }
+                    (
+                        Some(ContentBlock::Thinking { thinking, .. }),
+                        ContentBlockDelta::ThinkingDelta { thinking: t },
+                    ) => {
+                        *thinking += t.as_str();
+                    }
+                    (
+                        Some(ContentBlock::ToolUse { .. }),
+                        ContentBlockDelta::InputJsonDelta { partial_json },
+                    ) => {
+                        *inputs.entry(index).or_default() += partial_json.as_str();
+                    }
+                    _ => {}
+                }
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                message.stop_reason = delta.stop_reason;
+                message.stop_sequence = delta.stop_sequence;
+                if let Some(usage) = usage {
+                    message.usage = Usage {
+                        input_tokens: usage.input_tokens,
+                        output_tokens: usage.output_tokens,
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+    for (index, json) in inputs {
+        if let Some(ContentBlock::ToolUse { input, .. }) = message.content.get_mut(index)
+            && let Ok(json) = serde_json::from_str(&json)
+        {
+            *input = json;
+        }
+    }
+    message
+}
+
+/// Serializes a stream of Claude API events as SSE
+pub fn events_to_sse(
+    events: impl Stream<Item = StreamEvent> + Send + 'static,
+) -> impl Stream<Item = Result<Event, Infallible>> + Send + 'static {
+    events.map(|e| Ok(to_sse_event(&e)))
+}
+
+/// Builds the response to a Claude API client from a stream of events
+///
+/// # Arguments
+/// * `events` - Events of the message, possibly ending with an error event
+/// * `stream` - Whether the client requested a stream
+///
+/// # Returns
+/// * `Response` - An SSE stream of the events, or the JSON message if not streaming
+pub async fn events_response(
+    events: impl Stream<Item = StreamEvent> + Send + 'static,
+    stream: bool,
+) -> Response {
+    if stream {
+        return Sse::new(events_to_sse(events))
+            .keep_alive(Default::default())
+            .into_response();
+    }
+    let mut events = events.collect::<Vec<_>>().await;
+    if let Some(StreamEvent::Error { error }) =
+        events.pop_if(|e| matches!(e, StreamEvent::Error { .. }))
+    {
+        let error = ClaudeErrorBody {
+            message: json!(error.message),
+            r#type: error.type_,
+            code: Some(StatusCode::BAD_GATEWAY.as_u16()),
+        };
+        return ClewdrError::ClaudeHttpError(StatusCode::BAD_GATEWAY, error).into_response();
+    }
+    Json(events_to_message(events)).into_response()
+}
@@ -0,0 +1,116 @@
+use image::{
+    DynamicImage, ImageFormat, ImageReader, codecs::jpeg::JpegEncoder, imageops::FilterType,
+};
+use std::io::Cursor;
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError};
+
+/// JPEG quality used when re-encoding images
+const JPEG_QUALITY: u8 = 85;
+/// Number of times an image is downscaled to fit the size limit
+const MAX_DOWNSCALES: usize = 4;
+
+/// An image ready to be uploaded to Claude.ai
+#[derive(Debug)]
+pub struct PreparedImage {
+    pub bytes: Vec<u8>,
+    pub media_type: &'static str,
+}
+
+/// Validates an image and converts it into a format accepted by Claude.ai
+///
+/// The format is detected from the magic bytes, ignoring the declared media type.
+/// PNG, JPEG, GIF and WebP images within the limits are passed through unchanged.
+/// BMP and TIFF images are converted, and images exceeding `image_max_dimension`
+/// or `image_max_size` are downscaled and re-encoded. HEIC/HEIF images are not
+/// converted, as no HEIF decoder is built in, and are rejected.
+///
+/// # Arguments
+/// * `bytes` - Raw content of the image
+///
+/// # Returns
+/// * `Result<PreparedImage, ClewdrError>` - The processed image, or `InvalidImage` if it cannot be used
+pub fn prepare_image(bytes: Vec<u8>) -> Result<PreparedImage, ClewdrError> {
+    if is_heif(&bytes) {
+        return Err(ClewdrError::InvalidImage(
+            "HEIC/HEIF images cannot be converted by ClewdR, convert them to JPEG or PNG \
+            before sending"
+                .to_string(),
+        ));
+    }
+    let format = image::guess_format(&bytes)
+        .map_err(|_| ClewdrError::InvalidImage("unrecognized image format".to_string()))?;
+    let config = CLEWDR_CONFIG.load();
+    let max_dim = config.image_max_dimension;
+    let max_size = config.image_max_size * 1024 * 1024;
+
+    let (w, h) = ImageReader::with_format(Cursor::new(&bytes), format)
+        .into_dimensions()
+        .map_err(|e| ClewdrError::InvalidImage(e.to_string()))?;
+    let supported = matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+    );
+    let too_large = (max_dim > 0 && w.max(h) > max_dim) || bytes.len() > max_size;
+    if supported && !too_large {
+        return Ok(PreparedImage {
+            bytes,
+            media_type: format.to_mime_type(),
+        });
+    }
+
+    let mut img = image::load_from_memory_with_format(&bytes, format)
+        .map_err(|e| ClewdrError::InvalidImage(e.to_string()))?;
+    if max_dim > 0 && w.max(h) > max_dim {
+        img = img.resize(max_dim, max_dim, FilterType::Lanczos3);
+    }
+    let mut alpha = img.color().has_alpha();
+    for _ in 0..=MAX_DOWNSCALES {
+        let prepared = encode(&img, alpha)?;
+        if prepared.bytes.len() <= max_size {
+            return Ok(prepared);
+        }
+        // PNG did not fit, fall back to JPEG before shrinking
+        if alpha {
+            alpha = false;
+            continue;
+        }
+        img = img.resize(
+            img.width() * 3 / 4,
+            img.height() * 3 / 4,
+            FilterType::Lanczos3,
+        );
+    }
+    Err(ClewdrError::InvalidImage(format!(
+        "image is larger than {} MiB even after downscaling",
+        config.image_max_size
+    )))
+}
+
+/// Encodes an image as PNG if it has transparency, JPEG otherwise
+fn encode(img: &DynamicImage, alpha: bool) -> Result<PreparedImage, ClewdrError> {
+    let mut buf = vec![];
+    let res = if alpha {
+        img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
+            .map(|_| "image/png")
+    } else {
+        img.to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY))
+            .map(|_| "image/jpeg")
+    };
+    let media_type = res.map_err(|e| ClewdrError::InvalidImage(e.to_string()))?;
+    Ok(PreparedImage {
+        bytes: buf,
+        media_type,
+    })
+}
+
+/// Whether the bytes are a HEIC/HEIF image, which the `image` crate cannot decode
+fn is_heif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && matches!(
+            &bytes[8..12],
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"
+        )
+}
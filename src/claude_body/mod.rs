@@ -1,9 +1,18 @@
+pub mod events;
+pub mod media;
+pub mod prefill;
 pub mod request;
 pub mod response;
+pub mod structured;
+pub mod template;
+pub mod thinking;
+pub mod tokens;
+pub mod web_search;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::types::claude_message::ImageSource;
+use crate::types::claude_message::{DocumentSource, ImageSource};
 
 /// Claude.ai attachment
 #[derive(Deserialize, Serialize, Debug)]
@@ -30,6 +39,42 @@ impl Attachment {
             file_type: "txt".to_string(),
         }
     }
+
+    /// Creates a new Attachment with the given content and file name
+    ///
+    /// # Arguments
+    /// * `content` - The text content for the attachment
+    /// * `file_name` - The file name shown on Claude.ai
+    ///
+    /// # Returns
+    /// A new Attachment instance configured as a text file
+    pub fn with_name(content: String, file_name: String) -> Self {
+        Attachment {
+            file_name,
+            ..Attachment::new(content)
+        }
+    }
+}
+
+/// Document to be uploaded to Claude.ai
+#[derive(Debug)]
+pub struct Document {
+    pub title: Option<String>,
+    pub source: DocumentSource,
+}
+
+impl Document {
+    /// File name of the document, derived from its title
+    ///
+    /// # Arguments
+    /// * `ext` - Extension used when the title has none
+    pub fn file_name(&self, ext: &str) -> String {
+        match self.title.as_deref().map(str::trim) {
+            Some(t) if !t.is_empty() && t.contains('.') => t.to_string(),
+            Some(t) if !t.is_empty() => format!("{}.{}", t, ext),
+            _ => format!("document.{}", ext),
+        }
+    }
 }
 
 /// Request body to be sent to the Claude.ai
@@ -42,10 +87,18 @@ pub struct RequestBody {
     pub model: Option<String>,
     pub rendering_mode: String,
     pub prompt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_message_uuid: Option<String>,
     pub timezone: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
     #[serde(skip)]
     pub images: Vec<ImageSource>,
+    #[serde(skip)]
+    pub documents: Vec<Document>,
     pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub personalized_styles: Vec<Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -0,0 +1,256 @@
+use async_stream::stream;
+use bytes::Bytes;
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::{Stream, StreamExt, pin_mut};
+use serde_json::Value;
+use std::mem;
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    types::claude_message::{ContentBlock, CreateMessageParams, MessageContent, Role},
+};
+
+/// Instruction injected into the prompt when the request ends with an assistant prefill
+pub const PREFILL_INSTRUCTION: &str = "Your last response above was cut off. \
+    Continue it exactly from where it ends, without repeating any of it.";
+
+/// Extracts the assistant prefill of a request
+/// Only returns the prefill if prefill emulation is enabled
+///
+/// # Arguments
+/// * `p` - The client request
+///
+/// # Returns
+/// * `Option<String>` - Text of the trailing assistant message, if any
+pub fn extract_prefill(p: &CreateMessageParams) -> Option<String> {
+    if !CLEWDR_CONFIG.load().prefill {
+        return None;
+    }
+    trailing_assistant_text(p)
+}
+
+/// Extracts the text of the trailing assistant message of a request
+///
+/// # Arguments
+/// * `p` - The client request
+///
+/// # Returns
+/// * `Option<String>` - Text of the trailing assistant message, if any
+pub fn trailing_assistant_text(p: &CreateMessageParams) -> Option<String> {
+    let last = p.messages.last().filter(|m| m.role == Role::Assistant)?;
+    let text = match last.content {
+        MessageContent::Text { ref content } => content.trim().to_string(),
+        MessageContent::Blocks { ref content } => content
+            .iter()
+            .filter_map(|b| match b {
+                ContentBlock::Text { text, .. } => Some(text.trim()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// Removes the prefill from the beginning of the generated text
+/// Text is held back while it may still be an echo of the prefill
+struct PrefillStripper {
+    prefill: String,
+    buf: String,
+    done: bool,
+}
+
+impl PrefillStripper {
+    fn new(prefill: String) -> Self {
+        Self {
+            prefill,
+            buf: String::new(),
+            done: false,
+        }
+    }
+
+    /// Feeds a chunk of generated text
+    ///
+    /// # Arguments
+    /// * `text` - The generated chunk
+    ///
+    /// # Returns
+    /// The text that can be emitted, empty if it is held back
+    fn feed(&mut self, text: &str) -> String {
+        if self.done {
+            return text.to_string();
+        }
+        self.buf += text;
+        let prefill = self.prefill.trim();
+        if prefill.starts_with(self.buf.trim_start()) {
+            // still echoing the prefill
+            return String::new();
+        }
+        self.done = true;
+        let buf = mem::take(&mut self.buf);
+        match buf.trim_start().strip_prefix(prefill) {
+            Some(rest) => rest.to_string(),
+            None => buf,
+        }
+    }
+
+    /// Ends the generated text, releasing the text held back
+    ///
+    /// # Returns
+    /// The held back text, empty if it was the whole prefill
+    fn finish(&mut self) -> String {
+        if self.done {
+            return String::new();
+        }
+        self.done = true;
+        let buf = mem::take(&mut self.buf);
+        if buf.trim() == self.prefill.trim() {
+            String::new()
+        } else {
+            buf
+        }
+    }
+}
+
+/// Event carrying text held back by the stripper, used to release it
+struct HeldEvent {
+    event: String,
+    data: Value,
+    path: &'static str,
+}
+
+impl HeldEvent {
+    /// Builds the event with the given text, if there is any
+    fn release(mut self, text: String) -> Option<Bytes> {
+        if text.is_empty() {
+            return None;
+        }
+        if let Some(Value::String(t)) = self.data.pointer_mut(self.path) {
+            *t = text;
+        }
+        Some(sse_bytes(&self.event, &self.data.to_string()))
+    }
+}
+
+/// Strips an echoed prefill from a Claude.ai event stream
+///
+/// Works on both `raw` (completion) and `messages` (text delta) rendering modes.
+/// Other events are passed through unchanged.
+///
+/// # Arguments
+/// * `input` - The response stream from Claude.ai
+/// * `prefill` - The assistant prefill of the request
+/// * `prepend` - Whether the prefill is prepended to the output
+///
+/// # Returns
+/// A stream of SSE bytes with the echo removed
+pub fn strip_prefill(
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    prefill: String,
+    mut prepend: bool,
+) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+    stream! {
+        let mut stripper = PrefillStripper::new(prefill.to_owned());
+        let mut held: Option<HeldEvent> = None;
+        let events = input.eventsource();
+        pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(EventStreamError::Transport(e)) => {
+                    yield Err(e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let Ok(mut data) = serde_json::from_str::<Value>(&event.data) else {
+                yield Ok(sse_bytes(&event.event, &event.data));
+                continue;
+            };
+            let path = if data.get("completion").is_some() {
+                "/completion"
+            } else if data["type"] == "content_block_delta" && data["delta"]["type"] == "text_delta"
+            {
+                "/delta/text"
+            } else {
+                // the text block ends, release the text held back
+                if data["type"] == "content_block_stop"
+                    && let Some(bytes) = held.take().and_then(|h| h.release(stripper.finish()))
+                {
+                    yield Ok(bytes);
+                }
+                yield Ok(sse_bytes(&event.event, &event.data));
+                continue;
+            };
+            // the last completion carries the stop reason
+            let stopped = !data["stop_reason"].is_null();
+            let template = HeldEvent {
+                event: event.event.to_owned(),
+                data: data.to_owned(),
+                path,
+            };
+            let Some(Value::String(text)) = data.pointer_mut(path) else {
+                continue;
+            };
+            let mut out = stripper.feed(text);
+            if stopped {
+                out += &stripper.finish();
+            }
+            if prepend {
+                out = format!("{}{}", prefill, out);
+                prepend = false;
+            }
+            if out.is_empty() && !stopped {
+                held = Some(template);
+                continue;
+            }
+            held = None;
+            *text = out;
+            yield Ok(sse_bytes(&event.event, &data.to_string()));
+        }
+        // the stream ended while text was held back
+        if let Some(bytes) = held.and_then(|h| h.release(stripper.finish())) {
+            yield Ok(bytes);
+        }
+    }
+}
+
+/// Serializes an SSE event
+pub(crate) fn sse_bytes(event: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefillStripper;
+
+    #[test]
+    fn strips_echo_split_across_chunks() {
+        let mut s = PrefillStripper::new("Hello there".to_string());
+        assert_eq!(s.feed("Hel"), "");
+        assert_eq!(s.feed("lo th"), "");
+        assert_eq!(s.feed("ere, friend"), ", friend");
+        assert_eq!(s.feed(" again"), " again");
+        assert_eq!(s.finish(), "");
+    }
+
+    #[test]
+    fn passes_text_not_echoing_prefill() {
+        let mut s = PrefillStripper::new("Hello".to_string());
+        assert_eq!(s.feed("Goodbye"), "Goodbye");
+    }
+
+    #[test]
+    fn finish_releases_held_text() {
+        let mut s = PrefillStripper::new("Hello there".to_string());
+        assert_eq!(s.feed("Hello"), "");
+        assert_eq!(s.finish(), "Hello");
+    }
+
+    #[test]
+    fn finish_drops_whole_echo() {
+        let mut s = PrefillStripper::new("Hello".to_string());
+        assert_eq!(s.feed(" Hello"), "");
+        assert_eq!(s.finish(), "");
+    }
+}
@@ -1,46 +1,84 @@
 use base64::{Engine, prelude::BASE64_STANDARD};
-use futures::{StreamExt, stream};
+use chrono::Local;
+use colored::Colorize;
 use itertools::Itertools;
-use rand::{Rng, rng};
+use rand::{Rng, SeedableRng, rng, rngs::StdRng};
 use rquest::{
     Method,
     multipart::{Form, Part},
 };
 use serde_json::Value;
-use std::{fmt::Write, mem};
-use tracing::warn;
+use std::{collections::HashMap, fmt::Write, mem};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
 
 use crate::{
-    claude_body::{Attachment, RequestBody, Tool},
+    claude_body::{
+        Attachment, Document, RequestBody, Tool,
+        media::prepare_image,
+        prefill::{PREFILL_INSTRUCTION, extract_prefill, trailing_assistant_text},
+        template::{PromptTemplate, render, select_template},
+        web_search::WebSearch,
+    },
     claude_state::{ClaudeApiFormat, ClaudeState},
-    config::CLEWDR_CONFIG,
+    config::{AttachmentMode, CLEWDR_CONFIG, PadPlacement, RuleScope, apply_rules},
+    error::{CheckClaudeErr, ClewdrError},
     types::claude_message::{
-        ContentBlock, CreateMessageParams, ImageSource, Message, MessageContent, Role,
+        ContentBlock, CreateMessageParams, DocumentSource, ImageSource, Message, MessageContent,
+        Role,
+    },
+    utils::{
+        TOKENIZER,
+        fetch::{fetch_remote, sniff_media_type},
+        print_out_text,
     },
-    utils::{TIME_ZONE, print_out_text},
 };
 
-/// Merged messages and images
+/// Merged messages, images and documents
 #[derive(Default, Debug)]
 struct Merged {
     pub paste: String,
     pub prompt: String,
+    pub padding: Option<String>,
+    pub last_turn: Option<String>,
     pub images: Vec<ImageSource>,
+    pub attachments: Vec<Attachment>,
+    pub documents: Vec<Document>,
 }
 
 impl ClaudeState {
-    pub fn transform_request(&self, mut value: CreateMessageParams) -> Option<RequestBody> {
-        let (value, merged) = match self.api_format {
+    pub fn transform_request(
+        &self,
+        mut value: CreateMessageParams,
+    ) -> Result<RequestBody, ClewdrError> {
+        // a continuation always continues the partial output
+        let prefill = if self.continuation {
+            trailing_assistant_text(&value)
+        } else {
+            extract_prefill(&value)
+        };
+        let template = select_template(
+            &value.model,
+            self.user_key.as_ref(),
+            self.template.as_deref(),
+        );
+        let (value, mut merged) = match self.api_format {
             ClaudeApiFormat::Claude => {
                 let system = value.system.take();
                 let msgs = mem::take(&mut value.messages);
                 let system = merge_system(system.unwrap_or_default());
-                let merged = merge_messages(msgs, system)?;
+                let merged = merge_messages(
+                    msgs,
+                    system,
+                    self.resumed.is_none(),
+                    &value.model,
+                    template.as_deref(),
+                )?;
                 (value, merged)
             }
             ClaudeApiFormat::OpenAI => {
                 let mut msgs = mem::take(&mut value.messages);
-                let mut role = msgs.first().map(|m| m.role)?;
+                let mut role = msgs.first().map(|m| m.role).ok_or_else(empty_request)?;
                 for msg in msgs.iter_mut() {
                     if msg.role != Role::System {
                         role = msg.role;
@@ -48,93 +86,239 @@ impl ClaudeState {
                         msg.role = role;
                     }
                 }
-                let merged = merge_messages(msgs, String::new())?;
+                let merged = merge_messages(
+                    msgs,
+                    String::new(),
+                    self.resumed.is_none(),
+                    &value.model,
+                    template.as_deref(),
+                )?;
                 (value, merged)
             }
         };
+        // the last user turn goes first if it is moved out of the transcript
+        let mut prompt = merged.last_turn.unwrap_or_default();
+        push_paragraph(&mut prompt, &merged.prompt);
+        // assistant prefill emulation
+        if prefill.is_some() {
+            push_paragraph(&mut prompt, PREFILL_INSTRUCTION);
+        }
+        // claude.ai has no thinking budget, pass it as guidance
+        if let Some(instruction) = value.thinking.as_ref().and_then(|t| t.instruction()) {
+            push_paragraph(&mut prompt, &instruction);
+        }
+        // structured output instruction
+        if let Some(instruction) = value.response_format.as_ref().and_then(|f| f.instruction()) {
+            push_paragraph(&mut prompt, &instruction);
+        }
+        // user defined rewriting of the transcript and prompt
+        let rules = CLEWDR_CONFIG
+            .load()
+            .regex_rules_for(RuleScope::Input, &value.model);
+        if !rules.is_empty() {
+            merged.paste = apply_rules(&rules, merged.paste);
+            prompt = apply_rules(&rules, prompt);
+        }
         let mut tools = vec![];
-        if CLEWDR_CONFIG.load().web_search {
+        if let Some(search) = WebSearch::from_request(&value) {
             tools.push(Tool::web_search());
+            if let Some(instruction) = search.instruction() {
+                push_paragraph(&mut prompt, &instruction);
+            }
+        }
+        let mut attachments = vec![];
+        if let Some(padding) = merged.padding {
+            attachments.push(Attachment::with_name(padding, "padding.txt".to_string()));
         }
-        Some(RequestBody {
+        match CLEWDR_CONFIG.load().attachment_mode {
+            AttachmentMode::Prompt => {
+                let mut paste = merged.paste;
+                push_paragraph(&mut paste, &prompt);
+                prompt = paste;
+            }
+            _ if merged.paste.is_empty() => {}
+            AttachmentMode::Paste => attachments.push(Attachment::new(merged.paste)),
+            AttachmentMode::Chunked => {
+                let chunks =
+                    chunk_text(&merged.paste, CLEWDR_CONFIG.load().attachment_chunk_tokens);
+                if chunks.len() == 1 {
+                    attachments.push(Attachment::new(merged.paste));
+                } else {
+                    attachments.extend(
+                        chunks
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, c)| Attachment::with_name(c, format!("paste_{}.txt", i + 1))),
+                    );
+                }
+            }
+        }
+        attachments.extend(merged.attachments);
+        Ok(RequestBody {
             max_tokens_to_sample: value.max_tokens,
-            attachments: vec![Attachment::new(merged.paste)],
+            attachments,
             files: vec![],
             model: if self.is_pro() {
                 Some(value.model)
             } else {
                 None
             },
-            rendering_mode: if value.stream.unwrap_or_default() {
-                "messages".to_string()
-            } else {
-                "raw".to_string()
-            },
-            prompt: merged.prompt,
-            timezone: TIME_ZONE.to_string(),
+            // non-streaming responses are merged from the same events,
+            // raw mode has no thinking or web search blocks
+            rendering_mode: "messages".to_string(),
+            prompt,
+            parent_message_uuid: None,
+            timezone: self.timezone.to_owned(),
+            locale: self.locale.to_owned(),
             images: merged.images,
+            documents: merged.documents,
             tools,
+            personalized_styles: vec![],
         })
     }
 
     /// Upload images to the Claude.ai
-    pub async fn upload_images(&self, imgs: Vec<ImageSource>) -> Vec<String> {
-        // upload images
-        stream::iter(imgs)
-            .filter_map(async |img| {
-                // check if the image is base64
-                if img.type_ != "base64" {
-                    warn!("Image type is not base64");
-                    return None;
-                }
+    ///
+    /// Images are validated, converted and downscaled before uploading,
+    /// remote images are downloaded first. BMP and TIFF images are converted,
+    /// HEIC/HEIF images are not and are rejected with `InvalidImage`.
+    ///
+    /// # Arguments
+    /// * `imgs` - Images of the request
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>, ClewdrError>` - UUIDs of the uploaded files, or an error if an image
+    ///   is invalid or its upload fails
+    pub async fn upload_images(&self, imgs: Vec<ImageSource>) -> Result<Vec<String>, ClewdrError> {
+        let mut files = vec![];
+        for img in imgs {
+            let bytes = match img.type_.as_str() {
                 // decode the image
-                let bytes = BASE64_STANDARD
+                "base64" => BASE64_STANDARD
                     .decode(img.data)
-                    .inspect_err(|e| {
-                        warn!("Failed to decode image: {}", e);
-                    })
-                    .ok()?;
+                    .map_err(|e| ClewdrError::InvalidImage(e.to_string()))?,
+                // download the image
+                "url" => {
+                    let url = img
+                        .url
+                        .ok_or(ClewdrError::InvalidImage("missing image url".to_string()))?;
+                    fetch_remote(&url)
+                        .await
+                        .map_err(|e| {
+                            ClewdrError::InvalidImage(format!("failed to fetch {}: {}", url, e))
+                        })?
+                        .bytes
+                        .to_vec()
+                }
+                _ => {
+                    return Err(ClewdrError::InvalidImage(format!(
+                        "unsupported image source type: {}",
+                        img.type_
+                    )));
+                }
+            };
+            let (bytes, file_name) = if sniff_media_type(&bytes) == Some("application/pdf") {
+                (bytes, "document.pdf")
+            } else {
+                // validate, convert and downscale off the async runtime
+                let img = spawn_blocking(move || prepare_image(bytes))
+                    .await
+                    .map_err(|e| ClewdrError::InvalidImage(e.to_string()))??;
                 // choose the file name based on the media type
-                let file_name = match img.media_type.to_lowercase().as_str() {
+                let file_name = match img.media_type {
                     "image/png" => "image.png",
                     "image/jpeg" => "image.jpg",
-                    "image/jpg" => "image.jpg",
                     "image/gif" => "image.gif",
                     "image/webp" => "image.webp",
-                    "application/pdf" => "document.pdf",
                     _ => "file",
                 };
-                // create the part and form
-                let part = Part::bytes(bytes).file_name(file_name);
-                let form = Form::new().part("file", part);
-                let endpoint = format!("{}/api/{}/upload", self.endpoint, self.org_uuid.as_ref()?);
-                // send the request into future
-                let res = self
-                    .build_request(Method::POST, endpoint)
-                    .multipart(form)
-                    .send()
-                    .await
-                    .inspect_err(|e| {
-                        warn!("Failed to upload image: {}", e);
-                    })
-                    .ok()?;
-                #[derive(serde::Deserialize)]
-                struct UploadResponse {
-                    file_uuid: String,
-                }
-                // get the response json
-                let json = res
-                    .json::<UploadResponse>()
-                    .await
-                    .inspect_err(|e| {
-                        warn!("Failed to parse image response: {}", e);
-                    })
-                    .ok()?;
-                // extract the file_uuid
-                Some(json.file_uuid)
-            })
-            .collect::<Vec<_>>()
-            .await
+                (img.bytes, file_name)
+            };
+            files.push(self.upload_file(bytes, file_name.to_string()).await?);
+        }
+        Ok(files)
+    }
+
+    /// Upload documents to the Claude.ai
+    /// Documents from URLs are downloaded first
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>, ClewdrError>` - UUIDs of the uploaded files, or an error if
+    ///   a document cannot be read or uploaded
+    pub async fn upload_documents(&self, docs: Vec<Document>) -> Result<Vec<String>, ClewdrError> {
+        let mut files = vec![];
+        for doc in docs {
+            let (bytes, ext) = match doc.source {
+                DocumentSource::Base64 {
+                    ref media_type,
+                    ref data,
+                } => match BASE64_STANDARD.decode(data) {
+                    Ok(bytes) => (bytes, document_ext(media_type)),
+                    Err(e) => {
+                        return Err(ClewdrError::BadRequest(format!(
+                            "Failed to decode document {}: {}",
+                            doc.file_name(document_ext(media_type)),
+                            e
+                        )));
+                    }
+                },
+                DocumentSource::Text { ref data, .. } => (data.to_owned().into_bytes(), "txt"),
+                DocumentSource::Url { ref url } => match fetch_remote(url).await {
+                    Ok(fetched) => (fetched.bytes.to_vec(), document_ext(&fetched.media_type)),
+                    Err(e) => {
+                        return Err(ClewdrError::BadRequest(format!(
+                            "Failed to fetch document {}: {}",
+                            url, e
+                        )));
+                    }
+                },
+            };
+            files.push(self.upload_file(bytes, doc.file_name(ext)).await?);
+        }
+        Ok(files)
+    }
+
+    /// Upload a single file to the Claude.ai
+    ///
+    /// # Arguments
+    /// * `bytes` - Content of the file
+    /// * `file_name` - Name of the file, used by Claude.ai to detect its type
+    ///
+    /// # Returns
+    /// * `Result<String, ClewdrError>` - UUID of the uploaded file
+    async fn upload_file(&self, bytes: Vec<u8>, file_name: String) -> Result<String, ClewdrError> {
+        // create the part and form
+        let part = Part::bytes(bytes).file_name(file_name);
+        let form = Form::new().part("file", part);
+        let org_uuid = self.org_uuid.as_ref().ok_or(ClewdrError::UnexpectedNone)?;
+        let endpoint = format!("{}/api/{}/upload", self.endpoint, org_uuid);
+        #[derive(serde::Deserialize)]
+        struct UploadResponse {
+            file_uuid: String,
+        }
+        let json = self
+            .build_request(Method::POST, endpoint)
+            .multipart(form)
+            .send()
+            .await?
+            .check_claude()
+            .await?
+            .json::<UploadResponse>()
+            .await?;
+        Ok(json.file_uuid)
+    }
+}
+
+/// Chooses the file extension of a document based on its media type
+fn document_ext(media_type: &str) -> &'static str {
+    match media_type.to_lowercase().as_str() {
+        "application/pdf" => "pdf",
+        "text/html" => "html",
+        "text/markdown" => "md",
+        "text/csv" => "csv",
+        "application/json" => "json",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        _ => "txt",
     }
 }
 
@@ -144,13 +328,24 @@ impl ClaudeState {
 /// # Arguments
 /// * `msgs` - Vector of messages to merge
 /// * `system` - System instructions to prepend
+/// * `pad` - Whether padding text may be added
+/// * `model` - Model of the request, available to the template
+/// * `template` - Prompt template, None to use the built-in format
 ///
 /// # Returns
-/// * `Option<Merged>` - Merged prompt text, images, and additional metadata, or None if merging fails
-fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
+/// * `Result<Merged, ClewdrError>` - Merged prompt text, images, and additional metadata,
+///   or an error if nothing is left to send or a document cannot be read
+fn merge_messages(
+    msgs: Vec<Message>,
+    system: String,
+    pad: bool,
+    model: &str,
+    template: Option<&PromptTemplate>,
+) -> Result<Merged, ClewdrError> {
     if msgs.is_empty() {
-        return None;
+        return Err(empty_request());
     }
+    let message_count = msgs.len();
     let h = CLEWDR_CONFIG
         .load()
         .custom_h
@@ -168,14 +363,35 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
     let size = size_of_val(&msgs);
     // preallocate string to avoid reallocations
     let mut w = String::with_capacity(size);
-    // generate padding text
-    if !CLEWDR_CONFIG.load().pad_tokens.is_empty() {
-        let len = CLEWDR_CONFIG.load().padtxt_len;
-        let padding = generate_padding(len);
-        w.push_str(padding.as_str());
-    }
+    // end of the system prompt in the transcript
+    let mut system_end = 0;
 
+    let mut vars = HashMap::from([
+        ("model", model.to_string()),
+        ("date", Local::now().format("%Y-%m-%d").to_string()),
+        ("time", Local::now().format("%H:%M:%S").to_string()),
+        ("message_count", message_count.to_string()),
+        ("system", system.to_owned()),
+        ("user", h.to_owned()),
+        ("assistant", a.to_owned()),
+        (
+            "custom_prompt",
+            CLEWDR_CONFIG.load().custom_prompt.to_owned(),
+        ),
+    ]);
+
+    let keep_thinking = CLEWDR_CONFIG.load().keep_thinking;
     let mut imgs: Vec<ImageSource> = vec![];
+    let mut attachments: Vec<Attachment> = vec![];
+    let mut docs: Vec<Document> = vec![];
+    // first document that could not be read
+    let mut failed = None;
+    // placeholder of the image just pushed, if the template has one
+    let image_placeholder = |imgs: &Vec<ImageSource>| {
+        let t = template.filter(|t| !t.image.is_empty())?;
+        let vars = HashMap::from([("index", imgs.len().to_string())]);
+        Some(render(&t.image, &vars))
+    };
 
     let chunks = msgs
         .into_iter()
@@ -185,18 +401,57 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
                 let blocks = content
                     .into_iter()
                     .filter_map(|b| match b {
-                        ContentBlock::Text { text } => Some(text.trim().to_string()),
+                        ContentBlock::Text { text, .. } => Some(text.trim().to_string()),
+                        // thinking of previous turns is only kept if configured
+                        ContentBlock::Thinking { thinking, .. } if keep_thinking => {
+                            Some(format!("<thinking>\n{}\n</thinking>", thinking.trim()))
+                        }
                         ContentBlock::Image { source } => {
                             // push image to the list
                             imgs.push(source);
-                            None
+                            image_placeholder(&imgs)
                         }
                         ContentBlock::ImageUrl { image_url } => {
-                            // oai image
-                            if let Some(source) = extract_image_from_url(&image_url.url) {
-                                imgs.push(source);
+                            // oai image, either data URI or remote URL
+                            let source = extract_image_from_url(&image_url.url)?;
+                            imgs.push(source);
+                            image_placeholder(&imgs)
+                        }
+                        ContentBlock::Document {
+                            source,
+                            title,
+                            context,
+                        } => {
+                            let doc = Document { title, source };
+                            match doc.source {
+                                // plain text documents are sent as attachments
+                                DocumentSource::Text { ref data, .. } => attachments.push(
+                                    Attachment::with_name(data.to_owned(), doc.file_name("txt")),
+                                ),
+                                DocumentSource::Base64 {
+                                    ref media_type,
+                                    ref data,
+                                } if media_type.starts_with("text/") => {
+                                    match BASE64_STANDARD.decode(data) {
+                                        Ok(bytes) => attachments.push(Attachment::with_name(
+                                            String::from_utf8_lossy(&bytes).into_owned(),
+                                            doc.file_name(document_ext(media_type)),
+                                        )),
+                                        Err(e) => {
+                                            failed.get_or_insert(format!(
+                                                "Failed to decode document {}: {}",
+                                                doc.file_name(document_ext(media_type)),
+                                                e
+                                            ));
+                                        }
+                                    }
+                                }
+                                // other documents are uploaded
+                                _ => docs.push(doc),
                             }
-                            None
+                            context
+                                .map(|c| c.trim().to_string())
+                                .filter(|c| !c.is_empty())
                         }
                         _ => None,
                     })
@@ -221,62 +476,225 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
         // chunk by role
         .chunk_by(|m| m.0);
     // join same role with new line
-    let mut msgs = chunks.into_iter().map(|(role, grp)| {
-        let txt = grp.into_iter().map(|m| m.1).collect::<Vec<_>>().join("\n");
-        (role, txt)
-    });
-    // first message does not need prefix
-    if !system.is_empty() {
-        w += system.as_str();
+    let mut msgs = chunks
+        .into_iter()
+        .map(|(role, grp)| {
+            let txt = grp.into_iter().map(|m| m.1).collect::<Vec<_>>().join("\n");
+            (role, txt)
+        })
+        .collect::<Vec<_>>();
+    // move the last user turn out of the transcript
+    let last_turn = if CLEWDR_CONFIG.load().last_turn_in_prompt
+        && msgs.last().is_some_and(|m| m.0 == Role::User)
+    {
+        msgs.pop().map(|m| m.1)
     } else {
-        let first = msgs.next()?;
-        w += first.1.as_str();
+        None
+    };
+    if let Some(e) = failed {
+        return Err(ClewdrError::BadRequest(e));
     }
-    for (role, text) in msgs {
-        let prefix = match role {
-            Role::System => {
-                warn!("System message should be merged into the first message");
-                continue;
-            }
-            Role::User => format!("{}: ", h),
-            Role::Assistant => format!("{}: ", a),
-        };
-        write!(w, "{}{}{}", line_breaks, prefix, text).ok()?;
+    if system.is_empty() && msgs.is_empty() && last_turn.is_none() {
+        return Err(empty_request());
+    }
+    let p = if let Some(t) = template {
+        // format the transcript with the template
+        let mut sections = vec![];
+        if !system.is_empty() {
+            let section = render(&t.system, &vars);
+            system_end = section.len();
+            sections.push(section);
+        }
+        for (i, (role, text)) in msgs.into_iter().enumerate() {
+            let (format, name) = match role {
+                Role::System => {
+                    warn!("System message should be merged into the first message");
+                    continue;
+                }
+                Role::User => (&t.user, "user"),
+                Role::Assistant => (&t.assistant, "assistant"),
+            };
+            vars.insert("role", name.to_string());
+            vars.insert("index", i.to_string());
+            vars.insert("content", text);
+            sections.push(render(format, &vars));
+        }
+        w += sections.join(&t.separator).as_str();
+        render(&t.prompt, &vars)
+    } else {
+        let mut msgs = msgs.into_iter();
+        // first message does not need prefix
+        if !system.is_empty() {
+            w += system.as_str();
+            system_end = w.len();
+        } else if let Some(first) = msgs.next() {
+            w += first.1.as_str();
+        }
+        for (role, text) in msgs {
+            let prefix = match role {
+                Role::System => {
+                    warn!("System message should be merged into the first message");
+                    continue;
+                }
+                Role::User => format!("{}: ", h),
+                Role::Assistant => format!("{}: ", a),
+            };
+            write!(w, "{}{}{}", line_breaks, prefix, text)
+                .map_err(|_| ClewdrError::UnexpectedNone)?;
+        }
+        // prompt polyfill
+        CLEWDR_CONFIG.load().custom_prompt.to_owned()
+    };
+    // pad short prompts
+    let conf = CLEWDR_CONFIG.load();
+    let mut padding = None;
+    if pad
+        && !conf.pad_tokens.is_empty()
+        && below_threshold(
+            &[&w, &p, last_turn.as_deref().unwrap_or_default()],
+            conf.pad_threshold,
+        )
+    {
+        let text = generate_padding(&conf.pad_tokens, conf.padtxt_len, conf.pad_seed);
+        info!("Padding: {:?}", conf.pad_placement);
+        print_out_text(text.as_str(), "padding.txt");
+        padding = place_padding(
+            &mut w,
+            text,
+            conf.pad_placement,
+            &conf.pad_separator,
+            system_end,
+        );
     }
     print_out_text(w.as_str(), "paste.txt");
 
-    // prompt polyfill
-    let p = CLEWDR_CONFIG.load().custom_prompt.to_owned();
-
-    Some(Merged {
+    Ok(Merged {
         paste: w,
         prompt: p,
+        padding,
+        last_turn,
         images: imgs,
+        attachments,
+        documents: docs,
     })
 }
 
+/// Error of a request with nothing to send
+fn empty_request() -> ClewdrError {
+    ClewdrError::BadRequest("Empty request".to_string())
+}
+
+/// Appends a paragraph to the text, separated by a blank line
+fn push_paragraph(w: &mut String, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if !w.is_empty() {
+        *w += "\n\n";
+    }
+    *w += text;
+}
+
+/// Splits text into chunks of at most `max_tokens` tokens
+/// Chunks are split on line boundaries, lines longer than that are split by tokens
+///
+/// # Arguments
+/// * `text` - The text to split
+/// * `max_tokens` - Maximum number of tokens per chunk
+///
+/// # Returns
+/// A list of chunks, never empty
+fn chunk_text(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut tokens = 0;
+    for line in text.split_inclusive('\n') {
+        for (piece, len) in split_tokens(line, max_tokens) {
+            if tokens + len > max_tokens && !current.is_empty() {
+                chunks.push(mem::take(&mut current));
+                tokens = 0;
+            }
+            current += piece;
+            tokens += len;
+        }
+    }
+    chunks.push(current);
+    chunks
+}
+
+/// Splits text into pieces of at most `max_tokens` tokens
+/// Pieces end on character boundaries, so a piece may take a few more tokens
+/// when a character spans several of them
+///
+/// # Arguments
+/// * `text` - The text to split
+/// * `max_tokens` - Maximum number of tokens per piece
+///
+/// # Returns
+/// The pieces with their number of tokens
+fn split_tokens(text: &str, max_tokens: usize) -> Vec<(&str, usize)> {
+    let tokens = TOKENIZER.encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return vec![(text, tokens.len())];
+    }
+    let mut pieces = vec![];
+    let (mut start, mut end, mut count) = (0, 0, 0);
+    for bytes in TOKENIZER._decode_native_and_split(tokens) {
+        end += bytes.len();
+        count += 1;
+        if count >= max_tokens && text.is_char_boundary(end) {
+            pieces.push((&text[start..end], count));
+            start = end;
+            count = 0;
+        }
+    }
+    if start < text.len() {
+        pieces.push((&text[start..], count));
+    }
+    pieces
+}
+
+/// Whether a prompt is short enough to be padded
+///
+/// # Arguments
+/// * `parts` - The texts sent to Claude.ai
+/// * `threshold` - The configured `pad_threshold`
+///
+/// # Returns
+/// True if `threshold` is 0 or the parts have fewer tokens than it
+fn below_threshold(parts: &[&str], threshold: usize) -> bool {
+    if threshold == 0 {
+        return true;
+    }
+    let tokens = parts
+        .iter()
+        .map(|p| TOKENIZER.encode_ordinary(p).len())
+        .sum::<usize>();
+    tokens < threshold
+}
+
 /// Generates random padding text of specified length
 /// Used to pad prompts with tokens to meet minimum length requirements
+/// With `pad_seed` set, the same padding is generated for every request
 ///
 /// # Arguments
+/// * `tokens` - The tokens to draw the padding from
 /// * `length` - The target length of padding in tokens
+/// * `seed` - The configured `pad_seed`
 ///
 /// # Returns
-/// A string containing the padding text
-fn generate_padding(length: usize) -> String {
+/// A string containing the padding text, without separator
+fn generate_padding(tokens: &[String], length: usize, seed: Option<u64>) -> String {
     if length == 0 {
         return String::new();
     }
-    let conf = CLEWDR_CONFIG.load();
-    let tokens = conf
-        .pad_tokens
-        .iter()
-        .map(|s| s.as_str())
-        .collect::<Vec<_>>();
     assert!(tokens.len() >= length, "Padding tokens too short");
 
     let mut result = String::with_capacity(length * 8);
-    let mut rng = rng();
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rng()),
+    };
     let mut pushed = 0;
     loop {
         let slice_len = rng.random_range(16..64);
@@ -292,11 +710,39 @@ fn generate_padding(length: usize) -> String {
             break;
         }
     }
-    print_out_text(result.as_str(), "padding.txt");
-    result.push_str("\n\n\n\n------------------------------------------------------------\n");
+    info!("Padding: {} tokens", pushed.to_string().blue());
     result
 }
 
+/// Places the padding text in the transcript
+///
+/// # Arguments
+/// * `paste` - The transcript sent as `paste.txt`
+/// * `text` - The padding text
+/// * `placement` - The configured `pad_placement`
+/// * `separator` - The configured `pad_separator`
+/// * `system_end` - The byte offset where the system prompt ends, 0 if there is none
+///
+/// # Returns
+/// The padding text if it is sent as a separate attachment
+fn place_padding(
+    paste: &mut String,
+    text: String,
+    placement: PadPlacement,
+    separator: &str,
+    system_end: usize,
+) -> Option<String> {
+    match placement {
+        PadPlacement::Attachment => return Some(text),
+        PadPlacement::AfterSystem if system_end > 0 => {
+            paste.insert_str(system_end, &format!("\n\n{}{}", text, separator))
+        }
+        // without a system prompt, padding goes before the first message
+        _ => paste.insert_str(0, &(text + separator)),
+    }
+    None
+}
+
 /// Merges system message content into a single string
 /// Handles both string and array formats for system messages
 ///
@@ -321,8 +767,17 @@ fn merge_system(sys: Value) -> String {
 }
 
 fn extract_image_from_url(url: &str) -> Option<ImageSource> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        // downloaded when uploading
+        return Some(ImageSource {
+            type_: "url".to_string(),
+            media_type: String::new(),
+            data: String::new(),
+            url: Some(url.to_string()),
+        });
+    }
     if !url.starts_with("data:") {
-        return None; // only support data URI
+        return None;
     }
     let (metadata, base64_data) = url.split_once(',')?;
 
@@ -332,5 +787,104 @@ fn extract_image_from_url(url: &str) -> Option<ImageSource> {
         type_: type_.to_string(),
         media_type: media_type.to_string(),
         data: base64_data.to_owned(),
+        url: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{below_threshold, chunk_text, generate_padding, place_padding};
+    use crate::{config::PadPlacement, utils::TOKENIZER};
+
+    fn tokens(text: &str) -> usize {
+        TOKENIZER.encode_ordinary(text).len()
+    }
+
+    #[test]
+    fn keeps_short_text_in_one_chunk() {
+        let text = "first line\nsecond line\n";
+        assert_eq!(chunk_text(text, 100), vec![text.to_string()]);
+        assert_eq!(chunk_text("", 100), vec![String::new()]);
+    }
+
+    #[test]
+    fn splits_on_line_boundaries() {
+        let line = "one two three four five six seven eight\n";
+        let text = line.repeat(10);
+        let chunks = chunk_text(&text, tokens(line) * 3);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|c| c.ends_with('\n')));
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn splits_long_lines_by_tokens() {
+        let text = "word ".repeat(100);
+        let chunks = chunk_text(&text, 10);
+        assert!(chunks.len() >= 10);
+        assert!(chunks.iter().all(|c| tokens(c) <= 11));
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn keeps_characters_whole() {
+        let text = "日本語のテキスト".repeat(50);
+        let chunks = chunk_text(&text, 7);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), text);
+    }
+
+    fn pad_tokens() -> Vec<String> {
+        (0..200).map(|i| format!(" t{}", i)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_padding() {
+        let tokens = pad_tokens();
+        let first = generate_padding(&tokens, 100, Some(42));
+        assert!(!first.is_empty());
+        assert_eq!(first, generate_padding(&tokens, 100, Some(42)));
+        assert_ne!(first, generate_padding(&tokens, 100, Some(43)));
+        assert!(generate_padding(&tokens, 0, Some(42)).is_empty());
+    }
+
+    #[test]
+    fn pads_only_below_threshold() {
+        let text = "word ".repeat(100);
+        assert!(below_threshold(&[&text], 0));
+        assert!(below_threshold(&[&text], tokens(&text) + 1));
+        assert!(!below_threshold(&[&text], tokens(&text)));
+        assert!(!below_threshold(&[&text, "more"], tokens(&text) + 1));
+    }
+
+    #[test]
+    fn places_padding() {
+        let system = "System: be brief";
+        let paste = format!("{}\n\nHuman: hi", system);
+
+        let mut w = paste.clone();
+        let pad = place_padding(&mut w, "PAD".into(), PadPlacement::Attachment, "--", 0);
+        assert_eq!(pad.as_deref(), Some("PAD"));
+        assert_eq!(w, paste);
+
+        let mut w = paste.clone();
+        let pad = place_padding(&mut w, "PAD".into(), PadPlacement::BeforeSystem, "--", 0);
+        assert!(pad.is_none());
+        assert_eq!(w, format!("PAD--{}", paste));
+
+        let mut w = paste.clone();
+        place_padding(
+            &mut w,
+            "PAD".into(),
+            PadPlacement::AfterSystem,
+            "--",
+            system.len(),
+        );
+        assert_eq!(w, format!("{}\n\nPAD--\n\nHuman: hi", system));
+
+        // without a system prompt, padding goes before the first message
+        let mut w = paste.clone();
+        place_padding(&mut w, "PAD".into(), PadPlacement::AfterSystem, "--", 0);
+        assert_eq!(w, format!("PAD--{}", paste));
+    }
+}
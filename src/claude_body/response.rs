@@ -1,18 +1,26 @@
-use axum::{Json, body::Body, response::IntoResponse};
+use axum::{
+    Json,
+    body::Body,
+    response::{IntoResponse, Sse, sse::Event},
+};
 use bytes::Bytes;
 use eventsource_stream::{EventStream, Eventsource};
-use futures::{Stream, StreamExt, pin_mut};
+use futures::{Stream, StreamExt, pin_mut, stream};
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::{
     claude_state::ClaudeState,
     services::cache::CACHE,
-    types::claude_message::{ContentBlock, Message, Role},
+    types::claude_message::{
+        ContentBlock, ContentBlockDelta, Message, MessageDeltaContent, MessageStartContent, Role,
+        StopReason, StreamEvent,
+    },
     utils::print_out_text,
 };
 
 /// Merges server-sent events (SSE) from a stream into a single string
-/// Extracts and concatenates completion data from events
+/// Extracts and concatenates completion data or text deltas from events
 ///
 /// # Arguments
 /// * `stream` - Event stream to process
@@ -22,23 +30,176 @@ use crate::{
 pub async fn merge_sse(
     stream: EventStream<impl Stream<Item = Result<Bytes, rquest::Error>>>,
 ) -> String {
+    merge_sse_blocks(stream)
+        .await
+        .into_iter()
+        .filter_map(|b| match b {
+            ContentBlock::Text { text, .. } => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Merges server-sent events (SSE) from a stream into content blocks
+/// Keeps text, thinking and web search blocks, other blocks such as tool calls are dropped
+///
+/// # Arguments
+/// * `stream` - Event stream to process
+///
+/// # Returns
+/// Content blocks of the message, adjacent text blocks without citations are joined
+pub async fn merge_sse_blocks(
+    stream: EventStream<impl Stream<Item = Result<Bytes, rquest::Error>>>,
+) -> Vec<ContentBlock> {
     #[derive(Deserialize)]
     struct Data {
         completion: String,
     }
     pin_mut!(stream);
-    let mut w = String::new();
+    let mut completion = String::new();
+    let mut blocks: BTreeMap<usize, ContentBlock> = BTreeMap::new();
+    // partial JSON input of server tool calls
+    let mut inputs: HashMap<usize, String> = HashMap::new();
     while let Some(event) = stream.next().await {
         let Ok(event) = event else {
             continue;
         };
         let data = event.data;
-        let Ok(data) = serde_json::from_str::<Data>(&data) else {
+        if let Ok(data) = serde_json::from_str::<Data>(&data) {
+            completion += data.completion.as_str();
             continue;
-        };
-        w += data.completion.as_str();
+        }
+        match serde_json::from_str::<StreamEvent>(&data) {
+            Ok(StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            }) => {
+                blocks.insert(index, content_block);
+            }
+            Ok(StreamEvent::ContentBlockDelta { index, delta }) => {
+                let Some(block) = blocks.get_mut(&index) else {
+                    continue;
+                };
+                match (block, delta) {
+                    (ContentBlock::Text { text, .. }, ContentBlockDelta::TextDelta { text: t }) => {
+                        *text += t.as_str();
+                    }
+                    (
+                        ContentBlock::Text { citations, .. },
+                        ContentBlockDelta::CitationsDelta { citation },
+                    ) => {
+                        citations.get_or_insert_default().push(citation);
+                    }
+                    (
+                        ContentBlock::Thinking { thinking, .. },
+                        ContentBlockDelta::ThinkingDelta { thinking: t },
+                    ) => {
+                        *thinking += t.as_str();
+                    }
+                    (
+                        ContentBlock::Thinking { signature, .. },
+                        ContentBlockDelta::SignatureDelta { signature: s },
+                    ) => {
+                        *signature += s.as_str();
+                    }
+                    (
+                        ContentBlock::ServerToolUse { .. },
+                        ContentBlockDelta::InputJsonDelta { partial_json },
+                    ) => {
+                        *inputs.entry(index).or_default() += partial_json.as_str();
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    if blocks.is_empty() {
+        return vec![ContentBlock::text(completion)];
+    }
+    let mut merged: Vec<ContentBlock> = vec![];
+    for (index, mut block) in blocks {
+        if let ContentBlock::ServerToolUse { ref mut input, .. } = block
+            && let Some(json) = inputs.get(&index)
+            && let Ok(json) = serde_json::from_str(json)
+        {
+            *input = json;
+        }
+        match (merged.last_mut(), block) {
+            (
+                Some(ContentBlock::Text {
+                    text,
+                    citations: None,
+                }),
+                ContentBlock::Text {
+                    text: t,
+                    citations: None,
+                },
+            ) => {
+                *text += t.as_str();
+            }
+            (_, b @ ContentBlock::Text { .. })
+            | (_, b @ ContentBlock::Thinking { .. })
+            | (_, b @ ContentBlock::RedactedThinking { .. })
+            | (_, b @ ContentBlock::ServerToolUse { .. })
+            | (_, b @ ContentBlock::WebSearchToolResult { .. }) => merged.push(b),
+            _ => {}
+        }
     }
-    w
+    merged
+}
+
+/// Builds Claude API stream events for a complete assistant message
+///
+/// # Arguments
+/// * `text` - The full text of the message
+/// * `model` - Model name reported in the message start event
+///
+/// # Returns
+/// A list of events from `message_start` to `message_stop`
+pub fn text_to_events(text: String, model: String) -> Vec<StreamEvent> {
+    let start = MessageStartContent {
+        id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+        type_: "message".to_string(),
+        role: Role::Assistant,
+        model,
+        ..Default::default()
+    };
+    vec![
+        StreamEvent::MessageStart { message: start },
+        StreamEvent::ContentBlockStart {
+            index: 0,
+            content_block: ContentBlock::text(""),
+        },
+        StreamEvent::ContentBlockDelta {
+            index: 0,
+            delta: ContentBlockDelta::TextDelta { text },
+        },
+        StreamEvent::ContentBlockStop { index: 0 },
+        StreamEvent::MessageDelta {
+            delta: MessageDeltaContent {
+                stop_reason: Some(StopReason::EndTurn),
+                stop_sequence: None,
+            },
+            usage: None,
+        },
+        StreamEvent::MessageStop,
+    ]
+}
+
+/// Converts a Claude API stream event into an SSE event
+///
+/// # Arguments
+/// * `event` - The stream event to convert
+///
+/// # Returns
+/// An SSE event with the event name and JSON data set
+pub fn to_sse_event(event: &StreamEvent) -> Event {
+    let name = serde_json::to_value(event)
+        .ok()
+        .and_then(|v| v["type"].as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    Event::default().event(name).json_data(event).unwrap()
 }
 
 impl<S> From<S> for Message
@@ -53,10 +214,7 @@ where
     /// # Returns
     /// * `Message` - A message with assistant role and text content
     fn from(str: S) -> Self {
-        Message::new_blocks(
-            Role::Assistant,
-            vec![ContentBlock::Text { text: str.into() }],
-        )
+        Message::new_blocks(Role::Assistant, vec![ContentBlock::text(str)])
     }
 }
 
@@ -86,12 +244,73 @@ impl ClaudeState {
         // not streaming
         if !self.stream {
             let stream = input.eventsource();
-            let text = merge_sse(stream).await;
+            let blocks = merge_sse_blocks(stream).await;
+            let text = blocks
+                .iter()
+                .filter_map(|b| match b {
+                    ContentBlock::Text { text, .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<String>();
             print_out_text(&text, "non_stream.txt");
-            return Json(Message::from(text)).into_response();
+            return Json(Message::new_blocks(Role::Assistant, blocks)).into_response();
         }
 
         // stream the response
         Body::from_stream(input).into_response()
     }
+
+    /// Builds a response from a complete text, such as a validated structured output
+    ///
+    /// # Arguments
+    /// * `text` - The complete text of the assistant message
+    /// * `model` - Model name reported in stream events
+    ///
+    /// # Returns
+    /// * `axum::response::Response` - A JSON message, or an SSE stream if streaming
+    pub fn text_response(&self, text: String, model: String) -> axum::response::Response {
+        if !self.stream {
+            return Json(Message::from(text)).into_response();
+        }
+        let events = text_to_events(text, model)
+            .into_iter()
+            .map(|e| Ok::<_, axum::Error>(to_sse_event(&e)))
+            .collect::<Vec<_>>();
+        Sse::new(stream::iter(events)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use eventsource_stream::Eventsource;
+    use futures::stream;
+    use serde_json::json;
+
+    use super::merge_sse_blocks;
+    use crate::{claude_body::prefill::sse_bytes, types::claude_message::ContentBlock};
+
+    #[tokio::test]
+    async fn merges_thinking_and_text_blocks() {
+        let events = [
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "Let me "}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "thinking_delta", "thinking": "think."}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "text_delta", "text": "Hello"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_stop"}),
+        ];
+        let bytes = events
+            .iter()
+            .map(|e| Ok(sse_bytes(e["type"].as_str().unwrap(), &e.to_string())))
+            .collect::<Vec<Result<Bytes, rquest::Error>>>();
+        let blocks = merge_sse_blocks(stream::iter(bytes).eventsource()).await;
+        assert!(matches!(
+            blocks.as_slice(),
+            [ContentBlock::Thinking { thinking, .. }, ContentBlock::Text { text, .. }]
+                if thinking == "Let me think." && text == "Hello"
+        ));
+    }
 }
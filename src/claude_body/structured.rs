@@ -0,0 +1,386 @@
+use serde_json::Value;
+
+use crate::types::claude_message::ResponseFormat;
+
+impl ResponseFormat {
+    /// Whether the format requires the output to be JSON
+    pub fn is_json(&self) -> bool {
+        !matches!(self, ResponseFormat::Text)
+    }
+
+    /// Builds the instruction injected into the prompt
+    ///
+    /// # Returns
+    /// * `Option<String>` - Instruction text, None for plain text format
+    pub fn instruction(&self) -> Option<String> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(
+                "Respond only with a single valid JSON object. \
+                Do not wrap it in markdown code fences and do not add any other text."
+                    .to_string(),
+            ),
+            ResponseFormat::JsonSchema { json_schema } => {
+                let mut w = String::from(
+                    "Respond only with a single valid JSON value that conforms to the JSON schema below. \
+                    Do not wrap it in markdown code fences and do not add any other text.",
+                );
+                if let Some(ref desc) = json_schema.description {
+                    w += "\n\nDescription: ";
+                    w += desc;
+                }
+                if let Some(ref schema) = json_schema.schema {
+                    let schema = serde_json::to_string_pretty(schema).unwrap_or_default();
+                    w += "\n\n<schema>\n";
+                    w += schema.as_str();
+                    w += "\n</schema>";
+                }
+                Some(w)
+            }
+        }
+    }
+
+    /// Extracts and validates JSON from the model output
+    ///
+    /// # Arguments
+    /// * `text` - Complete text generated by the model
+    ///
+    /// # Returns
+    /// * `Ok(String)` - Compact JSON text if the output is valid
+    /// * `Err(String)` - Reason why the output is invalid
+    pub fn validate_output(&self, text: &str) -> Result<String, String> {
+        let value = match self {
+            ResponseFormat::Text => return Ok(text.to_string()),
+            ResponseFormat::JsonObject => {
+                let value = extract_json(text).ok_or("output is not valid JSON")?;
+                if !value.is_object() {
+                    return Err("output is not a JSON object".to_string());
+                }
+                value
+            }
+            ResponseFormat::JsonSchema { json_schema } => {
+                let value = extract_json(text).ok_or("output is not valid JSON")?;
+                if let Some(ref schema) = json_schema.schema {
+                    validate(&value, schema, schema, "$")?;
+                }
+                value
+            }
+        };
+        serde_json::to_string(&value).map_err(|e| e.to_string())
+    }
+}
+
+/// Extracts a JSON value from model output
+/// Accepts bare JSON, JSON wrapped in code fences, or JSON surrounded by other text
+///
+/// # Arguments
+/// * `text` - Text that may contain JSON
+///
+/// # Returns
+/// * `Option<Value>` - The parsed JSON value if found
+pub fn extract_json(text: &str) -> Option<Value> {
+    let text = text.trim();
+    if let Ok(v) = serde_json::from_str(text) {
+        return Some(v);
+    }
+    // strip markdown code fences
+    if let Some(inner) = text.strip_prefix("```") {
+        let inner = inner.split_once('\n').map(|(_, s)| s).unwrap_or(inner);
+        let inner = inner.trim_end().trim_end_matches("```");
+        if let Ok(v) = serde_json::from_str(inner.trim()) {
+            return Some(v);
+        }
+    }
+    // find the outermost object or array
+    let start = text.find(['{', '['])?;
+    let close = if text[start..].starts_with('{') {
+        '}'
+    } else {
+        ']'
+    };
+    let end = text.rfind(close)?;
+    if end <= start {
+        return None;
+    }
+    serde_json::from_str(&text[start..=end]).ok()
+}
+
+/// Resolves a local `$ref` such as `#/$defs/Item` against the root schema
+fn resolve_ref<'a>(root: &'a Value, r: &str) -> Option<&'a Value> {
+    let pointer = r.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+/// Checks whether a value matches a JSON schema type name
+fn type_matches(value: &Value, ty: &str) -> bool {
+    match ty {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+/// Validates a value against a subset of JSON schema
+///
+/// Supported keywords: `type`, `enum`, `const`, `properties`, `required`,
+/// `additionalProperties`, `items`, `minItems`, `maxItems`, `minLength`, `maxLength`,
+/// `minimum`, `maximum`, `anyOf`, `oneOf`, `allOf` and local `$ref`.
+///
+/// # Arguments
+/// * `value` - Value to validate
+/// * `schema` - Schema for the current value
+/// * `root` - Root schema, used to resolve `$ref`
+/// * `path` - JSON path of the current value, used in error messages
+///
+/// # Returns
+/// * `Result<(), String>` - Ok if valid, otherwise the first violation found
+pub fn validate(value: &Value, schema: &Value, root: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        // `true` or empty schema accepts everything
+        return if schema.as_bool() == Some(false) {
+            Err(format!("{}: not allowed", path))
+        } else {
+            Ok(())
+        };
+    };
+    if let Some(r) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let target = resolve_ref(root, r).ok_or(format!("{}: unresolved $ref {}", path, r))?;
+        validate(value, target, root, path)?;
+    }
+    match schema.get("type") {
+        Some(Value::String(ty)) if !type_matches(value, ty) => {
+            return Err(format!("{}: expected {}", path, ty));
+        }
+        Some(Value::Array(tys))
+            if !tys
+                .iter()
+                .filter_map(|t| t.as_str())
+                .any(|t| type_matches(value, t)) =>
+        {
+            return Err(format!("{}: unexpected type", path));
+        }
+        _ => {}
+    }
+    if let Some(e) = schema.get("enum").and_then(|e| e.as_array())
+        && !e.contains(value)
+    {
+        return Err(format!("{}: value not in enum", path));
+    }
+    if let Some(c) = schema.get("const")
+        && c != value
+    {
+        return Err(format!("{}: value does not match const", path));
+    }
+    if let Some(all) = schema.get("allOf").and_then(|a| a.as_array()) {
+        for s in all {
+            validate(value, s, root, path)?;
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(|a| a.as_array())
+        && !any.iter().any(|s| validate(value, s, root, path).is_ok())
+    {
+        return Err(format!("{}: does not match anyOf", path));
+    }
+    if let Some(one) = schema.get("oneOf").and_then(|a| a.as_array()) {
+        let matched = one
+            .iter()
+            .filter(|s| validate(value, s, root, path).is_ok())
+            .count();
+        if matched != 1 {
+            return Err(format!("{}: does not match exactly one of oneOf", path));
+        }
+    }
+    match value {
+        Value::Object(obj) => {
+            if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+                for k in required.iter().filter_map(|k| k.as_str()) {
+                    if !obj.contains_key(k) {
+                        return Err(format!("{}: missing required property {}", path, k));
+                    }
+                }
+            }
+            let props = schema.get("properties").and_then(|p| p.as_object());
+            for (k, v) in obj {
+                let sub_path = format!("{}.{}", path, k);
+                if let Some(s) = props.and_then(|p| p.get(k)) {
+                    validate(v, s, root, &sub_path)?;
+                    continue;
+                }
+                match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        return Err(format!("{}: additional property not allowed", sub_path));
+                    }
+                    Some(s @ Value::Object(_)) => validate(v, s, root, &sub_path)?,
+                    _ => {}
+                }
+            }
+        }
+        Value::Array(arr) => {
+            if let Some(min) = schema.get("minItems").and_then(|m| m.as_u64())
+                && (arr.len() as u64) < min
+            {
+                return Err(format!("{}: fewer than {} items", path, min));
+            }
+            if let Some(max) = schema.get("maxItems").and_then(|m| m.as_u64())
+                && arr.len() as u64 > max
+            {
+                return Err(format!("{}: more than {} items", path, max));
+            }
+            if let Some(items) = schema.get("items") {
+                for (i, v) in arr.iter().enumerate() {
+                    validate(v, items, root, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(|m| m.as_u64())
+                && len < min
+            {
+                return Err(format!("{}: shorter than {}", path, min));
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|m| m.as_u64())
+                && len > max
+            {
+                return Err(format!("{}: longer than {}", path, max));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(|m| m.as_f64())
+                && n < min
+            {
+                return Err(format!("{}: less than {}", path, min));
+            }
+            if let Some(max) = schema.get("maximum").and_then(|m| m.as_f64())
+                && n > max
+            {
+                return Err(format!("{}: greater than {}", path, max));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn check(value: Value, schema: Value) -> Result<(), String> {
+        validate(&value, &schema, &schema, "$")
+    }
+
+    #[test]
+    fn rejects_type_mismatches() {
+        assert!(check(json!(1), json!({ "type": "integer" })).is_ok());
+        assert!(check(json!(1.0), json!({ "type": "integer" })).is_ok());
+        assert_eq!(
+            check(json!(1.5), json!({ "type": "integer" })),
+            Err("$: expected integer".to_string())
+        );
+        assert_eq!(
+            check(json!("1"), json!({ "type": ["number", "null"] })),
+            Err("$: unexpected type".to_string())
+        );
+        assert!(check(json!(null), json!({ "type": ["number", "null"] })).is_ok());
+    }
+
+    #[test]
+    fn checks_required_and_enum() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "color": { "enum": ["red", "green"] } },
+            "required": ["color"],
+        });
+        assert!(check(json!({ "color": "red" }), schema.to_owned()).is_ok());
+        assert_eq!(
+            check(json!({}), schema.to_owned()),
+            Err("$: missing required property color".to_string())
+        );
+        assert_eq!(
+            check(json!({ "color": "blue" }), schema),
+            Err("$.color: value not in enum".to_string())
+        );
+    }
+
+    #[test]
+    fn resolves_nested_refs_with_paths() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "items": { "type": "array", "items": { "$ref": "#/$defs/Item" } },
+            },
+            "$defs": {
+                "Item": {
+                    "type": "object",
+                    "properties": { "price": { "type": "number", "minimum": 0 } },
+                    "required": ["price"],
+                    "additionalProperties": false,
+                },
+            },
+        });
+        assert!(check(json!({ "items": [{ "price": 1 }] }), schema.to_owned()).is_ok());
+        assert_eq!(
+            check(
+                json!({ "items": [{ "price": 1 }, { "price": -1 }] }),
+                schema.to_owned()
+            ),
+            Err("$.items[1].price: less than 0".to_string())
+        );
+        assert_eq!(
+            check(json!({ "items": [{ "price": 1, "name": "a" }] }), schema),
+            Err("$.items[0].name: additional property not allowed".to_string())
+        );
+        assert_eq!(
+            check(json!(1), json!({ "$ref": "#/$defs/Missing" })),
+            Err("$: unresolved $ref #/$defs/Missing".to_string())
+        );
+    }
+
+    #[test]
+    fn repairs_or_rejects_output() {
+        let format = serde_json::from_value::<ResponseFormat>(json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "answer",
+                "schema": {
+                    "type": "object",
+                    "properties": { "answer": { "type": "integer" } },
+                    "required": ["answer"],
+                },
+            },
+        }))
+        .unwrap();
+        // fenced or surrounded JSON is extracted and compacted
+        assert_eq!(
+            format.validate_output("```json\n{ \"answer\": 42 }\n```"),
+            Ok(r#"{"answer":42}"#.to_string())
+        );
+        assert_eq!(
+            format.validate_output("Sure! {\"answer\": 42} Hope it helps."),
+            Ok(r#"{"answer":42}"#.to_string())
+        );
+        assert_eq!(
+            format.validate_output("The answer is 42"),
+            Err("output is not valid JSON".to_string())
+        );
+        assert_eq!(
+            format.validate_output(r#"{"answer": "42"}"#),
+            Err("$.answer: expected integer".to_string())
+        );
+        assert_eq!(
+            ResponseFormat::JsonObject.validate_output("[1, 2]"),
+            Err("output is not a JSON object".to_string())
+        );
+    }
+}
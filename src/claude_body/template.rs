@@ -0,0 +1,141 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+use tracing::{info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, UserKey},
+    error::ClewdrError,
+};
+
+/// A loaded template with the modification time of its file
+type LoadedTemplate = (SystemTime, Arc<PromptTemplate>);
+
+/// Loaded templates, keyed by path, reloaded when the file is modified
+static TEMPLATES: LazyLock<Mutex<HashMap<PathBuf, LoadedTemplate>>> =
+    LazyLock::new(Default::default);
+
+/// Prompt template controlling how the transcript is formatted
+///
+/// Each field is a string with `{{variable}}` placeholders.
+/// Available everywhere: `model`, `date`, `time`, `message_count`, `system`,
+/// `user`, `assistant` and `custom_prompt`.
+/// Turns additionally get `content`, `role` and `index`, images get `index`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct PromptTemplate {
+    /// System section, only rendered if the system prompt is not empty
+    pub system: String,
+    /// Format of a user turn
+    pub user: String,
+    /// Format of an assistant turn
+    pub assistant: String,
+    /// Separator between the system section and turns
+    pub separator: String,
+    /// Placeholder inserted in place of each image, empty to omit
+    pub image: String,
+    /// The final prompt sent in the prompt field
+    pub prompt: String,
+}
+
+impl Default for PromptTemplate {
+    /// Matches the built-in transcript format
+    fn default() -> Self {
+        Self {
+            system: "{{system}}".to_string(),
+            user: "{{user}}: {{content}}".to_string(),
+            assistant: "{{assistant}}: {{content}}".to_string(),
+            separator: "\n\n".to_string(),
+            image: String::new(),
+            prompt: "{{custom_prompt}}".to_string(),
+        }
+    }
+}
+
+/// Selects the prompt template for a request
+///
+/// The template of the API key takes precedence, then the template of the
+/// model alias, then the longest matching model prefix in `model_templates`,
+/// then `template_file`.
+///
+/// # Arguments
+/// * `model` - Model of the request
+/// * `key` - API key the request was authenticated with
+/// * `alias` - Template set by the model alias of the request
+///
+/// # Returns
+/// * `Option<Arc<PromptTemplate>>` - The template, None to use the built-in format
+pub fn select_template(
+    model: &str,
+    key: Option<&UserKey>,
+    alias: Option<&Path>,
+) -> Option<Arc<PromptTemplate>> {
+    let config = CLEWDR_CONFIG.load();
+    let path = key
+        .and_then(|k| k.template.as_deref())
+        .or(alias)
+        .or_else(|| {
+            config
+                .model_templates
+                .iter()
+                .filter(|(m, _)| model.starts_with(m.as_str()))
+                .max_by_key(|(m, _)| m.len())
+                .map(|(_, p)| p.as_path())
+        })
+        .or(config.template_file.as_deref())
+        .filter(|p| !p.as_os_str().is_empty())?;
+    load_template(path)
+        .inspect_err(|e| {
+            warn!("Failed to load template {}: {}", path.display(), e);
+        })
+        .ok()
+}
+
+/// Loads a template file, reusing the parsed template until the file is modified
+fn load_template(path: &Path) -> Result<Arc<PromptTemplate>, ClewdrError> {
+    let modified = fs::metadata(path)?.modified()?;
+    let mut templates = TEMPLATES.lock().map_err(|_| ClewdrError::UnexpectedNone)?;
+    if let Some((t, template)) = templates.get(path)
+        && *t == modified
+    {
+        return Ok(template.to_owned());
+    }
+    let template: PromptTemplate = toml::from_str(&fs::read_to_string(path)?)?;
+    let template = Arc::new(template);
+    info!("Loaded template: {}", path.display());
+    templates.insert(path.to_owned(), (modified, template.to_owned()));
+    Ok(template)
+}
+
+/// Renders `{{variable}}` placeholders
+/// Unknown variables are left untouched
+///
+/// # Arguments
+/// * `template` - Template text
+/// * `vars` - Values of the variables
+///
+/// # Returns
+/// The rendered text
+pub fn render(template: &str, vars: &HashMap<&str, String>) -> String {
+    let mut w = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        w += &rest[..start];
+        let name = rest[start + 2..start + end].trim();
+        match vars.get(name) {
+            Some(v) => w += v,
+            None => w += &rest[start..start + end + 2],
+        }
+        rest = &rest[start + end + 2..];
+    }
+    w += rest;
+    w
+}
@@ -0,0 +1,68 @@
+use async_stream::stream;
+use bytes::Bytes;
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::{Stream, StreamExt, pin_mut};
+use serde_json::{Value, json};
+
+use super::prefill::sse_bytes;
+
+/// Delta types defined by the Claude API
+const KNOWN_DELTAS: [&str; 5] = [
+    "text_delta",
+    "input_json_delta",
+    "thinking_delta",
+    "signature_delta",
+    "citations_delta",
+];
+
+/// Rewrites thinking events of a Claude.ai stream into Claude API events
+///
+/// Claude.ai adds fields such as timestamps and summaries to thinking blocks,
+/// and sends delta types unknown to the API. Thinking blocks are reduced to
+/// `thinking` and `signature`, and unknown deltas are dropped.
+///
+/// # Arguments
+/// * `input` - The response stream from Claude.ai
+///
+/// # Returns
+/// A stream of SSE bytes with API compatible thinking events
+pub fn normalize_thinking(
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+    stream! {
+        let events = input.eventsource();
+        pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(EventStreamError::Transport(e)) => {
+                    yield Err(e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let Ok(mut data) = serde_json::from_str::<Value>(&event.data) else {
+                yield Ok(sse_bytes(&event.event, &event.data));
+                continue;
+            };
+            match data["type"].as_str() {
+                Some("content_block_start") if data["content_block"]["type"] == "thinking" => {
+                    let block = &data["content_block"];
+                    let thinking = block["thinking"].as_str().unwrap_or_default();
+                    let signature = block["signature"].as_str().unwrap_or_default();
+                    data["content_block"] = json!({
+                        "type": "thinking",
+                        "thinking": thinking,
+                        "signature": signature,
+                    });
+                    yield Ok(sse_bytes(&event.event, &data.to_string()));
+                }
+                Some("content_block_delta")
+                    if !data["delta"]["type"]
+                        .as_str()
+                        .is_some_and(|t| KNOWN_DELTAS.contains(&t)) => {}
+                _ => yield Ok(sse_bytes(&event.event, &event.data)),
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use image::ImageReader;
+use std::io::Cursor;
+
+use crate::{
+    claude_state::ClaudeState,
+    types::claude_message::{CountMessageTokensParams, ImageSource},
+    utils::TOKENIZER,
+};
+
+/// Tokens assumed for an image whose size cannot be determined
+const DEFAULT_IMAGE_TOKENS: u32 = 1600;
+
+/// Estimates the tokens of an image as Claude does, `width * height / 750`
+fn image_tokens(source: &ImageSource) -> u32 {
+    let size = BASE64_STANDARD.decode(&source.data).ok().and_then(|bytes| {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()
+    });
+    match size {
+        Some((w, h)) => (w as u64 * h as u64 / 750).clamp(1, DEFAULT_IMAGE_TOKENS as u64) as u32,
+        None => DEFAULT_IMAGE_TOKENS,
+    }
+}
+
+impl ClaudeState {
+    /// Estimates the input tokens of a request
+    ///
+    /// The estimate is computed over the request Claude.ai would receive,
+    /// including the system prompt, padding, attachments and images,
+    /// plus the definitions of client tools.
+    ///
+    /// # Arguments
+    /// * `p` - The token counting request
+    ///
+    /// # Returns
+    /// * `u32` - The estimated number of input tokens
+    pub fn count_tokens(&self, p: CountMessageTokensParams) -> u32 {
+        let tools = p
+            .tools
+            .as_ref()
+            .and_then(|t| serde_json::to_string(t).ok())
+            .unwrap_or_default();
+        let Ok(body) = self.transform_request(p.into()) else {
+            return 0;
+        };
+        let text = body
+            .attachments
+            .iter()
+            .map(|a| a.extracted_content.as_str())
+            .chain([body.prompt.as_str(), tools.as_str()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let images = body.images.iter().map(image_tokens).sum::<u32>();
+        TOKENIZER.encode_ordinary(&text).len() as u32 + images
+    }
+}
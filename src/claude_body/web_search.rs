@@ -0,0 +1,320 @@
+use async_stream::stream;
+use bytes::Bytes;
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::{Stream, StreamExt, pin_mut};
+use rquest::Url;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+
+use super::prefill::sse_bytes;
+use crate::{
+    config::CLEWDR_CONFIG, types::claude_message::CreateMessageParams, utils::fetch::host_matches,
+};
+
+/// Web search settings of a request
+///
+/// Claude.ai has no search limits, so `max_uses` and the domains are passed
+/// as guidance, and results from disallowed domains are removed from the output.
+#[derive(Debug, Clone, Default)]
+pub struct WebSearch {
+    pub max_uses: Option<u32>,
+    pub allowed_domains: Vec<String>,
+    pub blocked_domains: Vec<String>,
+}
+
+impl WebSearch {
+    /// Resolves whether a request uses web search
+    ///
+    /// Requests opt in with the `web_search` server tool or OpenAI
+    /// `web_search_options`, `web_search` in the config is the default.
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    ///
+    /// # Returns
+    /// * `Option<WebSearch>` - The search settings, None if web search is disabled
+    pub fn from_request(p: &CreateMessageParams) -> Option<Self> {
+        if let Some(tool) = p.tools.iter().flatten().find(|t| t.is_web_search()) {
+            return Some(Self {
+                max_uses: tool.max_uses,
+                allowed_domains: tool.allowed_domains.to_owned().unwrap_or_default(),
+                blocked_domains: tool.blocked_domains.to_owned().unwrap_or_default(),
+            });
+        }
+        if p.web_search_options.is_some() || CLEWDR_CONFIG.load().web_search {
+            return Some(Self::default());
+        }
+        None
+    }
+
+    /// Instruction appended to the prompt describing the search limits
+    pub fn instruction(&self) -> Option<String> {
+        let mut rules = vec![];
+        if let Some(max_uses) = self.max_uses {
+            rules.push(format!("Search the web at most {} times.", max_uses));
+        }
+        if !self.allowed_domains.is_empty() {
+            rules.push(format!(
+                "Only use results from these domains: {}.",
+                self.allowed_domains.join(", ")
+            ));
+        }
+        if !self.blocked_domains.is_empty() {
+            rules.push(format!(
+                "Never use results from these domains: {}.",
+                self.blocked_domains.join(", ")
+            ));
+        }
+        if rules.is_empty() {
+            None
+        } else {
+            Some(rules.join(" "))
+        }
+    }
+
+    /// Whether results from the URL may be returned
+    fn allows(&self, url: &str) -> bool {
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+        else {
+            return false;
+        };
+        (self.allowed_domains.is_empty()
+            || self.allowed_domains.iter().any(|d| host_matches(&host, d)))
+            && !self.blocked_domains.iter().any(|d| host_matches(&host, d))
+    }
+}
+
+/// A citation opened by Claude.ai and not yet closed
+struct OpenCitation {
+    index: u64,
+    url: Value,
+    title: Value,
+    cited_text: String,
+}
+
+impl OpenCitation {
+    /// Builds the `citations_delta` event closing the citation
+    fn into_event(self) -> Bytes {
+        let data = json!({
+            "type": "content_block_delta",
+            "index": self.index,
+            "delta": {
+                "type": "citations_delta",
+                "citation": {
+                    "type": "web_search_result_location",
+                    "url": self.url,
+                    "title": self.title,
+                    "encrypted_index": "",
+                    "cited_text": self.cited_text,
+                },
+            },
+        });
+        sse_bytes("content_block_delta", &data.to_string())
+    }
+}
+
+/// Converts the results of a Claude.ai web search into API search results
+fn search_results(content: &Value, search: &WebSearch) -> Value {
+    let results = content
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|r| r["url"].as_str().is_some_and(|u| search.allows(u)))
+        .map(|r| {
+            json!({
+                "type": "web_search_result",
+                "url": r["url"],
+                "title": r["title"],
+                "encrypted_content": "",
+                "page_age": r["metadata"]["age"],
+            })
+        })
+        .collect::<Vec<_>>();
+    Value::Array(results)
+}
+
+/// Translates web search events of a Claude.ai stream into Claude API events
+///
+/// Searches become `server_tool_use` and `web_search_tool_result` blocks,
+/// and Claude.ai citation markers become `citations_delta` events
+/// carrying the cited text. Results and citations from domains not allowed
+/// by the request are dropped. Other events are passed through unchanged.
+///
+/// # Arguments
+/// * `input` - The response stream from Claude.ai
+/// * `search` - Web search settings of the request
+///
+/// # Returns
+/// A stream of SSE bytes with API compatible web search events
+pub fn translate_web_search(
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    search: WebSearch,
+) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+    stream! {
+        // blocks holding search results, their deltas are dropped
+        let mut results: HashSet<u64> = HashSet::new();
+        let mut citations: HashMap<String, OpenCitation> = HashMap::new();
+        let events = input.eventsource();
+        pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(EventStreamError::Transport(e)) => {
+                    yield Err(e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let Ok(mut data) = serde_json::from_str::<Value>(&event.data) else {
+                yield Ok(sse_bytes(&event.event, &event.data));
+                continue;
+            };
+            let index = data["index"].as_u64().unwrap_or_default();
+            match data["type"].as_str() {
+                Some("content_block_start") if data["content_block"]["name"] == "web_search" => {
+                    let block = &data["content_block"];
+                    let block = match block["type"].as_str() {
+                        Some("tool_use") => json!({
+                            "type": "server_tool_use",
+                            "id": block["id"],
+                            "name": "web_search",
+                            "input": {},
+                        }),
+                        Some("tool_result") => {
+                            results.insert(index);
+                            json!({
+                                "type": "web_search_tool_result",
+                                "tool_use_id": block["tool_use_id"],
+                                "content": search_results(&block["content"], &search),
+                            })
+                        }
+                        _ => {
+                            yield Ok(sse_bytes(&event.event, &event.data));
+                            continue;
+                        }
+                    };
+                    data["content_block"] = block;
+                    yield Ok(sse_bytes(&event.event, &data.to_string()));
+                }
+                Some("content_block_delta") if results.contains(&index) => {}
+                Some("content_block_delta") => match data["delta"]["type"].as_str() {
+                    Some("citation_start_delta") => {
+                        let citation = &data["delta"]["citation"];
+                        let Some(uuid) = citation["uuid"].as_str() else {
+                            continue;
+                        };
+                        if !citation["url"].as_str().is_some_and(|u| search.allows(u)) {
+                            continue;
+                        }
+                        citations.insert(
+                            uuid.to_string(),
+                            OpenCitation {
+                                index,
+                                url: citation["url"].to_owned(),
+                                title: citation["title"].to_owned(),
+                                cited_text: String::new(),
+                            },
+                        );
+                    }
+                    Some("citation_end_delta") => {
+                        let uuid = data["delta"]["citation_uuid"].as_str().unwrap_or_default();
+                        if let Some(citation) = citations.remove(uuid) {
+                            yield Ok(citation.into_event());
+                        }
+                    }
+                    Some("text_delta") => {
+                        let text = data["delta"]["text"].as_str().unwrap_or_default();
+                        for c in citations.values_mut().filter(|c| c.index == index) {
+                            c.cited_text += text;
+                        }
+                        yield Ok(sse_bytes(&event.event, &event.data));
+                    }
+                    _ => yield Ok(sse_bytes(&event.event, &event.data)),
+                },
+                Some("content_block_stop") => {
+                    // close citations left open by the block
+                    let open = citations
+                        .iter()
+                        .filter(|(_, c)| c.index == index)
+                        .map(|(k, _)| k.to_owned())
+                        .collect::<Vec<_>>();
+                    for uuid in open {
+                        if let Some(citation) = citations.remove(&uuid) {
+                            yield Ok(citation.into_event());
+                        }
+                    }
+                    results.remove(&index);
+                    yield Ok(sse_bytes(&event.event, &event.data));
+                }
+                _ => yield Ok(sse_bytes(&event.event, &event.data)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use eventsource_stream::Eventsource;
+    use futures::stream;
+    use serde_json::json;
+
+    use super::{WebSearch, translate_web_search};
+    use crate::{
+        claude_body::{prefill::sse_bytes, response::merge_sse_blocks},
+        types::claude_message::ContentBlock,
+    };
+
+    #[tokio::test]
+    async fn translates_search_into_merged_blocks() {
+        let events = [
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "tool_use", "id": "srvtoolu_1", "name": "web_search", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": "{\"query\": \"rust\"}"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_result", "tool_use_id": "srvtoolu_1", "name": "web_search", "content": [
+                {"url": "https://www.rust-lang.org/", "title": "Rust", "metadata": {"age": "1 day ago"}},
+                {"url": "https://blocked.example/", "title": "Blocked"},
+            ]}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "ignored"}}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "content_block_start", "index": 2, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "citation_start_delta", "citation": {"uuid": "c1", "url": "https://www.rust-lang.org/", "title": "Rust"}}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "text_delta", "text": "Rust is fast."}}),
+            json!({"type": "content_block_delta", "index": 2, "delta": {"type": "citation_end_delta", "citation_uuid": "c1"}}),
+            json!({"type": "content_block_stop", "index": 2}),
+            json!({"type": "message_stop"}),
+        ];
+        let bytes = events
+            .iter()
+            .map(|e| Ok(sse_bytes(e["type"].as_str().unwrap(), &e.to_string())))
+            .collect::<Vec<Result<Bytes, rquest::Error>>>();
+        let search = WebSearch {
+            blocked_domains: vec!["blocked.example".to_string()],
+            ..Default::default()
+        };
+        let translated = translate_web_search(stream::iter(bytes), search);
+        let blocks = merge_sse_blocks(translated.eventsource()).await;
+        let [
+            ContentBlock::ServerToolUse { id, name, input },
+            ContentBlock::WebSearchToolResult {
+                tool_use_id,
+                content,
+            },
+            ContentBlock::Text { text, citations },
+        ] = blocks.as_slice()
+        else {
+            panic!("unexpected blocks: {:?}", blocks);
+        };
+        assert_eq!((id.as_str(), name.as_str()), ("srvtoolu_1", "web_search"));
+        assert_eq!(input, &json!({"query": "rust"}));
+        assert_eq!(tool_use_id, "srvtoolu_1");
+        assert_eq!(content.as_array().unwrap().len(), 1);
+        assert_eq!(content[0]["url"], "https://www.rust-lang.org/");
+        assert_eq!(text, "Rust is fast.");
+        let citations = citations.as_ref().unwrap();
+        assert_eq!(citations.len(), 1);
+        assert_eq!(citations[0]["cited_text"], "Rust is fast.");
+    }
+}
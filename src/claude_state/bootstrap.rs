@@ -7,6 +7,7 @@ use crate::{
     claude_state::ClaudeState,
     config::{CLEWDR_CONFIG, Reason},
     error::{CheckClaudeErr, ClewdrError},
+    services::model_list::{CLAUDE_MODELS, collect_claude_models},
     utils::print_out_json,
 };
 
@@ -17,6 +18,7 @@ impl ClaudeState {
     /// 1. Sends a request to get the bootstrap data from Claude.ai
     /// 2. Validates the cookie and account information
     /// 3. Collects capabilities and checks if the account is pro
+    ///    and records the models the account can use
     /// 4. Retrieves organization information
     /// 5. Checks for account flags (restrictions, warnings, bans)
     ///
@@ -62,6 +64,10 @@ impl ClaudeState {
         if !self.is_pro() && CLEWDR_CONFIG.load().skip_non_pro {
             return Err(ClewdrError::InvalidCookie(Reason::NonPro));
         }
+        // remember the models this account can use for the model listing
+        for model in collect_claude_models(&bootstrap) {
+            CLAUDE_MODELS.insert(model, ());
+        }
         let mut w = String::new();
         writeln!(
             w,
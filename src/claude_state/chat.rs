@@ -1,5 +1,7 @@
+use bytes::Bytes;
 use colored::Colorize;
-use futures::TryFutureExt;
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt};
 use rquest::{Method, Response, header::ACCEPT};
 use scopeguard::defer;
 use serde_json::json;
@@ -8,14 +10,20 @@ use tokio::spawn;
 use tracing::{Instrument, Level, debug, error, info, span, warn};
 
 use crate::{
-    config::CLEWDR_CONFIG,
-    error::{CheckClaudeErr, ClewdrError},
+    claude_body::{
+        prefill::{extract_prefill, strip_prefill},
+        response::merge_sse,
+        thinking::normalize_thinking,
+        web_search::{WebSearch, translate_web_search},
+    },
+    config::{CLEWDR_CONFIG, Profile, Reason},
+    error::{CheckClaudeErr, ClewdrError, RetryClass},
     services::cache::{CACHE, GetHashKey},
     types::claude_message::CreateMessageParams,
-    utils::print_out_json,
+    utils::{print_out_json, retry::Backoff},
 };
 
-use super::ClaudeState;
+use super::{ClaudeState, failover::failover_stream};
 
 impl ClaudeState {
     /// Attempts to retrieve a response from cache or initiates background caching
@@ -33,6 +41,10 @@ impl ClaudeState {
         &self,
         p: &CreateMessageParams,
     ) -> Option<axum::response::Response> {
+        if p.response_format.as_ref().is_some_and(|f| f.is_json()) {
+            // structured output must be validated, never cached
+            return None;
+        }
         let key = p.get_hash();
         if let Some(stream) = CACHE.pop(key) {
             info!("[CACHE] found response for key: {}", key);
@@ -57,9 +69,15 @@ impl ClaudeState {
     /// - Response transformation according to the specified API format
     /// - Error handling and cleanup
     ///
-    /// The method implements a sophisticated retry mechanism to handle transient failures,
-    /// and manages conversation cleanup to prevent resource leaks. It also includes
-    /// performance tracking to measure response times.
+    /// Failures are retried following `claude_retry`: transient errors are retried on
+    /// the same cookie, limited or invalid cookies are returned and another one is used,
+    /// and errors caused by the request fail immediately. Retries wait with exponential
+    /// backoff until the deadline of the policy. Conversations are cleaned up to prevent
+    /// resource leaks.
+    ///
+    /// When structured output is requested, the whole output is buffered and validated,
+    /// and a fresh attempt is made if validation fails, up to `structured_output_retries`.
+    /// These attempts do not count towards `max_retries`.
     ///
     /// # Arguments
     /// * `p` - The client request body containing messages and configuration
@@ -70,14 +88,34 @@ impl ClaudeState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
-        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
-            if i > 0 {
-                info!("[RETRY] attempt: {}", i.to_string().green());
+        let structured = p.response_format.to_owned().filter(|f| f.is_json());
+        let model = p.model.to_owned();
+        let prefill = extract_prefill(&p);
+        let prepend = CLEWDR_CONFIG.load().prefill_prepend;
+        let failover = self.stream && self.key.is_none() && CLEWDR_CONFIG.load().stream_failover;
+        let policy = CLEWDR_CONFIG.load().claude_retry.to_owned();
+        let mut backoff = Backoff::new(policy.to_owned());
+        let mut structured_failures = 0;
+        // cookie to retry on, and the retries made on it
+        let mut same_cookie = None;
+        let mut same_retries = 0;
+        // structured output failures are counted apart from `max_retries`
+        let mut retries = 0;
+        while retries <= CLEWDR_CONFIG.load().max_retries {
+            let attempt = retries + structured_failures;
+            if attempt > 0 {
+                info!("[RETRY] attempt: {}", attempt.to_string().green());
             }
             let mut state = self.to_owned();
             let p = p.to_owned();
 
-            state.request_cookie().await?;
+            let reused = match same_cookie.take() {
+                Some(cookie) => state.request_exact_cookie(cookie).await.is_ok(),
+                None => false,
+            };
+            if !reused {
+                state.request_cookie_for(&p).await?;
+            }
 
             let defer_clone = state.to_owned();
             defer! {
@@ -87,20 +125,46 @@ impl ClaudeState {
                 });
             }
             // check if request is successful
-            let web_res = async { state.bootstrap().await.and(state.send_chat(p).await) };
-            let transform_res =
-                web_res.and_then(async |r| Ok(self.transform_response(r.bytes_stream()).await));
+            let transform_res = async {
+                let stream = state
+                    .open_stream(p.to_owned(), prefill.to_owned(), prepend)
+                    .await?;
+                // race another cookie if the first token is slow
+                let (stream, secondary) = if state.hedges(&p.model) {
+                    let (stream, secondary) = state
+                        .hedge(p.to_owned(), prefill.to_owned(), prepend, stream.boxed())
+                        .await;
+                    (stream.left_stream(), secondary)
+                } else {
+                    (stream.right_stream(), None)
+                };
+                let Some(ref format) = structured else {
+                    // continue on another cookie if the stream breaks,
+                    // starting from the cookie that won the hedge
+                    let stream = if failover {
+                        let state = secondary.unwrap_or_else(|| state.to_owned());
+                        failover_stream(state, p, prefill.to_owned(), stream).left_stream()
+                    } else {
+                        stream.right_stream()
+                    };
+                    return Ok(self.transform_response(stream).await);
+                };
+                // buffer the whole output and validate it before responding
+                let text = merge_sse(stream.eventsource()).await;
+                let json = format
+                    .validate_output(&text)
+                    .map_err(ClewdrError::InvalidStructuredOutput)?;
+                Ok(self.text_response(json, model.to_owned()))
+            };
 
             match transform_res.await {
-                Ok(b) => {
-                    if let Err(e) = state.clean_chat().await {
-                        warn!("Failed to clean chat: {}", e);
-                    }
-                    return Ok(b);
-                }
+                // the chat is cleaned up once its stream is done
+                Ok(b) => return Ok(b),
                 Err(e) => {
-                    // delete chat after an error
-                    if let Err(e) = state.clean_chat().await {
+                    // delete chat after an error, unless its stream was consumed
+                    if !matches!(e, ClewdrError::InvalidStructuredOutput(_))
+                        && let Err(e) = state.clean_chat().await
+                    {
                         warn!("Failed to clean chat: {}", e);
                     }
                     error!(
@@ -108,12 +172,38 @@ impl ClaudeState {
                         state.cookie.as_ref().unwrap().cookie.ellipse().green(),
                         e
                     );
-                    // 429 error
-                    if let ClewdrError::InvalidCookie(ref r) = e {
-                        state.return_cookie(Some(r.to_owned())).await;
-                        continue;
+                    if let ClewdrError::InvalidStructuredOutput(_) = e {
+                        structured_failures += 1;
+                        if structured_failures <= CLEWDR_CONFIG.load().structured_output_retries {
+                            continue;
+                        }
+                        return Err(e);
+                    }
+                    match e.retry_class() {
+                        RetryClass::Fatal => return Err(e),
+                        RetryClass::SameCredential
+                            if same_retries < policy.same_credential_retries =>
+                        {
+                            same_retries += 1;
+                            same_cookie = state.cookie.to_owned();
+                        }
+                        _ => {
+                            same_retries = 0;
+                            let reason = match e {
+                                ClewdrError::InvalidCookie(ref r) => Some(r.to_owned()),
+                                _ if e.is_rate_limit() => {
+                                    Some(Reason::TooManyRequest(policy.cooldown_until()))
+                                }
+                                _ => None,
+                            };
+                            state.return_cookie(reason).await;
+                        }
+                    }
+                    retries += 1;
+                    if !backoff.wait().await {
+                        error!("Retry deadline exceeded");
+                        return Err(e);
                     }
-                    return Err(e);
                 }
             }
         }
@@ -121,6 +211,46 @@ impl ClaudeState {
         Err(ClewdrError::TooManyRetries)
     }
 
+    /// Sends the request and converts the Claude.ai stream into Claude API events
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    /// * `prefill` - The assistant prefill whose echo is stripped, if any
+    /// * `prepend` - Whether the prefill is prepended to the output
+    ///
+    /// # Returns
+    /// * `Result<impl Stream, ClewdrError>` - The event stream of the response
+    pub async fn open_stream(
+        &mut self,
+        p: CreateMessageParams,
+        prefill: Option<String>,
+        prepend: bool,
+    ) -> Result<impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static, ClewdrError>
+    {
+        self.bootstrap().await?;
+        let thinking = p.thinking.as_ref().is_some_and(|t| t.is_enabled());
+        let web_search = WebSearch::from_request(&p);
+        let history = p.to_owned();
+        let stream = self.send_chat(p).await?.bytes_stream();
+        // record the conversation before the chat is cleaned up at the end of the stream
+        let stream = self.track_conversation(stream, history);
+        let stream = self.guard_stream(stream);
+        let stream = match web_search {
+            Some(search) => translate_web_search(stream, search).left_stream(),
+            None => stream.right_stream(),
+        };
+        let stream = if thinking {
+            normalize_thinking(stream).left_stream()
+        } else {
+            stream.right_stream()
+        };
+        let stream = match prefill {
+            Some(prefill) => strip_prefill(stream, prefill, prepend).left_stream(),
+            None => stream.right_stream(),
+        };
+        Ok(stream)
+    }
+
     /// Sends a message to the Claude API by creating a new conversation and processing the request
     ///
     /// This method performs several key operations:
@@ -139,55 +269,51 @@ impl ClaudeState {
     ///
     /// # Returns
     /// * `Result<Response, ClewdrError>` - Response from Claude or error
-    async fn send_chat(&mut self, p: CreateMessageParams) -> Result<Response, ClewdrError> {
+    async fn send_chat(&mut self, mut p: CreateMessageParams) -> Result<Response, ClewdrError> {
         let org_uuid = self
             .org_uuid
             .to_owned()
             .ok_or(ClewdrError::UnexpectedNone)?;
 
-        // Create a new conversation
-        let new_uuid = uuid::Uuid::new_v4().to_string();
-        let endpoint = format!(
-            "{}/api/organizations/{}/chat_conversations",
-            self.endpoint, org_uuid
-        );
-        let mut body = json!({
-            "uuid": new_uuid,
-            "name": format!("ClewdR-{}", new_uuid),
-        });
+        let profile = CLEWDR_CONFIG
+            .load()
+            .profile_for(&p.model, self.user_key.as_ref())
+            .cloned();
 
-        // enable thinking mode
-        if p.thinking.is_some() && self.is_pro() {
-            body["paprika_mode"] = "extended".into();
-            body["model"] = p.model.to_owned().into();
-        }
-        self.build_request(Method::POST, endpoint)
-            .json(&body)
-            .send()
-            .await?
-            .check_claude()
-            .await?;
-        self.conv_uuid = Some(new_uuid.to_string());
-        debug!("New conversation created: {}", new_uuid);
+        // Continue a previous conversation, only sending the new turns
+        let parent_message_uuid = self.resume_turns(&mut p, &org_uuid);
+        let conv_uuid = match self.conv_uuid {
+            Some(ref uuid) if parent_message_uuid.is_some() => uuid.to_owned(),
+            _ => {
+                self.new_conversation(&p, profile.as_ref(), &org_uuid)
+                    .await?
+            }
+        };
 
         // generate the request body
         // check if the request is empty
-        let mut body = self
-            .transform_request(p)
-            .ok_or(ClewdrError::BadRequest("Empty request".to_string()))?;
+        let mut body = self.transform_request(p)?;
+        body.parent_message_uuid = parent_message_uuid;
+        if let Some(ref profile) = profile
+            && let Some(style) = self.profile_style(profile, &org_uuid).await?
+        {
+            body.personalized_styles = vec![style];
+        }
 
         // check images
         let images = mem::take(&mut body.images);
 
-        // upload images
-        let files = self.upload_images(images).await;
+        // upload images and documents
+        let documents = mem::take(&mut body.documents);
+        let mut files = self.upload_images(images).await?;
+        files.extend(self.upload_documents(documents).await?);
         body.files = files;
 
         // send the request
         print_out_json(&body, "clewdr_req.json");
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}/completion",
-            self.endpoint, org_uuid, new_uuid
+            self.endpoint, org_uuid, conv_uuid
         );
 
         self.build_request(Method::POST, endpoint)
@@ -198,4 +324,52 @@ impl ClaudeState {
             .check_claude()
             .await
     }
+
+    /// Creates a new conversation on Claude.ai
+    /// Conversations of a profile with a project are created inside the project
+    ///
+    /// # Arguments
+    /// * `p` - The client request, used to configure thinking mode
+    /// * `profile` - The profile of the request, if any
+    /// * `org_uuid` - Organization to create the conversation in
+    ///
+    /// # Returns
+    /// * `Result<String, ClewdrError>` - UUID of the new conversation
+    async fn new_conversation(
+        &mut self,
+        p: &CreateMessageParams,
+        profile: Option<&Profile>,
+        org_uuid: &str,
+    ) -> Result<String, ClewdrError> {
+        let new_uuid = uuid::Uuid::new_v4().to_string();
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations",
+            self.endpoint, org_uuid
+        );
+        let mut body = json!({
+            "uuid": new_uuid,
+            "name": format!("ClewdR-{}", new_uuid),
+        });
+
+        if let Some(profile) = profile
+            && let Some(project) = self.profile_project(profile, org_uuid).await?
+        {
+            body["project_uuid"] = project.into();
+        }
+
+        // enable thinking mode
+        if p.thinking.as_ref().is_some_and(|t| t.is_enabled()) && self.is_pro() {
+            body["paprika_mode"] = "extended".into();
+            body["model"] = p.model.to_owned().into();
+        }
+        self.build_request(Method::POST, endpoint)
+            .json(&body)
+            .send()
+            .await?
+            .check_claude()
+            .await?;
+        self.conv_uuid = Some(new_uuid.to_string());
+        debug!("New conversation created: {}", new_uuid);
+        Ok(new_uuid)
+    }
 }
@@ -0,0 +1,211 @@
+use async_stream::stream;
+use bytes::Bytes;
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, pin_mut};
+use rquest::Method;
+use serde_json::Value;
+use tokio::spawn;
+use tracing::{debug, info, warn};
+
+use crate::{
+    claude_body::response::merge_sse,
+    config::CLEWDR_CONFIG,
+    error::{CheckClaudeErr, ClewdrError},
+    services::conversation::{CONVERSATIONS, ConversationState, conversation_key},
+    types::claude_message::{CreateMessageParams, Message, Role},
+};
+
+use super::ClaudeState;
+
+impl ClaudeState {
+    /// Requests a cookie for the given request
+    ///
+    /// If conversation reuse is enabled and the history of the request continues a
+    /// known claude.ai conversation, the cookie owning that conversation is requested.
+    /// Otherwise, or if that cookie is no longer valid, any cookie is requested.
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    pub async fn request_cookie_for(&mut self, p: &CreateMessageParams) -> Result<(), ClewdrError> {
+        self.resumed = None;
+        if CLEWDR_CONFIG.load().reuse_conversation
+            && let Some(conv) = find_conversation(p)
+        {
+            match self
+                .event_sender
+                .request_exact(conv.cookie.to_owned())
+                .await
+            {
+                Ok(cookie) => {
+                    info!("[CONV] continuing conversation: {}", conv.conv_uuid);
+                    self.resumed = Some(conv);
+                    return self.set_cookie(cookie);
+                }
+                Err(e) => {
+                    debug!("Conversation cookie unavailable: {}", e);
+                }
+            }
+        }
+        self.request_cookie().await
+    }
+
+    /// Trims the request to the turns not yet sent to the resumed conversation
+    ///
+    /// # Arguments
+    /// * `p` - The client request, trimmed in place
+    /// * `org_uuid` - Organization of the current cookie
+    ///
+    /// # Returns
+    /// * `Option<String>` - UUID of the parent message if the conversation is resumed
+    pub(super) fn resume_turns(
+        &mut self,
+        p: &mut CreateMessageParams,
+        org_uuid: &str,
+    ) -> Option<String> {
+        let conv = self.resumed.to_owned()?;
+        let last = p.messages.iter().rposition(|m| m.role == Role::Assistant);
+        let Some(last) = last.filter(|_| conv.org_uuid == org_uuid) else {
+            self.resumed = None;
+            return None;
+        };
+        if last + 1 >= p.messages.len() {
+            // nothing new to send
+            self.resumed = None;
+            return None;
+        }
+        p.messages.drain(..=last);
+        // system prompt is already part of the conversation
+        p.system = None;
+        self.conv_uuid = Some(conv.conv_uuid);
+        Some(conv.parent_message_uuid)
+    }
+
+    /// Wraps the response stream to record the conversation once it completes
+    ///
+    /// The stream is passed through unchanged. When conversation reuse is enabled,
+    /// the generated text is collected and the conversation is stored under the key
+    /// of the history including the new assistant message.
+    ///
+    /// # Arguments
+    /// * `input` - The response stream from Claude.ai
+    /// * `history` - The original client request
+    ///
+    /// # Returns
+    /// The same stream of bytes
+    pub fn track_conversation(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+        history: CreateMessageParams,
+    ) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+        let enabled = CLEWDR_CONFIG.load().reuse_conversation;
+        let state = self.to_owned();
+        stream! {
+            pin_mut!(input);
+            let mut chunks = vec![];
+            while let Some(chunk) = input.next().await {
+                if enabled && let Ok(ref c) = chunk {
+                    chunks.push(c.to_owned());
+                }
+                yield chunk;
+            }
+            if !enabled {
+                return;
+            }
+            let text = futures::stream::iter(chunks.into_iter().map(Ok::<_, rquest::Error>));
+            let text = merge_sse(text.eventsource()).await;
+            if let Err(e) = state.record_conversation(history, text).await {
+                warn!("Failed to record conversation: {}", e);
+            }
+        }
+    }
+
+    /// Deletes the conversations evicted from the reuse store
+    ///
+    /// Spawns a task deleting each evicted conversation on Claude.ai with the cookie
+    /// owning it, unless the conversation is still recorded under another history.
+    pub fn clean_evicted_conversations(&self) {
+        let Some(mut evicted) = CONVERSATIONS.take_evicted() else {
+            return;
+        };
+        let state = self.to_owned();
+        spawn(async move {
+            while let Some(conv) = evicted.recv().await {
+                let mut state = state.to_owned();
+                if let Err(e) = state.set_cookie(conv.cookie) {
+                    warn!("Failed to clean evicted chat: {}", e);
+                    continue;
+                }
+                state.org_uuid = Some(conv.org_uuid);
+                state.conv_uuid = Some(conv.conv_uuid);
+                if let Err(e) = state.clean_chat().await {
+                    warn!("Failed to clean evicted chat: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Stores the current conversation for reuse by the next turn
+    ///
+    /// # Arguments
+    /// * `history` - The original client request
+    /// * `text` - The text generated by the assistant
+    async fn record_conversation(
+        &self,
+        mut history: CreateMessageParams,
+        text: String,
+    ) -> Result<(), ClewdrError> {
+        let org_uuid = self
+            .org_uuid
+            .to_owned()
+            .ok_or(ClewdrError::UnexpectedNone)?;
+        let conv_uuid = self
+            .conv_uuid
+            .to_owned()
+            .ok_or(ClewdrError::UnexpectedNone)?;
+        let cookie = self.cookie.to_owned().ok_or(ClewdrError::UnexpectedNone)?;
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations/{}?tree=True&rendering_mode=messages",
+            self.endpoint, org_uuid, conv_uuid
+        );
+        let conv = self
+            .build_request(Method::GET, endpoint)
+            .send()
+            .await?
+            .check_claude()
+            .await?
+            .json::<Value>()
+            .await?;
+        let leaf = conv["current_leaf_message_uuid"]
+            .as_str()
+            .or_else(|| {
+                conv["chat_messages"]
+                    .as_array()
+                    .and_then(|m| m.last())
+                    .and_then(|m| m["uuid"].as_str())
+            })
+            .ok_or(ClewdrError::UnexpectedNone)?;
+        history
+            .messages
+            .push(Message::new_text(Role::Assistant, text));
+        let key = conversation_key(&history.model, history.system.as_ref(), &history.messages);
+        debug!("Recorded conversation {} for key: {}", conv_uuid, key);
+        CONVERSATIONS.insert(
+            key,
+            ConversationState {
+                cookie,
+                org_uuid,
+                conv_uuid,
+                parent_message_uuid: leaf.to_string(),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Finds the stored conversation continued by the request
+/// The history up to and including the last assistant message must match exactly
+fn find_conversation(p: &CreateMessageParams) -> Option<ConversationState> {
+    let last = p.messages.iter().rposition(|m| m.role == Role::Assistant)?;
+    let key = conversation_key(&p.model, p.system.as_ref(), &p.messages[..=last]);
+    CONVERSATIONS.get(key)
+}
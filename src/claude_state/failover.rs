@@ -0,0 +1,269 @@
+use async_stream::stream;
+use bytes::Bytes;
+use colored::Colorize;
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, stream::BoxStream};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use tracing::{error, warn};
+
+use crate::{
+    claude_body::prefill::{sse_bytes, trailing_assistant_text},
+    config::{CLEWDR_CONFIG, CookieStatus},
+    error::ClewdrError,
+    types::claude_message::{ContentBlock, CreateMessageParams, Message, MessageContent, Role},
+};
+
+use super::ClaudeState;
+
+type ByteStream = BoxStream<'static, Result<Bytes, rquest::Error>>;
+
+/// State of the stream sent to the client
+#[derive(Default)]
+struct StreamTracker {
+    /// Text generated so far
+    text: String,
+    /// Index and type of the block that is still open
+    open: Option<(usize, String)>,
+    /// Index of the next block sent to the client
+    next_index: usize,
+    /// Whether the message was completed
+    done: bool,
+    /// Last error event, sent if no continuation succeeds
+    error: Option<String>,
+}
+
+impl StreamTracker {
+    /// Records an event and rewrites it for the client
+    ///
+    /// Events of a continuation are spliced into the client stream:
+    /// the message start is dropped, the first text block continues the
+    /// open text block, and other blocks are renumbered.
+    ///
+    /// # Arguments
+    /// * `data` - Data of the event
+    /// * `map` - Block indices of the current response mapped to client indices
+    /// * `continuation` - Whether the event comes from a continuation
+    ///
+    /// # Returns
+    /// The data to send, None if the event is dropped
+    fn feed(
+        &mut self,
+        mut data: Value,
+        map: &mut HashMap<usize, usize>,
+        continuation: bool,
+    ) -> Option<Value> {
+        let index = data["index"].as_u64().map(|i| i as usize);
+        match data["type"].as_str() {
+            Some("message_start") if continuation => return None,
+            Some("message_stop") => self.done = true,
+            Some("error") => {
+                self.error = Some(data.to_string());
+                return None;
+            }
+            Some("content_block_start") => {
+                let index = index?;
+                let kind = data["content_block"]["type"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                if continuation
+                    && map.is_empty()
+                    && kind == "text"
+                    && let Some((open, ref open_kind)) = self.open
+                    && open_kind == "text"
+                {
+                    // continue the text block left open
+                    map.insert(index, open);
+                    return None;
+                }
+                map.insert(index, self.next_index);
+                data["index"] = self.next_index.into();
+                self.open = Some((self.next_index, kind));
+                self.next_index += 1;
+            }
+            Some("content_block_delta") => {
+                let index = *map.get(&index?)?;
+                data["index"] = index.into();
+                if data["delta"]["type"] == "text_delta" {
+                    self.text += data["delta"]["text"].as_str().unwrap_or_default();
+                }
+            }
+            Some("content_block_stop") => {
+                let index = *map.get(&index?)?;
+                data["index"] = index.into();
+                self.open = None;
+            }
+            _ => {}
+        }
+        Some(data)
+    }
+
+    /// Closes a block left open that cannot be continued
+    ///
+    /// # Returns
+    /// The stop event of the block, if any
+    fn close_open(&mut self) -> Option<Bytes> {
+        match self.open {
+            Some((_, ref kind)) if kind == "text" => None,
+            Some((index, _)) => {
+                self.open = None;
+                let data = json!({ "type": "content_block_stop", "index": index });
+                Some(sse_bytes("content_block_stop", &data.to_string()))
+            }
+            None => None,
+        }
+    }
+}
+
+/// Builds the request continuing a partial output
+///
+/// The partial output is appended to the trailing assistant message,
+/// or sent as a new assistant message, and continued as a prefill.
+fn continuation_request(mut p: CreateMessageParams, partial: &str) -> CreateMessageParams {
+    if partial.is_empty() {
+        return p;
+    }
+    match p.messages.last_mut() {
+        Some(m) if m.role == Role::Assistant => match m.content {
+            MessageContent::Text { ref mut content } => *content += partial,
+            MessageContent::Blocks { ref mut content } => content.push(ContentBlock::text(partial)),
+        },
+        _ => p
+            .messages
+            .push(Message::new_text(Role::Assistant, partial.to_string())),
+    }
+    p
+}
+
+impl ClaudeState {
+    /// Opens a stream continuing a partial output on another cookie
+    ///
+    /// The cookies that failed during the request are not used again, and
+    /// the partial output echoed by the continuation is stripped.
+    ///
+    /// # Arguments
+    /// * `p` - The request continuing the partial output
+    /// * `failed` - Cookies that failed during the request
+    async fn continue_stream(
+        &mut self,
+        p: CreateMessageParams,
+        failed: &HashSet<CookieStatus>,
+    ) -> Result<ByteStream, ClewdrError> {
+        self.cookie = None;
+        self.conv_uuid = None;
+        self.continuation = true;
+        // cookies are dispatched in turn, so each failed cookie comes up at most once
+        for _ in 0..=failed.len() {
+            self.request_cookie_for(&p).await?;
+            if self.cookie.as_ref().is_some_and(|c| failed.contains(c)) {
+                self.return_cookie(None).await;
+                self.cookie = None;
+                continue;
+            }
+            let partial = trailing_assistant_text(&p);
+            let stream = self.open_stream(p, partial, false).await?;
+            return Ok(stream.boxed());
+        }
+        Err(ClewdrError::NoCookieAvailable)
+    }
+}
+
+/// Continues a broken Claude.ai stream on another cookie
+///
+/// If the stream ends without `message_stop`, or reports an error event,
+/// a new cookie continues the generation from the partial output,
+/// and the continuation is spliced into the same client stream.
+/// At most `max_retries` continuations are attempted.
+///
+/// # Arguments
+/// * `state` - State of the original request
+/// * `p` - The client request
+/// * `prefill` - The assistant prefill of the request, if emulated
+/// * `input` - The stream of the original request, in Claude API events
+///
+/// # Returns
+/// A stream of SSE bytes for the client
+pub fn failover_stream(
+    mut state: ClaudeState,
+    p: CreateMessageParams,
+    prefill: Option<String>,
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+    let prepend = CLEWDR_CONFIG.load().prefill_prepend;
+    stream! {
+        let mut tracker = StreamTracker::default();
+        let mut input: ByteStream = input.boxed();
+        // whether a continuation is streaming
+        let mut continuation = false;
+        let mut attempts = 0;
+        // cookies that failed during this request
+        let mut failed = HashSet::new();
+        loop {
+            let mut map = HashMap::new();
+            let mut events = input.eventsource();
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Stream interrupted: {}", e);
+                        break;
+                    }
+                };
+                let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+                    yield Ok(sse_bytes(&event.event, &event.data));
+                    continue;
+                };
+                if let Some(data) = tracker.feed(data, &mut map, continuation) {
+                    yield Ok(sse_bytes(&event.event, &data.to_string()));
+                }
+                if tracker.error.is_some() {
+                    break;
+                }
+            }
+            if tracker.done || attempts >= CLEWDR_CONFIG.load().max_retries {
+                break;
+            }
+            attempts += 1;
+            warn!("[FAILOVER] stream ended early, continuing: {}", attempts.to_string().yellow());
+            if let Some(stop) = tracker.close_open() {
+                yield Ok(stop);
+            }
+            // the echoed prefill is part of the assistant message already
+            let generated = match prefill {
+                Some(ref prefill) if prepend => {
+                    tracker.text.strip_prefix(prefill.as_str()).unwrap_or(&tracker.text)
+                }
+                _ => tracker.text.as_str(),
+            };
+            let p = continuation_request(p.to_owned(), generated);
+            if let Some(ref cookie) = state.cookie {
+                failed.insert(cookie.to_owned());
+            }
+            let mut s = state.to_owned();
+            match s.continue_stream(p, &failed).await {
+                Ok(stream) => {
+                    tracker.error = None;
+                    input = stream;
+                    continuation = true;
+                    state = s;
+                }
+                Err(e) => {
+                    error!("Failed to continue stream: {}", e);
+                    if let Err(e) = s.clean_chat().await {
+                        warn!("Failed to clean chat: {}", e);
+                    }
+                    let reason = match e {
+                        ClewdrError::InvalidCookie(ref r) => Some(r.to_owned()),
+                        _ => None,
+                    };
+                    s.return_cookie(reason).await;
+                    break;
+                }
+            }
+        }
+        if !tracker.done && let Some(error) = tracker.error {
+            yield Ok(sse_bytes("error", &error));
+        }
+    }
+}
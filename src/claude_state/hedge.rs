@@ -0,0 +1,231 @@
+use bytes::Bytes;
+use colored::Colorize;
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{select, spawn, sync::oneshot, time::timeout};
+use tracing::{info, warn};
+
+use crate::{
+    config::CLEWDR_CONFIG, error::ClewdrError, types::claude_message::CreateMessageParams,
+};
+
+use super::ClaudeState;
+
+type ByteStream = BoxStream<'static, Result<Bytes, rquest::Error>>;
+
+/// Events marking the first token of a stream, in the messages and the raw completion format
+const TOKEN_EVENTS: [&[u8]; 2] = [b"content_block_delta", b"event: completion"];
+
+/// Length of the longest token event
+const TOKEN_EVENT_LEN: usize = 19;
+
+/// Number of hedged requests in flight
+static HEDGED: AtomicUsize = AtomicUsize::new(0);
+
+/// A slot of the global cap on hedged requests, released on drop
+struct HedgeSlot;
+
+impl HedgeSlot {
+    /// Takes a slot if fewer than `max_hedged_requests` are in flight
+    fn acquire() -> Option<Self> {
+        let max = CLEWDR_CONFIG.load().max_hedged_requests;
+        HEDGED
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| HedgeSlot)
+    }
+}
+
+impl Drop for HedgeSlot {
+    fn drop(&mut self) {
+        HEDGED.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads a stream until its first token
+///
+/// # Returns
+/// The chunks read, up to the first token event or the end of the stream,
+/// and the rest of the stream
+async fn first_token(mut input: ByteStream) -> (Vec<Result<Bytes, rquest::Error>>, ByteStream) {
+    let mut head = vec![];
+    // the event name may be split across chunks
+    let mut tail = vec![];
+    while let Some(chunk) = input.next().await {
+        let token = match chunk {
+            Ok(ref bytes) => {
+                tail.extend_from_slice(bytes);
+                let found = TOKEN_EVENTS
+                    .iter()
+                    .any(|e| tail.windows(e.len()).any(|w| w == *e));
+                tail.drain(..tail.len().saturating_sub(TOKEN_EVENT_LEN));
+                found
+            }
+            Err(_) => true,
+        };
+        head.push(chunk);
+        if token {
+            break;
+        }
+    }
+    (head, input)
+}
+
+/// Puts read chunks back in front of the rest of their stream
+fn rejoin((head, rest): (Vec<Result<Bytes, rquest::Error>>, ByteStream)) -> ByteStream {
+    stream::iter(head).chain(rest).boxed()
+}
+
+impl ClaudeState {
+    /// Whether requests for the model are hedged
+    pub fn hedges(&self, model: &str) -> bool {
+        let config = CLEWDR_CONFIG.load();
+        self.key.is_none()
+            && config.max_hedged_requests > 0
+            && config
+                .hedge_models
+                .iter()
+                .any(|m| model.starts_with(m.as_str()))
+    }
+
+    /// Races the request on a second cookie if the first is slow to respond
+    ///
+    /// If the stream produces no token within `hedge_delay_ms`, the same request
+    /// is sent with another cookie, and whichever stream produces a token first
+    /// is returned. The other is dropped, which stops its generation and cleans
+    /// up its chat.
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    /// * `prefill` - The assistant prefill whose echo is stripped, if any
+    /// * `prepend` - Whether the prefill is prepended to the output
+    /// * `primary` - The stream of the request on the current cookie
+    ///
+    /// # Returns
+    /// The stream of the winner, and the state of the second cookie if it won
+    pub async fn hedge(
+        &self,
+        p: CreateMessageParams,
+        prefill: Option<String>,
+        prepend: bool,
+        primary: ByteStream,
+    ) -> (ByteStream, Option<ClaudeState>) {
+        let delay = Duration::from_millis(CLEWDR_CONFIG.load().hedge_delay_ms);
+        let mut primary = Box::pin(first_token(primary));
+        let primary = match timeout(delay, &mut primary).await {
+            Ok(res) => return (rejoin(res), None),
+            Err(_) => primary,
+        };
+        let Some(slot) = HedgeSlot::acquire() else {
+            return (rejoin(primary.await), None);
+        };
+        info!(
+            "[HEDGE] no token after {}ms, racing another cookie",
+            delay.as_millis().to_string().yellow()
+        );
+
+        // the secondary request outlives the race, so a loser cleans up after itself
+        let (mut tx, rx) = oneshot::channel();
+        let mut state = self.to_owned();
+        let current = self.cookie.to_owned();
+        spawn(async move {
+            // the slot is held until the race is decided
+            let _slot = slot;
+            let res = async {
+                state.request_cookie_for(&p).await?;
+                if state.cookie == current {
+                    state.return_cookie(None).await;
+                    return Err(ClewdrError::NoCookieAvailable);
+                }
+                match state.open_stream(p, prefill, prepend).await {
+                    Ok(stream) => Ok(stream.boxed()),
+                    Err(e) => {
+                        if let Err(e) = state.clean_chat().await {
+                            warn!("Failed to clean chat: {}", e);
+                        }
+                        let reason = match e {
+                            ClewdrError::InvalidCookie(ref r) => Some(r.to_owned()),
+                            _ => None,
+                        };
+                        state.return_cookie(reason).await;
+                        Err(e)
+                    }
+                }
+            }
+            .await;
+            let res = match res {
+                Ok(stream) => select! {
+                    res = first_token(stream) => Ok((res, state)),
+                    // the primary won, dropping the stream stops the generation
+                    // and its guard cleans up the chat and returns the cookie
+                    _ = tx.closed() => return,
+                },
+                Err(e) => Err(e),
+            };
+            // dropping an unsent stream cancels it
+            let _ = tx.send(res);
+        });
+
+        let mut primary = primary;
+        // dropping the receiver when the primary wins cancels the secondary
+        select! {
+            res = &mut primary => (rejoin(res), None),
+            res = rx => match res {
+                Ok(Ok((res, state))) => {
+                    info!("[HEDGE] {}", "second cookie won".green());
+                    drop(primary);
+                    (rejoin(res), Some(state))
+                }
+                Ok(Err(e)) => {
+                    warn!("[HEDGE] second request failed: {}", e);
+                    (rejoin(primary.await), None)
+                }
+                Err(_) => (rejoin(primary.await), None),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(chunks: Vec<&'static str>) -> ByteStream {
+        stream::iter(
+            chunks
+                .into_iter()
+                .map(|c| Ok(Bytes::from_static(c.as_bytes()))),
+        )
+        .boxed()
+    }
+
+    #[tokio::test]
+    async fn stops_at_first_token_event() {
+        let (head, rest) = first_token(chunks(vec![
+            "event: message_start\ndata: {}\n\n",
+            "event: content_block_del",
+            "ta\ndata: {}\n\n",
+            "event: message_stop\ndata: {}\n\n",
+        ]))
+        .await;
+        assert_eq!(head.len(), 3);
+        assert_eq!(rest.count().await, 1);
+
+        let (head, rest) = first_token(chunks(vec![
+            "event: comp",
+            "letion\ndata: {\"completion\":\"Hi\"}\n\n",
+            "event: completion\ndata: {}\n\n",
+        ]))
+        .await;
+        assert_eq!(head.len(), 2);
+        assert_eq!(rest.count().await, 1);
+    }
+}
@@ -0,0 +1,266 @@
+use async_stream::stream;
+use bytes::Bytes;
+use colored::Colorize;
+use futures::{Stream, StreamExt, pin_mut};
+use rquest::Method;
+use serde_json::Value;
+use std::fmt::Display;
+use tokio::spawn;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::Reason,
+    error::{CheckClaudeErr, ClewdrError},
+};
+
+use super::ClaudeState;
+
+/// How a Claude.ai completion ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamOutcome {
+    /// The completion was streamed to `message_stop`
+    Completed,
+    /// The upstream stream broke or reported an error
+    Failed(Option<Reason>),
+    /// The stream was dropped before the end, e.g. the client disconnected
+    Cancelled,
+}
+
+impl StreamOutcome {
+    /// Reason the cookie is returned with
+    pub fn reason(&self) -> Option<Reason> {
+        match self {
+            StreamOutcome::Failed(reason) => reason.to_owned(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StreamOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamOutcome::Completed => write!(f, "{}", "Completed".green()),
+            StreamOutcome::Failed(Some(reason)) => write!(f, "{}: {}", "Failed".red(), reason),
+            StreamOutcome::Failed(None) => write!(f, "{}", "Failed".red()),
+            StreamOutcome::Cancelled => write!(f, "{}", "Cancelled".yellow()),
+        }
+    }
+}
+
+/// Finishes the completion when dropped
+///
+/// Dropping happens when the stream ends, fails, or is dropped by the client,
+/// so the chat is cleaned up only once the upstream is done with it.
+struct Lifecycle {
+    state: Option<ClaudeState>,
+    outcome: StreamOutcome,
+    /// Whether `message_stop`, or a raw completion with a stop reason, was received
+    stopped: bool,
+    /// Whether the upstream stream reached its end
+    ended: bool,
+}
+
+impl Lifecycle {
+    /// Records an SSE event of the upstream stream
+    ///
+    /// # Arguments
+    /// * `event` - Name of the event
+    /// * `data` - Data of the event
+    fn record(&mut self, event: &str, data: &str) {
+        match event {
+            "message_stop" => self.stopped = true,
+            // raw rendering ends with a completion carrying the stop reason
+            "completion" => {
+                let data = serde_json::from_str::<Value>(data).unwrap_or_default();
+                if !data["stop_reason"].is_null() {
+                    self.stopped = true;
+                }
+            }
+            "error" => {
+                let data = serde_json::from_str(data).unwrap_or_default();
+                self.outcome = error_outcome(&data);
+            }
+            _ => {}
+        }
+    }
+
+    /// Records a broken upstream stream
+    fn fail(&mut self) {
+        self.outcome = StreamOutcome::Failed(None);
+    }
+
+    /// Records the end of the upstream stream
+    fn end(&mut self) {
+        self.ended = true;
+    }
+}
+
+impl Drop for Lifecycle {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let outcome = match self.outcome {
+            StreamOutcome::Cancelled if self.stopped => StreamOutcome::Completed,
+            // a stream ending without message_stop was cut off
+            StreamOutcome::Cancelled if self.ended => StreamOutcome::Failed(None),
+            ref outcome => outcome.to_owned(),
+        };
+        spawn(async move { state.finish(outcome).await });
+    }
+}
+
+/// Reads the outcome of an error event of Claude.ai
+///
+/// Rate limits reported mid-stream carry the reset time in the message,
+/// as a JSON string or an object.
+fn error_outcome(data: &Value) -> StreamOutcome {
+    let message = &data["error"]["message"];
+    let message = match message.as_str() {
+        Some(s) => serde_json::from_str::<Value>(s).unwrap_or_default(),
+        None => message.to_owned(),
+    };
+    match message["resetsAt"].as_i64() {
+        Some(time) => StreamOutcome::Failed(Some(Reason::TooManyRequest(time))),
+        None => StreamOutcome::Failed(None),
+    }
+}
+
+impl ClaudeState {
+    /// Ties the cleanup of the current chat to the lifetime of its stream
+    ///
+    /// When the stream ends, the chat is deleted and the cookie returned with
+    /// the outcome. If the stream is dropped early, the upstream request is
+    /// cancelled and Claude.ai is asked to stop the generation first.
+    ///
+    /// # Arguments
+    /// * `input` - The completion stream of Claude.ai
+    ///
+    /// # Returns
+    /// The same stream of bytes
+    pub fn guard_stream(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    ) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+        let mut guard = Lifecycle {
+            state: Some(self.to_owned()),
+            outcome: StreamOutcome::Cancelled,
+            stopped: false,
+            ended: false,
+        };
+        stream! {
+            pin_mut!(input);
+            // incomplete line of the previous chunk
+            let mut line = String::new();
+            let mut event = String::new();
+            while let Some(chunk) = input.next().await {
+                let Ok(ref bytes) = chunk else {
+                    guard.fail();
+                    yield chunk;
+                    break;
+                };
+                line += &String::from_utf8_lossy(bytes);
+                while let Some(end) = line.find('\n') {
+                    let l = line.drain(..=end).collect::<String>();
+                    let l = l.trim_end();
+                    if let Some(e) = l.strip_prefix("event:") {
+                        event = e.trim().to_string();
+                    } else if let Some(data) = l.strip_prefix("data:") {
+                        guard.record(&event, data.trim());
+                    }
+                }
+                yield chunk;
+            }
+            guard.end();
+        }
+    }
+
+    /// Cleans up after a completion and returns the cookie with its outcome
+    ///
+    /// # Arguments
+    /// * `outcome` - How the completion ended
+    async fn finish(&self, outcome: StreamOutcome) {
+        if let Some(ref cookie) = self.cookie {
+            info!("[{}] {}", cookie.cookie.ellipse().green(), outcome);
+        }
+        if outcome == StreamOutcome::Cancelled
+            && let Err(e) = self.stop_generation().await
+        {
+            warn!("Failed to stop generation: {}", e);
+        }
+        if let Err(e) = self.clean_chat().await {
+            warn!("Failed to clean chat: {}", e);
+        }
+        self.return_cookie(outcome.reason()).await;
+    }
+
+    /// Asks Claude.ai to stop generating in the current chat
+    async fn stop_generation(&self) -> Result<(), ClewdrError> {
+        let (Some(org_uuid), Some(conv_uuid)) = (&self.org_uuid, &self.conv_uuid) else {
+            return Ok(());
+        };
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations/{}/stop_response",
+            self.endpoint, org_uuid, conv_uuid
+        );
+        debug!("Stopping generation: {}", conv_uuid);
+        self.build_request(Method::POST, endpoint)
+            .send()
+            .await?
+            .check_claude()
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Lifecycle, StreamOutcome, error_outcome};
+    use crate::config::Reason;
+
+    #[test]
+    fn reads_reset_time_from_json_string() {
+        let message = json!({ "type": "exceeded_limit", "resetsAt": 1700000000 }).to_string();
+        let data =
+            json!({ "type": "error", "error": { "type": "rate_limit_error", "message": message } });
+        assert_eq!(
+            error_outcome(&data),
+            StreamOutcome::Failed(Some(Reason::TooManyRequest(1700000000)))
+        );
+    }
+
+    #[test]
+    fn reads_reset_time_from_object() {
+        let data = json!({ "error": { "message": { "resetsAt": 1700000000 } } });
+        assert_eq!(
+            error_outcome(&data),
+            StreamOutcome::Failed(Some(Reason::TooManyRequest(1700000000)))
+        );
+    }
+
+    #[test]
+    fn fails_without_reason_otherwise() {
+        let data = json!({ "error": { "type": "overloaded_error", "message": "Overloaded" } });
+        assert_eq!(error_outcome(&data), StreamOutcome::Failed(None));
+        assert_eq!(error_outcome(&json!({})), StreamOutcome::Failed(None));
+    }
+
+    #[test]
+    fn raw_completion_with_stop_reason_stops() {
+        let mut lifecycle = Lifecycle {
+            state: None,
+            outcome: StreamOutcome::Cancelled,
+            stopped: false,
+            ended: false,
+        };
+        lifecycle.record("completion", r#"{"completion":"Hi","stop_reason":null}"#);
+        assert!(!lifecycle.stopped);
+        lifecycle.record(
+            "completion",
+            r#"{"completion":"","stop_reason":"end_turn"}"#,
+        );
+        assert!(lifecycle.stopped);
+    }
+}
@@ -8,16 +8,24 @@ use strum::Display;
 use tracing::{debug, error};
 use url::Url;
 
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
 use crate::{
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
+    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason, UserKey},
     error::ClewdrError,
-    services::cookie_manager::CookieEventSender,
+    services::{
+        conversation::{CONVERSATIONS, ConversationState},
+        cookie_manager::CookieEventSender,
+    },
 };
 
 pub mod bootstrap;
 pub mod chat;
+pub mod conversation;
+pub mod failover;
+pub mod hedge;
+pub mod lifecycle;
+pub mod project;
 /// Placeholder
 static SUPER_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
 
@@ -49,6 +57,12 @@ pub struct ClaudeState {
     pub stream: bool,
     pub client: Client,
     pub key: Option<(u64, usize)>,
+    pub resumed: Option<ConversationState>,
+    pub user_key: Option<UserKey>,
+    pub template: Option<PathBuf>,
+    pub continuation: bool,
+    pub timezone: String,
+    pub locale: Option<String>,
 }
 
 impl ClaudeState {
@@ -67,6 +81,12 @@ impl ClaudeState {
             stream: false,
             client: SUPER_CLIENT.to_owned(),
             key: None,
+            resumed: None,
+            user_key: None,
+            template: None,
+            continuation: false,
+            timezone: CLEWDR_CONFIG.load().timezone.to_owned(),
+            locale: CLEWDR_CONFIG.load().locale.to_owned(),
         }
     }
 
@@ -111,6 +131,19 @@ impl ClaudeState {
     /// Updates the internal state with the new cookie and proxy configuration
     pub async fn request_cookie(&mut self) -> Result<(), ClewdrError> {
         let res = self.event_sender.request().await?;
+        self.set_cookie(res)
+    }
+
+    /// Requests the given cookie again, if it is still valid
+    /// Used to retry a request on the same cookie
+    pub async fn request_exact_cookie(&mut self, cookie: CookieStatus) -> Result<(), ClewdrError> {
+        let res = self.event_sender.request_exact(cookie).await?;
+        self.set_cookie(res)
+    }
+
+    /// Uses the given cookie for the following requests
+    /// Builds a new client with the cookie and the newest proxy configuration
+    fn set_cookie(&mut self, res: CookieStatus) -> Result<(), ClewdrError> {
         self.cookie = Some(res.to_owned());
         let mut client = ClientBuilder::new()
             .cookie_store(true)
@@ -142,6 +175,7 @@ impl ClaudeState {
 
     /// Deletes or renames the current chat conversation based on configuration
     /// If preserve_chats is true, the chat is renamed rather than deleted
+    /// If the chat is recorded for reuse, it is kept for the next turn
     pub async fn clean_chat(&self) -> Result<(), ClewdrError> {
         if CLEWDR_CONFIG.load().preserve_chats {
             return Ok(());
@@ -152,6 +186,9 @@ impl ClaudeState {
         let Some(ref conv_uuid) = self.conv_uuid else {
             return Ok(());
         };
+        if CONVERSATIONS.contains(conv_uuid) {
+            return Ok(());
+        }
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}",
             self.endpoint, org_uuid, conv_uuid
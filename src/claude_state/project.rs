@@ -0,0 +1,232 @@
+use moka::sync::Cache;
+use rquest::Method;
+use serde_json::{Value, json};
+use std::{
+    path::PathBuf,
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    config::Profile,
+    error::{CheckClaudeErr, ClewdrError},
+};
+
+use super::ClaudeState;
+
+/// How long the styles of an account are kept
+const STYLE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Projects provisioned for profiles, keyed by organization and project name
+static PROJECTS: LazyLock<Cache<(String, String), String>> = LazyLock::new(|| Cache::new(1024));
+
+/// Knowledge files of a profile as of their modification times
+type KnowledgeKey = (String, Option<String>, Vec<(PathBuf, SystemTime)>);
+
+/// Project names of the knowledge read from disk, so files are only read again once modified
+static KNOWLEDGE: LazyLock<Cache<KnowledgeKey, String>> = LazyLock::new(|| Cache::new(256));
+
+/// Styles listed by each organization
+static STYLES: LazyLock<Cache<String, Vec<Value>>> =
+    LazyLock::new(|| Cache::builder().time_to_live(STYLE_TTL).build());
+
+/// Serializes provisioning, so concurrent requests share one project
+static PROVISION_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+impl ClaudeState {
+    /// Resolves the project to create conversations of a profile in
+    ///
+    /// # Arguments
+    /// * `profile` - The profile of the request
+    /// * `org_uuid` - Organization of the current cookie
+    ///
+    /// # Returns
+    /// * `Result<Option<String>, ClewdrError>` - UUID of the project, None if the profile has none
+    pub async fn profile_project(
+        &self,
+        profile: &Profile,
+        org_uuid: &str,
+    ) -> Result<Option<String>, ClewdrError> {
+        if let Some(ref uuid) = profile.project_uuid {
+            return Ok(Some(uuid.to_owned()));
+        }
+        if !profile.provisions() {
+            return Ok(None);
+        }
+        let knowledge_key = (
+            profile.name.to_owned(),
+            profile.instructions.to_owned(),
+            profile
+                .knowledge
+                .iter()
+                .cloned()
+                .zip(profile.knowledge_mtimes()?)
+                .collect::<Vec<_>>(),
+        );
+        let mut knowledge = None;
+        let name = match KNOWLEDGE.get(&knowledge_key) {
+            Some(name) => name,
+            None => {
+                let (name, files) = profile.read_knowledge()?;
+                KNOWLEDGE.insert(knowledge_key, name.to_owned());
+                knowledge = Some(files);
+                name
+            }
+        };
+        let key = (org_uuid.to_string(), name.to_owned());
+        if let Some(uuid) = PROJECTS.get(&key) {
+            return Ok(Some(uuid));
+        }
+        let _lock = PROVISION_LOCK.lock().await;
+        if let Some(uuid) = PROJECTS.get(&key) {
+            return Ok(Some(uuid));
+        }
+
+        let endpoint = format!("{}/api/organizations/{}/projects", self.endpoint, org_uuid);
+        let projects = self
+            .build_request(Method::GET, &endpoint)
+            .send()
+            .await?
+            .check_claude()
+            .await?
+            .json::<Vec<Value>>()
+            .await?;
+        let mut found = None;
+        for project in projects.iter() {
+            let (Some(uuid), Some(n)) = (project["uuid"].as_str(), project["name"].as_str()) else {
+                continue;
+            };
+            if n == name {
+                found = Some(uuid.to_string());
+            } else if profile.is_project(n) {
+                // the knowledge has changed since this project was provisioned
+                self.delete_project(org_uuid, uuid).await;
+            }
+        }
+        let uuid = match found {
+            Some(uuid) => uuid,
+            None => {
+                let uuid = self
+                    .build_request(Method::POST, &endpoint)
+                    .json(&json!({
+                        "name": name,
+                        "description": format!("Knowledge of the ClewdR profile {}", profile.name),
+                        "is_private": true,
+                    }))
+                    .send()
+                    .await?
+                    .check_claude()
+                    .await?
+                    .json::<Value>()
+                    .await?["uuid"]
+                    .as_str()
+                    .ok_or(ClewdrError::UnexpectedNone)?
+                    .to_string();
+                let files = match knowledge {
+                    Some(files) => files,
+                    None => profile.read_knowledge()?.1,
+                };
+                let project = format!("{}/{}", endpoint, uuid);
+                let filled = async {
+                    if let Some(ref instructions) = profile.instructions {
+                        self.build_request(Method::PUT, &project)
+                            .json(&json!({ "prompt_template": instructions }))
+                            .send()
+                            .await?
+                            .check_claude()
+                            .await?;
+                    }
+                    for file in files {
+                        self.build_request(Method::POST, format!("{}/docs", project))
+                            .json(&json!({
+                                "file_name": file.file_name,
+                                "content": file.content,
+                            }))
+                            .send()
+                            .await?
+                            .check_claude()
+                            .await?;
+                    }
+                    Ok::<_, ClewdrError>(())
+                }
+                .await;
+                if let Err(e) = filled {
+                    // a half filled project would be reused under its final name
+                    self.delete_project(org_uuid, &uuid).await;
+                    return Err(e);
+                }
+                info!("Provisioned project {} for profile {}", uuid, profile.name);
+                uuid
+            }
+        };
+        PROJECTS.insert(key, uuid.to_owned());
+        Ok(Some(uuid))
+    }
+
+    /// Deletes a provisioned project that is out of date
+    async fn delete_project(&self, org_uuid: &str, uuid: &str) {
+        let endpoint = format!(
+            "{}/api/organizations/{}/projects/{}",
+            self.endpoint, org_uuid, uuid
+        );
+        let res = match self.build_request(Method::DELETE, endpoint).send().await {
+            Ok(res) => res.check_claude().await.map(|_| ()),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            warn!("Failed to delete project {}: {}", uuid, e);
+        }
+    }
+
+    /// Finds the style of a profile among the styles of the account
+    ///
+    /// # Arguments
+    /// * `profile` - The profile of the request
+    /// * `org_uuid` - Organization of the current cookie
+    ///
+    /// # Returns
+    /// * `Result<Option<Value>, ClewdrError>` - The style as listed by Claude.ai, if found
+    pub async fn profile_style(
+        &self,
+        profile: &Profile,
+        org_uuid: &str,
+    ) -> Result<Option<Value>, ClewdrError> {
+        let Some(ref style) = profile.style else {
+            return Ok(None);
+        };
+        let styles = match STYLES.get(org_uuid) {
+            Some(styles) => styles,
+            None => {
+                let endpoint = format!(
+                    "{}/api/organizations/{}/list_styles",
+                    self.endpoint, org_uuid
+                );
+                let res = self
+                    .build_request(Method::GET, endpoint)
+                    .send()
+                    .await?
+                    .check_claude()
+                    .await?
+                    .json::<Value>()
+                    .await?;
+                let styles = ["defaultStyles", "customStyles"]
+                    .iter()
+                    .filter_map(|k| res[k].as_array())
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                STYLES.insert(org_uuid.to_string(), styles.to_owned());
+                styles
+            }
+        };
+        let found = styles
+            .into_iter()
+            .find(|s| s["key"].as_str() == Some(style) || s["name"].as_str() == Some(style));
+        if found.is_none() {
+            warn!("Style {} not found for profile {}", style, profile.name);
+        }
+        Ok(found)
+    }
+}
@@ -1,4 +1,5 @@
 use axum::http::{Uri, uri::Scheme};
+use chrono_tz::Tz;
 use colored::Colorize;
 use figment::{
     Figment,
@@ -8,7 +9,7 @@ use passwords::PasswordGenerator;
 use rquest::{Proxy, Url};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::{Debug, Display},
     net::{IpAddr, SocketAddr},
     path::PathBuf,
@@ -19,8 +20,13 @@ use tracing::{error, warn};
 
 use crate::{
     config::{
-        CONFIG_NAME, CookieStatus, UselessCookie, default_check_update, default_ip,
-        default_max_retries, default_padtxt_len, default_port, default_skip_cool_down,
+        ANTHROPIC_ENDPOINT, AnthropicKey, CONFIG_NAME, CookieStatus, ModelAlias, OpenAiProvider,
+        Profile, RegexRule, RetryPolicy, RuleScope, Upstream, UselessCookie, UserKey,
+        default_attachment_chunk_tokens, default_check_update, default_fetch_max_size,
+        default_fetch_timeout, default_hedge_delay_ms, default_image_max_dimension,
+        default_image_max_size, default_ip, default_max_hedged_requests, default_max_retries,
+        default_pad_separator, default_padtxt_len, default_port, default_regex_window,
+        default_skip_cool_down, default_structured_output_retries, default_timezone,
         default_use_real_roles,
     },
     error::ClewdrError,
@@ -52,6 +58,20 @@ fn generate_password() -> String {
     pg.generate_one().unwrap()
 }
 
+/// Whether the timezone is a zone of the IANA database, e.g. `Asia/Tokyo`
+fn is_timezone(tz: &str) -> bool {
+    tz.parse::<Tz>().is_ok()
+}
+
+/// Whether the locale looks like a BCP 47 language tag, e.g. `en-US` or `zh-Hans-CN`
+fn is_locale(locale: &str) -> bool {
+    let mut parts = locale.split(['-', '_']);
+    parts
+        .next()
+        .is_some_and(|l| (2..=3).contains(&l.len()) && l.chars().all(|c| c.is_ascii_alphabetic()))
+        && parts.all(|p| (2..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct VertexConfig {
     pub refresh_token: Option<String>,
@@ -72,6 +92,32 @@ impl VertexConfig {
     }
 }
 
+/// Where the merged transcript is placed in the Claude.ai request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentMode {
+    /// Everything is sent in the prompt field
+    Prompt,
+    /// The transcript is sent as a single `paste.txt` attachment
+    #[default]
+    Paste,
+    /// The transcript is split into multiple attachments by token count
+    Chunked,
+}
+
+/// Where the padding text is placed in the Claude.ai request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PadPlacement {
+    /// Before the system prompt, at the start of the transcript
+    #[default]
+    BeforeSystem,
+    /// Between the system prompt and the first message
+    AfterSystem,
+    /// As a separate `padding.txt` attachment
+    Attachment,
+}
+
 /// A struct representing the configuration of the application
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClewdrConfig {
@@ -84,6 +130,12 @@ pub struct ClewdrConfig {
     pub wasted_cookie: HashSet<UselessCookie>,
     #[serde(default)]
     pub gemini_keys: HashSet<KeyStatus>,
+    #[serde(default)]
+    pub anthropic_keys: HashSet<KeyStatus<AnthropicKey>>,
+    #[serde(default)]
+    pub openai_providers: Vec<OpenAiProvider>,
+    #[serde(default)]
+    pub user_keys: Vec<UserKey>,
 
     // Server settings, cannot hot reload
     #[serde(default = "default_ip")]
@@ -108,6 +160,8 @@ pub struct ClewdrConfig {
     pub proxy: Option<String>,
     #[serde(default)]
     pub rproxy: Option<Url>,
+    #[serde(default)]
+    pub anthropic_api_base: Option<Url>,
 
     // Api settings, can hot reload
     #[serde(default = "default_max_retries")]
@@ -117,7 +171,39 @@ pub struct ClewdrConfig {
     #[serde(default)]
     pub preserve_chats: bool,
     #[serde(default)]
+    pub reuse_conversation: bool,
+    #[serde(default)]
+    pub stream_failover: bool,
+    #[serde(default)]
+    pub hedge_models: Vec<String>,
+    #[serde(default = "default_hedge_delay_ms")]
+    pub hedge_delay_ms: u64,
+    #[serde(default = "default_max_hedged_requests")]
+    pub max_hedged_requests: usize,
+    #[serde(default)]
+    pub claude_retry: RetryPolicy,
+    #[serde(default)]
+    pub gemini_retry: RetryPolicy,
+    #[serde(default)]
+    pub anthropic_retry: RetryPolicy,
+    #[serde(default)]
+    pub openai_retry: RetryPolicy,
+    #[serde(default)]
+    pub model_upstreams: HashMap<String, Upstream>,
+    #[serde(default)]
     pub web_search: bool,
+    #[serde(default = "default_structured_output_retries")]
+    pub structured_output_retries: usize,
+    #[serde(default)]
+    pub fetch_allowlist: Vec<String>,
+    #[serde(default = "default_fetch_max_size")]
+    pub fetch_max_size: usize,
+    #[serde(default = "default_fetch_timeout")]
+    pub fetch_timeout: u64,
+    #[serde(default = "default_image_max_dimension")]
+    pub image_max_dimension: u32,
+    #[serde(default = "default_image_max_size")]
+    pub image_max_size: usize,
 
     // Cache settings, can hot reload
     #[serde(default)]
@@ -151,9 +237,49 @@ pub struct ClewdrConfig {
     #[serde(default)]
     pub custom_prompt: String,
     #[serde(default)]
+    pub attachment_mode: AttachmentMode,
+    #[serde(default = "default_attachment_chunk_tokens")]
+    pub attachment_chunk_tokens: usize,
+    #[serde(default)]
+    pub last_turn_in_prompt: bool,
+    #[serde(default)]
+    pub keep_thinking: bool,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub prefill: bool,
+    #[serde(default)]
+    pub prefill_prepend: bool,
+    #[serde(default)]
     pub padtxt_file: Option<PathBuf>,
     #[serde(default = "default_padtxt_len")]
     pub padtxt_len: usize,
+    #[serde(default)]
+    pub padtxt_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub pad_threshold: usize,
+    #[serde(default = "default_pad_separator")]
+    pub pad_separator: String,
+    #[serde(default)]
+    pub pad_seed: Option<u64>,
+    #[serde(default)]
+    pub pad_placement: PadPlacement,
+    #[serde(default)]
+    pub template_file: Option<PathBuf>,
+    #[serde(default)]
+    pub model_templates: HashMap<String, PathBuf>,
+    #[serde(default)]
+    pub regex_rules: Vec<RegexRule>,
+    #[serde(default = "default_regex_window")]
+    pub regex_window: usize,
+    #[serde(default)]
+    pub model_aliases: Vec<ModelAlias>,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+    #[serde(default)]
+    pub model_profiles: HashMap<String, String>,
 
     // Skip field, can hot reload
     #[serde(skip)]
@@ -173,23 +299,63 @@ impl Default for ClewdrConfig {
             cookie_array: HashSet::new(),
             wasted_cookie: HashSet::new(),
             gemini_keys: HashSet::new(),
+            anthropic_keys: HashSet::new(),
+            openai_providers: vec![],
+            user_keys: vec![],
             password: String::new(),
             admin_password: String::new(),
             proxy: None,
             ip: default_ip(),
             port: default_port(),
             rproxy: None,
+            anthropic_api_base: None,
             use_real_roles: default_use_real_roles(),
             custom_prompt: String::new(),
+            attachment_mode: AttachmentMode::default(),
+            attachment_chunk_tokens: default_attachment_chunk_tokens(),
+            last_turn_in_prompt: false,
+            keep_thinking: false,
+            timezone: default_timezone(),
+            locale: None,
+            prefill: false,
+            prefill_prepend: false,
             padtxt_file: None,
             padtxt_len: default_padtxt_len(),
+            padtxt_files: vec![],
+            pad_threshold: 0,
+            pad_separator: default_pad_separator(),
+            pad_seed: None,
+            pad_placement: PadPlacement::default(),
+            template_file: None,
+            model_templates: HashMap::new(),
+            regex_rules: vec![],
+            regex_window: default_regex_window(),
+            model_aliases: vec![],
+            profiles: vec![],
+            model_profiles: HashMap::new(),
             custom_h: None,
             custom_a: None,
             rquest_proxy: None,
             pad_tokens: Arc::new(vec![]),
             pass_params: false,
             preserve_chats: false,
+            reuse_conversation: false,
+            stream_failover: false,
+            hedge_models: vec![],
+            hedge_delay_ms: default_hedge_delay_ms(),
+            max_hedged_requests: default_max_hedged_requests(),
+            claude_retry: Default::default(),
+            gemini_retry: Default::default(),
+            anthropic_retry: Default::default(),
+            openai_retry: Default::default(),
+            model_upstreams: HashMap::new(),
             web_search: false,
+            structured_output_retries: default_structured_output_retries(),
+            fetch_allowlist: vec![],
+            fetch_max_size: default_fetch_max_size(),
+            fetch_timeout: default_fetch_timeout(),
+            image_max_dimension: default_image_max_dimension(),
+            image_max_size: default_image_max_size(),
             cache_response: 0,
             not_hash_system: false,
             not_hash_last_n: 0,
@@ -250,12 +416,24 @@ impl Display for ClewdrConfig {
         if let Some(ref rproxy) = self.rproxy {
             writeln!(f, "Reverse Proxy: {}", rproxy.to_string().blue())?;
         }
+        if let Some(ref base) = self.anthropic_api_base {
+            writeln!(f, "Anthropic API Base: {}", base.to_string().blue())?;
+        }
+        for provider in self.openai_providers.iter() {
+            writeln!(
+                f,
+                "OpenAI Provider {}: {}",
+                provider.name,
+                provider.base_url.to_string().blue()
+            )?;
+        }
         if !self.pad_tokens.is_empty() {
             writeln!(
                 f,
                 "Pad txt token count: {}",
                 self.pad_tokens.len().to_string().blue()
-            )?
+            )?;
+            writeln!(f, "Pad placement: {:?}", self.pad_placement)?;
         }
         writeln!(f, "Skip non Pro: {}", enabled(self.skip_non_pro))?;
         writeln!(f, "Skip restricted: {}", enabled(self.skip_restricted))?;
@@ -277,7 +455,153 @@ impl Display for ClewdrConfig {
 
 impl ClewdrConfig {
     pub fn user_auth(&self, key: &str) -> bool {
-        key == self.password
+        key == self.password || self.user_key(key).is_some()
+    }
+
+    /// Finds the user key matching the given API key
+    ///
+    /// # Arguments
+    /// * `key` - The API key of the request
+    ///
+    /// # Returns
+    /// * `Option<&UserKey>` - The user key with its settings, if any
+    pub fn user_key(&self, key: &str) -> Option<&UserKey> {
+        self.user_keys.iter().find(|k| k.key == key)
+    }
+
+    /// Collects the regex rules applying to a request, in order
+    ///
+    /// # Arguments
+    /// * `scope` - Either `Input` or `Output`
+    /// * `model` - Model of the request
+    ///
+    /// # Returns
+    /// * `Vec<RegexRule>` - The compiled rules to apply
+    pub fn regex_rules_for(&self, scope: RuleScope, model: &str) -> Vec<RegexRule> {
+        self.regex_rules
+            .iter()
+            .filter(|r| r.applies(scope, model))
+            .cloned()
+            .collect()
+    }
+
+    /// Resolves the alias of a requested model
+    /// Aliases are tried in order, the first match wins
+    ///
+    /// # Arguments
+    /// * `model` - The requested model
+    ///
+    /// # Returns
+    /// * `Option<(String, &ModelAlias)>` - The served model and the matching alias
+    pub fn model_alias(&self, model: &str) -> Option<(String, &ModelAlias)> {
+        self.model_aliases
+            .iter()
+            .find_map(|a| a.resolve(model).map(|m| (m, a)))
+    }
+
+    /// Selects the profile of a request
+    /// The profile of the key wins over the longest matching model prefix
+    ///
+    /// # Arguments
+    /// * `model` - Model of the request
+    /// * `key` - API key the request was authenticated with
+    ///
+    /// # Returns
+    /// * `Option<&Profile>` - The profile, None for a bare conversation
+    pub fn profile_for(&self, model: &str, key: Option<&UserKey>) -> Option<&Profile> {
+        let name = key.and_then(|k| k.profile.as_deref()).or_else(|| {
+            self.model_profiles
+                .iter()
+                .filter(|(m, _)| model.starts_with(m.as_str()))
+                .max_by_key(|(m, _)| m.len())
+                .map(|(_, p)| p.as_str())
+        })?;
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Selects the upstream of a request
+    /// The upstream of the key wins over a provider listing the model,
+    /// which wins over the longest matching model prefix,
+    /// an empty prefix matches every model.
+    /// Gemini models are served by Gemini unless configured otherwise
+    ///
+    /// # Arguments
+    /// * `model` - Model of the request
+    /// * `key` - API key the request was authenticated with
+    ///
+    /// # Returns
+    /// * `Upstream` - The upstream, cookies if none is configured
+    pub fn upstream_for(&self, model: &str, key: Option<&UserKey>) -> Upstream {
+        key.and_then(|k| k.upstream)
+            .or_else(|| {
+                self.openai_providers
+                    .iter()
+                    .any(|p| p.models.iter().any(|m| m == model))
+                    .then_some(Upstream::OpenAi)
+            })
+            .or_else(|| {
+                self.model_upstreams
+                    .iter()
+                    .filter(|(m, _)| model.starts_with(m.as_str()))
+                    .max_by_key(|(m, _)| m.len())
+                    .map(|(_, u)| *u)
+            })
+            .or_else(|| model.starts_with("gemini-").then_some(Upstream::Gemini))
+            .unwrap_or_default()
+    }
+
+    /// Finds the OpenAI compatible provider serving a model
+    /// A provider listing the model wins over the first provider without a model list
+    ///
+    /// # Arguments
+    /// * `model` - Model of the request
+    ///
+    /// # Returns
+    /// * `Result<&OpenAiProvider, ClewdrError>` - The provider, or an error if none serves the model
+    pub fn provider_for(&self, model: &str) -> Result<&OpenAiProvider, ClewdrError> {
+        self.openai_providers
+            .iter()
+            .find(|p| p.models.iter().any(|m| m == model))
+            .or_else(|| self.openai_providers.iter().find(|p| p.models.is_empty()))
+            .ok_or_else(|| {
+                ClewdrError::BadRequest(format!("No OpenAI compatible provider serves {}", model))
+            })
+    }
+
+    /// Resolves the timezone and locale of a request
+    /// Values of the request win over those of the key, which win over the global ones
+    ///
+    /// # Arguments
+    /// * `key` - API key the request was authenticated with
+    /// * `timezone` - Timezone requested by the client
+    /// * `locale` - Locale requested by the client
+    ///
+    /// # Returns
+    /// * `Result<(String, Option<String>), ClewdrError>` - The timezone and locale,
+    ///   or an error if the client requested an invalid one
+    pub fn request_locale(
+        &self,
+        key: Option<&UserKey>,
+        timezone: Option<&str>,
+        locale: Option<&str>,
+    ) -> Result<(String, Option<String>), ClewdrError> {
+        if let Some(tz) = timezone
+            && !is_timezone(tz)
+        {
+            return Err(ClewdrError::BadRequest(format!("Invalid timezone: {}", tz)));
+        }
+        if let Some(l) = locale
+            && !is_locale(l)
+        {
+            return Err(ClewdrError::BadRequest(format!("Invalid locale: {}", l)));
+        }
+        let timezone = timezone
+            .or(key.and_then(|k| k.timezone.as_deref()))
+            .unwrap_or(&self.timezone);
+        let locale = locale
+            .or(key.and_then(|k| k.locale.as_deref()))
+            .or(self.locale.as_deref());
+        Ok((timezone.to_string(), locale.map(str::to_string)))
     }
 
     pub fn admin_auth(&self, key: &str) -> bool {
@@ -336,20 +660,43 @@ impl ClewdrConfig {
         config
     }
 
-    /// Loads padding text from a file
+    /// Sources of padding text, the single file first
+    fn padtxt_sources(&self) -> impl Iterator<Item = &PathBuf> {
+        self.padtxt_file.iter().chain(self.padtxt_files.iter())
+    }
+
+    /// Loads padding text from files and directories
     /// Used to pad prompts with tokens to reach minimum token requirements
+    /// Directories contribute every file directly inside them, in name order
     ///
     /// # Effects
-    /// Updates the pad_tokens field with tokenized content from the file
+    /// Updates the pad_tokens field with tokenized content from the sources
     fn load_padtxt(&mut self) -> Result<(), ClewdrError> {
-        let Some(padtxt) = &self.padtxt_file else {
+        let mut files = vec![];
+        for source in self.padtxt_sources() {
+            if !source.exists() {
+                return Err(ClewdrError::PathNotFound(source.display().to_string()));
+            }
+            if source.is_dir() {
+                let mut entries = std::fs::read_dir(source)?
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                entries.retain(|p| p.is_file());
+                entries.sort();
+                files.extend(entries);
+            } else {
+                files.push(source.to_owned());
+            }
+        }
+        if files.is_empty() {
             self.pad_tokens = Default::default();
             return Ok(());
-        };
-        if !padtxt.exists() {
-            return Err(ClewdrError::PathNotFound(padtxt.display().to_string()));
         }
-        let padtxt_string = std::fs::read_to_string(padtxt.as_path())?;
+        let padtxt_string = files
+            .iter()
+            .map(std::fs::read_to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
 
         let bpe = o200k_base().unwrap();
         let ranks = bpe.encode_with_special_tokens(&padtxt_string);
@@ -359,8 +706,8 @@ impl ClewdrConfig {
             .collect::<Vec<_>>();
         if tokens.len() < 4096 {
             warn!(
-                "Pad txt file {} is too short, token count {}",
-                padtxt.display(),
+                "Pad txt from {} file(s) is too short, token count {}",
+                files.len(),
                 tokens.len()
             );
             return Err(ClewdrError::PadtxtTooShort);
@@ -381,6 +728,20 @@ impl ClewdrConfig {
         ENDPOINT_URL.to_owned()
     }
 
+    /// Gets the base URL of the Anthropic API
+    /// Returns the configured base URL if any, otherwise the official endpoint
+    ///
+    /// # Returns
+    /// The base URL, without trailing slash
+    pub fn anthropic_endpoint(&self) -> String {
+        self.anthropic_api_base
+            .as_ref()
+            .map(|u| u.as_str())
+            .unwrap_or(ANTHROPIC_ENDPOINT)
+            .trim_end_matches('/')
+            .to_string()
+    }
+
     /// address of proxy
     pub fn address(&self) -> SocketAddr {
         SocketAddr::new(self.ip, self.port)
@@ -417,11 +778,100 @@ impl ClewdrConfig {
                 })
                 .ok()
         });
+        if self.attachment_chunk_tokens == 0 {
+            error!("Invalid attachment_chunk_tokens: 0");
+            self.attachment_chunk_tokens = default_attachment_chunk_tokens();
+        }
+        if !is_timezone(&self.timezone) {
+            error!("Invalid timezone: {}", self.timezone);
+            self.timezone = default_timezone();
+        }
+        if let Some(ref l) = self.locale
+            && !is_locale(l)
+        {
+            error!("Invalid locale: {}", l);
+            self.locale = None;
+        }
+        for key in self.user_keys.iter_mut() {
+            if let Some(ref tz) = key.timezone
+                && !is_timezone(tz)
+            {
+                error!("Invalid timezone of key {}: {}", key.ellipse(), tz);
+                key.timezone = None;
+            }
+            if let Some(ref l) = key.locale
+                && !is_locale(l)
+            {
+                error!("Invalid locale of key {}: {}", key.ellipse(), l);
+                key.locale = None;
+            }
+        }
+        for alias in self.model_aliases.iter_mut() {
+            alias.compile().unwrap_or_else(|e| {
+                error!("Failed to compile model alias {}: {}", alias.pattern, e);
+            });
+        }
+        for rule in self.regex_rules.iter_mut() {
+            rule.compile().unwrap_or_else(|e| {
+                error!("Failed to compile regex rule {}: {}", rule.pattern, e);
+            });
+        }
         self.load_padtxt().unwrap_or_else(|e| {
             error!("Failed to load padtxt: {}", e);
             self.pad_tokens = Default::default();
             self.padtxt_file = None;
+            self.padtxt_files.clear();
         });
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_language_tags() {
+        assert!(is_locale("en"));
+        assert!(is_locale("en-US"));
+        assert!(is_locale("zh_Hans_CN"));
+        assert!(!is_locale(""));
+        assert!(!is_locale("english"));
+        assert!(!is_locale("en-"));
+        assert!(!is_locale("en-US;q=0.9"));
+    }
+
+    #[test]
+    fn request_wins_over_key_and_global() {
+        let config = ClewdrConfig {
+            timezone: "UTC".to_string(),
+            locale: Some("en-US".to_string()),
+            ..Default::default()
+        };
+        let key = UserKey {
+            timezone: Some("Asia/Tokyo".to_string()),
+            locale: Some("ja-JP".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.request_locale(None, None, None).unwrap(),
+            ("UTC".to_string(), Some("en-US".to_string()))
+        );
+        assert_eq!(
+            config.request_locale(Some(&key), None, None).unwrap(),
+            ("Asia/Tokyo".to_string(), Some("ja-JP".to_string()))
+        );
+        assert_eq!(
+            config
+                .request_locale(Some(&key), Some("Europe/Paris"), Some("fr-FR"))
+                .unwrap(),
+            ("Europe/Paris".to_string(), Some("fr-FR".to_string()))
+        );
+        assert!(
+            config
+                .request_locale(None, Some("Mars/Base"), None)
+                .is_err()
+        );
+        assert!(config.request_locale(None, None, Some("fr FR")).is_err());
+    }
+}
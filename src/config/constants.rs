@@ -13,6 +13,7 @@ use crate::{config::ClewdrConfig, utils::set_clewdr_dir};
 pub const CONFIG_NAME: &str = "clewdr.toml";
 pub const CLAUDE_ENDPOINT: &str = "https://claude.ai";
 pub const GEMINI_ENDPOINT: &str = "https://generativelanguage.googleapis.com";
+pub const ANTHROPIC_ENDPOINT: &str = "https://api.anthropic.com";
 pub static ENDPOINT_URL: LazyLock<Url> = LazyLock::new(|| {
     Url::parse(CLAUDE_ENDPOINT).unwrap_or_else(|_| {
         panic!("Failed to parse endpoint URL: {}", CLAUDE_ENDPOINT);
@@ -93,6 +94,55 @@ pub const fn default_use_real_roles() -> bool {
     true
 }
 
+/// Default number of retries when structured output fails validation,
+/// counted separately from `max_retries`
+///
+/// # Returns
+/// * `usize` - The default value of 2
+pub const fn default_structured_output_retries() -> usize {
+    2
+}
+
+/// Default maximum size of a remote file, in MiB
+///
+/// # Returns
+/// * `usize` - The default value of 20 MiB
+pub const fn default_fetch_max_size() -> usize {
+    20
+}
+
+/// Default timeout for fetching a remote file, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 15 seconds
+pub const fn default_fetch_timeout() -> u64 {
+    15
+}
+
+/// Default maximum width or height of an uploaded image, in pixels
+///
+/// # Returns
+/// * `u32` - The default value of 2048 pixels
+pub const fn default_image_max_dimension() -> u32 {
+    2048
+}
+
+/// Default maximum size of an uploaded image, in MiB
+///
+/// # Returns
+/// * `usize` - The default value of 5 MiB
+pub const fn default_image_max_size() -> usize {
+    5
+}
+
+/// Default maximum number of tokens per attachment in chunked mode
+///
+/// # Returns
+/// * `usize` - The default value of 30000 tokens
+pub const fn default_attachment_chunk_tokens() -> usize {
+    30000
+}
+
 /// Default length of padding text
 ///
 /// # Returns
@@ -101,6 +151,14 @@ pub const fn default_padtxt_len() -> usize {
     4000
 }
 
+/// Default separator between the padding text and the transcript
+///
+/// # Returns
+/// * `String` - A line of dashes after blank lines
+pub fn default_pad_separator() -> String {
+    "\n\n\n\n------------------------------------------------------------\n".to_string()
+}
+
 /// Default setting for checking updates on startup
 ///
 /// # Returns
@@ -116,5 +174,37 @@ pub const fn default_skip_cool_down() -> bool {
     true
 }
 
+/// Default delay before a slow request is hedged
+///
+/// # Returns
+/// * `u64` - The default value of 5000 milliseconds
+pub const fn default_hedge_delay_ms() -> u64 {
+    5000
+}
+
+/// Default cap on hedged requests in flight
+///
+/// # Returns
+/// * `usize` - The default value of 2
+pub const fn default_max_hedged_requests() -> usize {
+    2
+}
+
+/// Default number of bytes of streamed text held back for output regex rules
+///
+/// # Returns
+/// * `usize` - The default value of 1024 bytes
+pub const fn default_regex_window() -> usize {
+    1024
+}
+
+/// Default timezone sent to Claude.ai
+///
+/// # Returns
+/// * `String` - The default value of "America/New_York"
+pub fn default_timezone() -> String {
+    "America/New_York".to_string()
+}
+
 /// Default cookie value for testing purposes
 pub const PLACEHOLDER_COOKIE: &str = "sk-ant-REDACTED";
@@ -1,7 +1,14 @@
-use serde::{Deserialize, Serialize};
-use std::{fmt::Display, ops::Deref};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Display},
+    hash::Hash,
+    ops::Deref,
+};
 use tracing::warn;
 
+use crate::config::ClewdrConfig;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(from = "String")]
 #[serde(into = "String")]
@@ -64,13 +71,136 @@ impl From<GeminiKey> for String {
     }
 }
 
+/// An Anthropic API key
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String")]
+#[serde(into = "String")]
+pub struct AnthropicKey {
+    pub inner: String,
+}
+
+impl Deref for AnthropicKey {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl AnthropicKey {
+    pub fn validate(&self) -> bool {
+        let re = regex::Regex::new(r"^sk-ant-[A-Za-z0-9_-]{20,}$").unwrap();
+        re.is_match(&self.inner)
+    }
+    pub fn ellipse(&self) -> String {
+        let len = self.inner.len();
+        if len > 16 {
+            format!("{}...", &self.inner[..16])
+        } else {
+            self.inner.to_owned()
+        }
+    }
+}
+
+impl Display for AnthropicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl<S> From<S> for AnthropicKey
+where
+    S: AsRef<str>,
+{
+    /// Create a new key from a string
+    fn from(original: S) -> Self {
+        let original = original.as_ref();
+        // only keep '_' '-' and alphanumeric characters
+        let original = original
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+            .collect::<String>();
+        let key = Self { inner: original };
+        if !key.validate() {
+            warn!("Invalid Anthropic key format: {}", key.ellipse());
+        }
+        key
+    }
+}
+impl From<AnthropicKey> for String {
+    /// Convert the key to a string
+    fn from(key: AnthropicKey) -> Self {
+        key.to_string()
+    }
+}
+
+/// A key kept in a pool by the key manager
+pub trait PoolKey:
+    Debug + Display + Clone + Eq + Hash + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    /// Name of the pool in logs
+    const POOL: &'static str;
+
+    /// Whether the key is well formed
+    fn validate(&self) -> bool;
+
+    /// Shortened key for logging
+    fn ellipse(&self) -> String;
+
+    /// Keys of the pool saved in the config
+    fn pool(config: &ClewdrConfig) -> &HashSet<KeyStatus<Self>>;
+
+    /// Mutable keys of the pool saved in the config
+    fn pool_mut(config: &mut ClewdrConfig) -> &mut HashSet<KeyStatus<Self>>;
+}
+
+impl PoolKey for GeminiKey {
+    const POOL: &'static str = "Gemini";
+
+    fn validate(&self) -> bool {
+        GeminiKey::validate(self)
+    }
+
+    fn ellipse(&self) -> String {
+        GeminiKey::ellipse(self)
+    }
+
+    fn pool(config: &ClewdrConfig) -> &HashSet<KeyStatus<Self>> {
+        &config.gemini_keys
+    }
+
+    fn pool_mut(config: &mut ClewdrConfig) -> &mut HashSet<KeyStatus<Self>> {
+        &mut config.gemini_keys
+    }
+}
+
+impl PoolKey for AnthropicKey {
+    const POOL: &'static str = "Anthropic";
+
+    fn validate(&self) -> bool {
+        AnthropicKey::validate(self)
+    }
+
+    fn ellipse(&self) -> String {
+        AnthropicKey::ellipse(self)
+    }
+
+    fn pool(config: &ClewdrConfig) -> &HashSet<KeyStatus<Self>> {
+        &config.anthropic_keys
+    }
+
+    fn pool_mut(config: &mut ClewdrConfig) -> &mut HashSet<KeyStatus<Self>> {
+        &mut config.anthropic_keys
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
-pub struct KeyStatus {
-    pub key: GeminiKey,
+pub struct KeyStatus<K = GeminiKey> {
+    pub key: K,
     // TODO: add more fields
 }
 
-impl KeyStatus {
+impl<K: PoolKey> KeyStatus<K> {
     pub fn validate(&self) -> bool {
         self.key.validate()
     }
@@ -4,9 +4,23 @@ mod constants;
 mod cookie;
 mod reason;
 mod key;
+mod user_key;
+mod regex_rule;
+mod model_alias;
+mod profile;
+mod retry;
+mod upstream;
+mod provider;
 
 pub use clewdr_config::*;
 pub use constants::*;
 pub use cookie::*;
 pub use reason::*;
 pub use key::*;
+pub use user_key::*;
+pub use regex_rule::*;
+pub use model_alias::*;
+pub use profile::*;
+pub use retry::*;
+pub use upstream::*;
+pub use provider::*;
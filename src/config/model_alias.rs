@@ -0,0 +1,69 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Maps a requested model name to the model actually served
+///
+/// The pattern is matched against the whole model name:
+/// - `re:` prefix: a regular expression, the target may use `$1` or `${name}`
+/// - containing `*` or `?`: a glob pattern
+/// - otherwise: the exact model name
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelAlias {
+    /// Pattern of the requested model
+    pub pattern: String,
+    /// Model served instead
+    pub target: String,
+    /// Enables or disables thinking for the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<bool>,
+    /// Overrides `max_tokens` of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Prompt template file used for the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl ModelAlias {
+    /// Compiles the pattern of the alias
+    /// An alias that fails to compile never matches
+    pub fn compile(&mut self) -> Result<(), regex::Error> {
+        self.regex = None;
+        let re = match self.pattern.strip_prefix("re:") {
+            Some(re) => format!("^(?:{})$", re),
+            None => {
+                let glob = regex::escape(&self.pattern)
+                    .replace(r"\*", ".*")
+                    .replace(r"\?", ".");
+                format!("^{}$", glob)
+            }
+        };
+        self.regex = Some(Regex::new(&re)?);
+        Ok(())
+    }
+
+    /// Whether the pattern matches any model, rather than a single name
+    pub fn is_pattern(&self) -> bool {
+        self.pattern.starts_with("re:") || self.pattern.contains(['*', '?'])
+    }
+
+    /// Resolves the served model if the alias matches
+    ///
+    /// # Arguments
+    /// * `model` - The requested model
+    ///
+    /// # Returns
+    /// * `Option<String>` - The target model, None if the alias does not match
+    pub fn resolve(&self, model: &str) -> Option<String> {
+        let caps = self.regex.as_ref()?.captures(model)?;
+        if !self.pattern.starts_with("re:") {
+            return Some(self.target.to_owned());
+        }
+        let mut target = String::new();
+        caps.expand(&self.target, &mut target);
+        Some(target)
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, path::PathBuf, time::SystemTime};
+
+/// Static context kept on Claude.ai instead of being sent with every request
+///
+/// Conversations of the profile are created inside a project, either an
+/// existing one or one provisioned per account from local knowledge files,
+/// and may use a personalized style of the account.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Name of the profile, referenced by models and keys
+    pub name: String,
+    /// UUID of an existing project to create conversations in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_uuid: Option<String>,
+    /// Knowledge files of the project provisioned for each account
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub knowledge: Vec<PathBuf>,
+    /// Custom instructions of the provisioned project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    /// Key or name of the style to use, as listed by the account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+}
+
+/// A knowledge file read from disk
+pub struct KnowledgeFile {
+    pub file_name: String,
+    pub content: String,
+}
+
+impl Profile {
+    /// Whether a project is provisioned for each account
+    pub fn provisions(&self) -> bool {
+        self.project_uuid.is_none() && (!self.knowledge.is_empty() || self.instructions.is_some())
+    }
+
+    /// Prefix of the names of the projects provisioned for this profile
+    pub fn project_prefix(&self) -> String {
+        format!("ClewdR-{}-", self.name)
+    }
+
+    /// Whether a project was provisioned for this profile
+    ///
+    /// Matches the prefix followed by exactly the hash of the knowledge, so
+    /// that profile `a` does not claim the projects of profile `a-b`
+    pub fn is_project(&self, name: &str) -> bool {
+        name.strip_prefix(&self.project_prefix())
+            .is_some_and(|hash| hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    /// Modification times of the knowledge files, used to tell when they change
+    pub fn knowledge_mtimes(&self) -> Result<Vec<SystemTime>, std::io::Error> {
+        self.knowledge
+            .iter()
+            .map(|path| fs::metadata(path)?.modified())
+            .collect()
+    }
+
+    /// Reads the knowledge files of the profile
+    ///
+    /// # Returns
+    /// * `Result<(String, Vec<KnowledgeFile>), std::io::Error>` - Name of the project
+    ///   for this version of the knowledge, and the files
+    pub fn read_knowledge(&self) -> Result<(String, Vec<KnowledgeFile>), std::io::Error> {
+        let files = self
+            .knowledge
+            .iter()
+            .map(|path| {
+                Ok(KnowledgeFile {
+                    file_name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "knowledge.txt".to_string()),
+                    content: fs::read_to_string(path)?,
+                })
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        // a new project is provisioned whenever the knowledge changes, the digest
+        // is stable across builds so existing projects keep their names
+        let mut hasher = Sha256::new();
+        let mut field = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        field(self.instructions.as_deref().unwrap_or_default().as_bytes());
+        field(&[self.instructions.is_some() as u8]);
+        for f in files.iter() {
+            field(f.file_name.as_bytes());
+            field(f.content.as_bytes());
+        }
+        let hash = hasher.finalize()[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let name = format!("{}{}", self.project_prefix(), hash);
+        Ok((name, files))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_only_own_projects() {
+        let profile = Profile {
+            name: "a".to_string(),
+            ..Default::default()
+        };
+        assert!(profile.is_project("ClewdR-a-0123456789abcdef"));
+        assert!(!profile.is_project("ClewdR-a-b-0123456789abcdef"));
+        assert!(!profile.is_project("ClewdR-a-0123456789abcde"));
+        assert!(!profile.is_project("ClewdR-a-0123456789abcdeg"));
+    }
+
+    #[test]
+    fn names_projects_by_stable_digest() {
+        let profile = Profile {
+            name: "a".to_string(),
+            instructions: Some("Be brief".to_string()),
+            ..Default::default()
+        };
+        let (name, files) = profile.read_knowledge().unwrap();
+        assert!(files.is_empty());
+        assert!(profile.is_project(&name));
+        // the name must not change between builds, or projects are provisioned again
+        assert_eq!(name, "ClewdR-a-99073c5432c9675e");
+    }
+}
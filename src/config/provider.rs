@@ -0,0 +1,85 @@
+use rquest::Url;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, ops::Deref};
+
+/// A key of an OpenAI compatible provider, kept as written as providers use any format
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "String")]
+#[serde(into = "String")]
+pub struct OpenAiKey {
+    pub inner: String,
+}
+
+impl Deref for OpenAiKey {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl OpenAiKey {
+    pub fn ellipse(&self) -> String {
+        let prefix = self.inner.chars().take(12).collect::<String>();
+        if prefix.len() < self.inner.len() {
+            format!("{}...", prefix)
+        } else {
+            prefix
+        }
+    }
+}
+
+impl Display for OpenAiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner)
+    }
+}
+
+impl<S> From<S> for OpenAiKey
+where
+    S: AsRef<str>,
+{
+    /// Create a new key from a string
+    fn from(original: S) -> Self {
+        Self {
+            inner: original.as_ref().trim().to_string(),
+        }
+    }
+}
+impl From<OpenAiKey> for String {
+    /// Convert the key to a string
+    fn from(key: OpenAiKey) -> Self {
+        key.to_string()
+    }
+}
+
+/// An upstream speaking the OpenAI Chat Completions API, such as vLLM or another gateway
+///
+/// Requests for the models of the provider are converted from the Claude format,
+/// and the responses converted back.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OpenAiProvider {
+    /// Name of the provider, shown in logs
+    pub name: String,
+    /// Base URL of the API, requests are sent to `{base_url}/chat/completions`
+    pub base_url: Url,
+    /// API keys used in turn, none for providers without authentication
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<OpenAiKey>,
+    /// Models served by the provider, empty to serve any model routed to it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+}
+
+impl OpenAiProvider {
+    /// Gets the endpoint of chat completions
+    ///
+    /// # Returns
+    /// The endpoint URL
+    pub fn endpoint(&self) -> String {
+        format!(
+            "{}/chat/completions",
+            self.base_url.as_str().trim_end_matches('/')
+        )
+    }
+}
@@ -0,0 +1,94 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Where a regex rule is applied
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleScope {
+    /// The merged prompt before sending
+    Input,
+    /// The completion before it reaches the client
+    Output,
+    /// Both input and output
+    #[default]
+    Both,
+}
+
+/// A find/replace rule applied to prompts or completions
+///
+/// Rules are applied in the order they are configured.
+/// The replacement supports capture groups like `$1` or `${name}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegexRule {
+    /// Regex to find
+    pub pattern: String,
+    /// Replacement text
+    #[serde(default)]
+    pub replacement: String,
+    /// Where the rule is applied
+    #[serde(default)]
+    pub scope: RuleScope,
+    /// Model prefixes the rule applies to, empty for all models
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl RegexRule {
+    /// Compiles the pattern of the rule
+    /// A rule that fails to compile is never applied
+    pub fn compile(&mut self) -> Result<(), regex::Error> {
+        self.regex = None;
+        self.regex = Some(Regex::new(&self.pattern)?);
+        Ok(())
+    }
+
+    /// Whether the rule applies to the given scope and model
+    ///
+    /// # Arguments
+    /// * `scope` - Either `Input` or `Output`
+    /// * `model` - Model of the request
+    pub fn applies(&self, scope: RuleScope, model: &str) -> bool {
+        self.regex.is_some()
+            && (self.scope == RuleScope::Both || self.scope == scope)
+            && (self.models.is_empty() || self.models.iter().any(|m| model.starts_with(m)))
+    }
+
+    /// Finds a match of the rule overlapping a position of the text
+    ///
+    /// # Arguments
+    /// * `text` - The text to search
+    /// * `pos` - Byte position in the text
+    ///
+    /// # Returns
+    /// * `Option<usize>` - Start of the match starting before and ending after `pos`
+    pub fn overlapping(&self, text: &str, pos: usize) -> Option<usize> {
+        self.regex
+            .as_ref()?
+            .find_iter(text)
+            .take_while(|m| m.start() < pos)
+            .find(|m| m.end() > pos)
+            .map(|m| m.start())
+    }
+
+    /// Applies the rule to the text
+    pub fn apply(&self, text: &str) -> String {
+        match self.regex {
+            Some(ref re) => re.replace_all(text, self.replacement.as_str()).into_owned(),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// Applies rules to the text in order
+///
+/// # Arguments
+/// * `rules` - Rules to apply
+/// * `text` - The text to rewrite
+///
+/// # Returns
+/// The rewritten text
+pub fn apply_rules(rules: &[RegexRule], text: String) -> String {
+    rules.iter().fold(text, |text, rule| rule.apply(&text))
+}
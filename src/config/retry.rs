@@ -0,0 +1,62 @@
+use rand::{Rng, rng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How failed requests of a backend are retried
+///
+/// The number of attempts is bounded by `max_retries`,
+/// this policy controls which credential is used and how long to wait.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retries on the same credential before switching to another
+    pub same_credential_retries: usize,
+    /// Delay before the first retry, in milliseconds
+    pub base_delay_ms: u64,
+    /// Upper bound of the delay between retries, in milliseconds
+    pub max_delay_ms: u64,
+    /// Time after which no more retries are started, in seconds, 0 for no limit
+    pub deadline_secs: u64,
+    /// Cooldown of a rate limited credential without a reset time, in seconds
+    pub cooldown_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            same_credential_retries: 1,
+            base_delay_ms: 500,
+            max_delay_ms: 8000,
+            deadline_secs: 120,
+            cooldown_secs: 300,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before a retry, exponential with jitter
+    /// The delay is drawn between half and all of the exponential delay
+    ///
+    /// # Arguments
+    /// * `attempt` - Number of retries already made
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let max = self
+            .base_delay_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay_ms);
+        Duration::from_millis(rng().random_range(max / 2..=max))
+    }
+
+    /// Total time allowed for retries, None for no limit
+    pub fn deadline(&self) -> Option<Duration> {
+        (self.deadline_secs > 0).then(|| Duration::from_secs(self.deadline_secs))
+    }
+
+    /// Reset time of a rate limited credential without a reset time
+    ///
+    /// # Returns
+    /// * `i64` - Unix timestamp after which the credential may be used again
+    pub fn cooldown_until(&self) -> i64 {
+        chrono::Utc::now().timestamp() + self.cooldown_secs as i64
+    }
+}
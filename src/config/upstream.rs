@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+/// Backend that serves the Claude requests of a model or key
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Display)]
+#[serde(rename_all = "snake_case")]
+pub enum Upstream {
+    /// Claude.ai, through the cookie pool
+    #[default]
+    Cookie,
+    /// The Anthropic API, through the API key pool
+    ApiKey,
+    /// An OpenAI compatible provider, through its key pool
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// Google Gemini, through the Gemini key pool
+    Gemini,
+}
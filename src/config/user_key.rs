@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::config::Upstream;
+
+/// An API key for clients, with optional per-key settings
+///
+/// Requests authenticated with this key use these settings
+/// instead of the global ones
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct UserKey {
+    /// The API key
+    pub key: String,
+    /// Prompt template file used for this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    /// Name of the profile used for this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// IANA timezone sent to Claude.ai for this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Locale sent to Claude.ai for this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Upstream serving the Claude requests of this key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<Upstream>,
+}
+
+impl UserKey {
+    /// Shortened key for logging
+    pub fn ellipse(&self) -> String {
+        let len = self.key.len();
+        if len > 10 {
+            format!("{}...", &self.key[..10])
+        } else {
+            self.key.to_owned()
+        }
+    }
+}
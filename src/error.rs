@@ -9,7 +9,7 @@ use tokio::sync::oneshot;
 use tracing::{debug, error};
 
 use crate::{
-    config::Reason,
+    config::{PoolKey, Reason},
     services::{cookie_manager::CookieEvent, key_manager::KeyEvent},
     types::claude_message::Message,
 };
@@ -37,14 +37,16 @@ pub enum ClewdrError {
     PadtxtTooShort,
     #[error(transparent)]
     FigmentError(#[from] figment::Error),
-    #[error(transparent)]
-    KeySendError(#[from] tokio::sync::mpsc::error::SendError<KeyEvent>),
+    #[error("Failed to send key event: {0}")]
+    KeySendError(String),
     #[error(transparent)]
     CookieSendError(#[from] tokio::sync::mpsc::error::SendError<CookieEvent>),
     #[error("Retries exceeded")]
     TooManyRetries,
     #[error(transparent)]
     EventSourceError(#[from] eventsource_stream::EventStreamError<axum::Error>),
+    #[error("Failed to read response body: {0}")]
+    BodyError(#[from] axum::Error),
     #[error(transparent)]
     ZipError(#[from] zip::result::ZipError),
     #[error("Asset Error: {0}")]
@@ -85,6 +87,16 @@ pub enum ClewdrError {
     TimestampError(i64),
     #[error("Key/Password Invalid")]
     InvalidKey,
+    #[error("Invalid structured output: {0}")]
+    InvalidStructuredOutput(String),
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+}
+
+impl<K: PoolKey> From<tokio::sync::mpsc::error::SendError<KeyEvent<K>>> for ClewdrError {
+    fn from(e: tokio::sync::mpsc::error::SendError<KeyEvent<K>>) -> Self {
+        ClewdrError::KeySendError(e.to_string())
+    }
 }
 
 impl IntoResponse for ClewdrError {
@@ -115,6 +127,11 @@ impl IntoResponse for ClewdrError {
             ClewdrError::PathNotFound(_) => (StatusCode::NOT_FOUND, json!(self.to_string())),
             ClewdrError::InvalidKey => (StatusCode::UNAUTHORIZED, json!(self.to_string())),
             ClewdrError::BadRequest(_) => (StatusCode::BAD_REQUEST, json!(self.to_string())),
+            ClewdrError::InvalidImage(_) => (StatusCode::BAD_REQUEST, json!(self.to_string())),
+            ClewdrError::InvalidStructuredOutput(_) => {
+                (StatusCode::BAD_GATEWAY, json!(self.to_string()))
+            }
+            ClewdrError::BodyError(_) => (StatusCode::BAD_GATEWAY, json!(self.to_string())),
             ClewdrError::InvalidHeaderValue(_) => {
                 (StatusCode::BAD_REQUEST, json!(self.to_string()))
             }
@@ -131,6 +148,61 @@ impl IntoResponse for ClewdrError {
     }
 }
 
+/// How a failed request should be retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    /// A transient failure, the same credential may succeed
+    SameCredential,
+    /// The credential is limited or invalid, another one may succeed
+    OtherCredential,
+    /// The request itself is at fault, retrying cannot succeed
+    Fatal,
+}
+
+impl ClewdrError {
+    /// Classifies the error for the retry policy
+    pub fn retry_class(&self) -> RetryClass {
+        match self {
+            ClewdrError::InvalidCookie(_) => RetryClass::OtherCredential,
+            ClewdrError::RquestError(e) if e.is_timeout() || e.is_connect() => {
+                RetryClass::SameCredential
+            }
+            ClewdrError::ClaudeHttpError(status, _) | ClewdrError::GeminiHttpError(status, _)
+                if self.is_rate_limit()
+                    || *status == StatusCode::UNAUTHORIZED
+                    || *status == StatusCode::FORBIDDEN =>
+            {
+                RetryClass::OtherCredential
+            }
+            ClewdrError::GeminiHttpError(StatusCode::BAD_REQUEST, body)
+                if body.message.contains("API key") =>
+            {
+                // Google reports invalid keys as bad requests
+                RetryClass::OtherCredential
+            }
+            ClewdrError::ClaudeHttpError(status, _) | ClewdrError::GeminiHttpError(status, _)
+                if status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT =>
+            {
+                RetryClass::SameCredential
+            }
+            ClewdrError::ClaudeHttpError(_, body) if body.r#type == "overloaded_error" => {
+                RetryClass::SameCredential
+            }
+            _ => RetryClass::Fatal,
+        }
+    }
+
+    /// Whether the error is a rate limit without a known reset time
+    pub fn is_rate_limit(&self) -> bool {
+        match self {
+            ClewdrError::ClaudeHttpError(status, _) | ClewdrError::GeminiHttpError(status, _) => {
+                *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
 /// HTTP error response
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClaudeError {
@@ -218,6 +290,60 @@ where
     fn check_gemini(self) -> impl Future<Output = Result<Self, ClewdrError>>;
 }
 
+pub trait CheckOpenAIErr
+where
+    Self: Sized,
+{
+    fn check_openai(self) -> impl Future<Output = Result<Self, ClewdrError>>;
+}
+
+impl CheckOpenAIErr for Response {
+    /// Checks response from an OpenAI compatible API for errors
+    /// The error is reported in the Claude format, so clients of both formats can read it
+    ///
+    /// # Returns
+    /// * `Ok(Response)` if the request was successful
+    /// * `Err(ClewdrError)` if the request failed, with details about the failure
+    async fn check_openai(self) -> Result<Self, ClewdrError> {
+        let status = self.status();
+        if status.is_success() {
+            return Ok(self);
+        }
+        debug!("Error response status: {}", status);
+        let text = match self.text().await {
+            Ok(text) => text,
+            Err(err) => {
+                let error = ClaudeErrorBody {
+                    message: json!(err.to_string()),
+                    r#type: "error_get_error_body".to_string(),
+                    code: Some(status.as_u16()),
+                };
+                return Err(ClewdrError::ClaudeHttpError(status, error));
+            }
+        };
+        let body = serde_json::from_str::<Value>(&text).unwrap_or_default();
+        // OpenAI nests the error, some servers such as vLLM do not
+        let err = if body["error"].is_object() {
+            &body["error"]
+        } else {
+            &body
+        };
+        let error = match err["message"].as_str() {
+            Some(message) => ClaudeErrorBody {
+                message: json!(message),
+                r#type: err["type"].as_str().unwrap_or("api_error").to_string(),
+                code: Some(status.as_u16()),
+            },
+            None => ClaudeErrorBody {
+                message: format!("Unknown error: {}", text).into(),
+                r#type: "error_parse_error_body".to_string(),
+                code: Some(status.as_u16()),
+            },
+        };
+        Err(ClewdrError::ClaudeHttpError(status, error))
+    }
+}
+
 impl CheckGeminiErr for Response {
     async fn check_gemini(self) -> Result<Self, ClewdrError> {
         let status = self.status();
@@ -325,3 +451,85 @@ impl CheckClaudeErr for Response {
         Err(ClewdrError::ClaudeHttpError(status, inner_error))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claude(status: StatusCode, r#type: &str) -> ClewdrError {
+        ClewdrError::ClaudeHttpError(
+            status,
+            ClaudeErrorBody {
+                message: json!("error"),
+                r#type: r#type.to_string(),
+                code: Some(status.as_u16()),
+            },
+        )
+    }
+
+    fn gemini(status: StatusCode, message: &str) -> ClewdrError {
+        ClewdrError::GeminiHttpError(
+            status,
+            GeminiErrorBody {
+                message: message.to_string(),
+                status: "ERROR".to_string(),
+                code: Some(status.as_u16()),
+            },
+        )
+    }
+
+    #[test]
+    fn credential_errors_switch_credential() {
+        assert_eq!(
+            ClewdrError::InvalidCookie(Reason::Banned).retry_class(),
+            RetryClass::OtherCredential
+        );
+        for status in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+        ] {
+            assert_eq!(
+                claude(status, "error").retry_class(),
+                RetryClass::OtherCredential
+            );
+            assert_eq!(
+                gemini(status, "error").retry_class(),
+                RetryClass::OtherCredential
+            );
+        }
+        assert_eq!(
+            gemini(StatusCode::BAD_REQUEST, "API key not valid").retry_class(),
+            RetryClass::OtherCredential
+        );
+    }
+
+    #[test]
+    fn transient_errors_retry_same_credential() {
+        assert_eq!(
+            claude(StatusCode::INTERNAL_SERVER_ERROR, "api_error").retry_class(),
+            RetryClass::SameCredential
+        );
+        assert_eq!(
+            gemini(StatusCode::REQUEST_TIMEOUT, "timeout").retry_class(),
+            RetryClass::SameCredential
+        );
+        assert_eq!(
+            claude(StatusCode::from_u16(529).unwrap(), "overloaded_error").retry_class(),
+            RetryClass::SameCredential
+        );
+    }
+
+    #[test]
+    fn request_errors_are_fatal() {
+        assert_eq!(
+            claude(StatusCode::BAD_REQUEST, "invalid_request_error").retry_class(),
+            RetryClass::Fatal
+        );
+        assert_eq!(
+            gemini(StatusCode::BAD_REQUEST, "Invalid JSON payload").retry_class(),
+            RetryClass::Fatal
+        );
+        assert_eq!(ClewdrError::UnexpectedNone.retry_class(), RetryClass::Fatal);
+    }
+}
@@ -0,0 +1,488 @@
+use async_stream::stream;
+use axum::response::Response;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::Bytes;
+use colored::Colorize;
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, pin_mut};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tracing::info;
+
+use crate::{
+    claude_body::events::EventBuilder,
+    error::ClewdrError,
+    gemini_body::GeminiArgs,
+    types::{
+        claude_message::{
+            ContentBlock, CreateMessageParams, DocumentSource, MessageContent, ResponseFormat,
+            Role, StopReason, StreamError, StreamEvent, ToolChoice,
+        },
+        gemini::request::{
+            Chat, FunctionCall, FunctionResponse, GeminiRequestBody, InlineData, Part,
+            Role as GeminiRole, SystemInstruction, Tool,
+        },
+    },
+    utils::{fetch::fetch_remote, print_out_json},
+};
+
+use super::{GeminiApiFormat, GeminiState};
+
+/// Largest number of stop sequences accepted by Gemini
+const MAX_STOP_SEQUENCES: usize = 5;
+
+/// Converts a Claude API request into a Gemini request
+///
+/// The system prompt and system messages become `system_instruction`, images and
+/// documents become `inline_data`, tool calls and results become function parts,
+/// tools become `functionDeclarations` and thinking becomes `thinkingConfig`.
+/// Remote images and documents are downloaded, as Gemini only reads inline data.
+///
+/// # Arguments
+/// * `p` - The client request
+///
+/// # Returns
+/// * `Result<GeminiRequestBody, ClewdrError>` - Body of the Gemini request
+pub async fn gemini_body(p: &CreateMessageParams) -> Result<GeminiRequestBody, ClewdrError> {
+    let mut system = match p.system {
+        Some(Value::String(ref s)) if !s.is_empty() => vec![s.to_owned()],
+        Some(Value::Array(ref blocks)) => blocks
+            .iter()
+            .filter_map(|b| b["text"].as_str().map(String::from))
+            .collect(),
+        _ => vec![],
+    };
+    // names of the called tools, function responses are matched by name
+    let mut tool_names = HashMap::new();
+    let mut contents: Vec<(Role, Vec<Part>)> = vec![];
+    for m in p.messages.iter() {
+        let blocks = match m.content {
+            MessageContent::Text { ref content } => vec![ContentBlock::text(content)],
+            MessageContent::Blocks { ref content } => content.to_owned(),
+        };
+        if m.role == Role::System {
+            system.extend(blocks.into_iter().filter_map(|b| match b {
+                ContentBlock::Text { text, .. } => Some(text),
+                _ => None,
+            }));
+            continue;
+        }
+        let mut parts = vec![];
+        for block in blocks {
+            let part = match block {
+                ContentBlock::Text { text, .. } if !text.is_empty() => Part::text(text),
+                ContentBlock::Image { source } => match source.url {
+                    Some(ref url) if source.type_ == "url" => remote_part(url).await?,
+                    _ => inline_part(source.media_type, source.data),
+                },
+                ContentBlock::ImageUrl { image_url } => {
+                    match image_url
+                        .url
+                        .strip_prefix("data:")
+                        .and_then(|d| d.split_once(";base64,"))
+                    {
+                        Some((media_type, data)) => inline_part(media_type, data),
+                        None => remote_part(&image_url.url).await?,
+                    }
+                }
+                ContentBlock::Document { source, title, .. } => match source {
+                    DocumentSource::Base64 { media_type, data } => inline_part(media_type, data),
+                    DocumentSource::Text { data, .. } => match title {
+                        Some(title) => Part::text(format!("{}\n\n{}", title, data)),
+                        None => Part::text(data),
+                    },
+                    DocumentSource::Url { url } => remote_part(&url).await?,
+                },
+                ContentBlock::ToolUse { id, name, input } => {
+                    tool_names.insert(id, name.to_owned());
+                    Part::functionCall(FunctionCall {
+                        id: None,
+                        name,
+                        args: Some(input),
+                    })
+                }
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                } => Part::functionResponse(FunctionResponse {
+                    id: None,
+                    name: tool_names.get(&tool_use_id).cloned().unwrap_or(tool_use_id),
+                    response: json!({ "content": content }),
+                }),
+                // thinking cannot be sent back without its Gemini signature
+                _ => continue,
+            };
+            parts.push(part);
+        }
+        if parts.is_empty() {
+            continue;
+        }
+        match contents.last_mut() {
+            Some((role, last)) if *role == m.role => last.extend(parts),
+            _ => contents.push((m.role, parts)),
+        }
+    }
+    let contents = contents
+        .into_iter()
+        .map(|(role, parts)| Chat {
+            role: match role {
+                Role::Assistant => GeminiRole::model,
+                _ => GeminiRole::user,
+            },
+            parts,
+        })
+        .collect();
+
+    let mut config = json!({ "maxOutputTokens": p.max_tokens });
+    if let Some(temperature) = p.temperature {
+        config["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = p.top_p {
+        config["topP"] = json!(top_p);
+    }
+    if let Some(top_k) = p.top_k {
+        config["topK"] = json!(top_k);
+    }
+    let stop = p
+        .stop_sequences
+        .iter()
+        .chain(p.stop.iter())
+        .flatten()
+        .take(MAX_STOP_SEQUENCES)
+        .collect::<Vec<_>>();
+    if !stop.is_empty() {
+        config["stopSequences"] = json!(stop);
+    }
+    if let Some(ref thinking) = p.thinking
+        && thinking.is_enabled()
+    {
+        // -1 lets the model decide how long to think
+        let budget = match thinking.budget_tokens {
+            0 => -1,
+            b => b as i64,
+        };
+        config["thinkingConfig"] = json!({ "includeThoughts": true, "thinkingBudget": budget });
+    }
+    match p.response_format {
+        Some(ResponseFormat::JsonObject) => {
+            config["responseMimeType"] = json!("application/json");
+        }
+        Some(ResponseFormat::JsonSchema { ref json_schema }) => {
+            config["responseMimeType"] = json!("application/json");
+            if let Some(ref schema) = json_schema.schema {
+                config["responseJsonSchema"] = schema.to_owned();
+            }
+        }
+        _ => {}
+    }
+
+    let declarations = p
+        .tools
+        .iter()
+        .flatten()
+        .filter(|t| t.type_.as_deref().is_none_or(|t| t == "custom"))
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "description": t.description.to_owned().unwrap_or_default(),
+                "parametersJsonSchema": t.input_schema,
+            })
+        })
+        .collect::<Vec<_>>();
+    let web_search =
+        p.web_search_options.is_some() || p.tools.iter().flatten().any(|t| t.is_web_search());
+    let mut tools = vec![];
+    let mut tool_config = None;
+    if !declarations.is_empty() {
+        tools.push(Tool::functionDeclarations(declarations));
+        tool_config = p.tool_choice.as_ref().map(|choice| {
+            let config = match choice {
+                ToolChoice::Auto => json!({ "mode": "AUTO" }),
+                ToolChoice::Any => json!({ "mode": "ANY" }),
+                ToolChoice::Tool { name } => {
+                    json!({ "mode": "ANY", "allowedFunctionNames": [name] })
+                }
+            };
+            json!({ "functionCallingConfig": config })
+        });
+    }
+    if web_search {
+        tools.push(Tool::google_search(json!({})));
+    }
+
+    Ok(GeminiRequestBody {
+        system_instruction: (!system.is_empty())
+            .then(|| SystemInstruction::from_string(system.join("\n\n"))),
+        tools: (!tools.is_empty()).then_some(tools),
+        contents,
+        generation_config: Some(config),
+        tool_config,
+    })
+}
+
+/// Builds an inline data part
+fn inline_part(media_type: impl Into<String>, data: impl Into<String>) -> Part {
+    Part::inline_data(InlineData {
+        mime_type: media_type.into(),
+        data: data.into(),
+    })
+}
+
+/// Downloads a remote file into an inline data part
+async fn remote_part(url: &str) -> Result<Part, ClewdrError> {
+    let fetched = fetch_remote(url).await?;
+    Ok(inline_part(
+        fetched.media_type,
+        BASE64_STANDARD.encode(&fetched.bytes),
+    ))
+}
+
+/// Converts a Gemini SSE stream into Claude API stream events
+///
+/// # Arguments
+/// * `input` - The stream of `streamGenerateContent` with `alt=sse`
+/// * `model` - Model name reported in the message start event
+///
+/// # Returns
+/// The events of the message, ending with `message_stop` or an error event
+pub fn gemini_to_events(
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    model: String,
+) -> impl Stream<Item = StreamEvent> + Send + 'static {
+    stream! {
+        let mut builder = EventBuilder::new(model);
+        let input = input.eventsource();
+        pin_mut!(input);
+        let mut error = None;
+        // number of function calls, Gemini sends each call whole
+        let mut calls = 0;
+        while let Some(event) = input.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            };
+            let Ok(chunk) = serde_json::from_str::<Value>(&event.data) else {
+                continue;
+            };
+            if !chunk["error"].is_null() {
+                error = Some(
+                    chunk["error"]["message"]
+                        .as_str()
+                        .map(String::from)
+                        .unwrap_or_else(|| chunk["error"].to_string()),
+                );
+                break;
+            }
+            if let Some(reason) = chunk["promptFeedback"]["blockReason"].as_str() {
+                error = Some(format!("Prompt blocked: {}", reason));
+                break;
+            }
+            let usage = &chunk["usageMetadata"];
+            if usage.is_object() {
+                let tokens = |k: &str| usage[k].as_u64().unwrap_or_default() as u32;
+                builder.usage(
+                    tokens("promptTokenCount"),
+                    tokens("candidatesTokenCount") + tokens("thoughtsTokenCount"),
+                );
+            }
+            let candidate = &chunk["candidates"][0];
+            for part in candidate["content"]["parts"].as_array().into_iter().flatten() {
+                if let Some(text) = part["text"].as_str() {
+                    let events = if part["thought"].as_bool().unwrap_or_default() {
+                        builder.thinking(text)
+                    } else {
+                        builder.text(text)
+                    };
+                    for e in events {
+                        yield e;
+                    }
+                }
+                if let Some(name) = part["functionCall"]["name"].as_str() {
+                    let key = calls.to_string();
+                    calls += 1;
+                    let args = match part["functionCall"]["args"] {
+                        Value::Null => "{}".to_string(),
+                        ref args => args.to_string(),
+                    };
+                    let mut events = builder.tool_use(&key, part["functionCall"]["id"].as_str(), name);
+                    events.extend(builder.tool_input(&key, &args));
+                    for e in events {
+                        yield e;
+                    }
+                }
+            }
+            match candidate["finishReason"].as_str() {
+                Some("MAX_TOKENS") => builder.stop(StopReason::MaxTokens),
+                Some(_) if calls > 0 => builder.stop(StopReason::ToolUse),
+                Some(_) => builder.stop(StopReason::EndTurn),
+                None => {}
+            }
+        }
+        match error {
+            Some(message) => yield StreamEvent::Error {
+                error: StreamError {
+                    type_: "api_error".to_string(),
+                    message,
+                },
+            },
+            None => {
+                for e in builder.finish() {
+                    yield e;
+                }
+            }
+        }
+    }
+}
+
+impl GeminiState {
+    /// Serves a Claude API request with a Gemini model
+    ///
+    /// The request is converted into a Gemini request, sent with a key of the pool,
+    /// and the stream converted back into Claude events.
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    ///
+    /// # Returns
+    /// * `Result<Response, ClewdrError>` - The response in the Claude format
+    pub async fn try_claude(&mut self, p: CreateMessageParams) -> Result<Response, ClewdrError> {
+        info!("[GEMINI] model: {}", p.model.green());
+        self.model = p.model.to_owned();
+        self.path = format!("models/{}:streamGenerateContent", p.model);
+        self.query = GeminiArgs {
+            key: String::new(),
+            alt: Some("sse".to_string()),
+        };
+        self.stream = p.stream.unwrap_or_default();
+        self.vertex = false;
+        self.api_format = GeminiApiFormat::Claude;
+        let body = gemini_body(&p).await?;
+        print_out_json(&body, "gemini_req.json");
+        if let Some(res) = self.try_from_cache(&body).await {
+            return Ok(res);
+        }
+        self.try_chat(body).await
+    }
+
+    /// Counts the input tokens of a Claude API request with Gemini's `countTokens`
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    ///
+    /// # Returns
+    /// * `Result<u32, ClewdrError>` - The number of input tokens
+    pub async fn count_claude_tokens(
+        &mut self,
+        p: &CreateMessageParams,
+    ) -> Result<u32, ClewdrError> {
+        let mut request = serde_json::to_value(gemini_body(p).await?)?;
+        request["model"] = format!("models/{}", p.model).into();
+        let path = format!("models/{}:countTokens", p.model);
+        let res = self
+            .count_tokens(&path, json!({ "generateContentRequest": request }))
+            .await?;
+        res["totalTokens"]
+            .as_u64()
+            .map(|t| t as u32)
+            .ok_or(ClewdrError::UnexpectedNone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    async fn convert(raw: Value) -> Value {
+        let p = CreateMessageParams::deserialize(&raw).unwrap();
+        serde_json::to_value(gemini_body(&p).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn converts_messages_and_tools() {
+        let body = convert(json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 2048,
+            "system": "Be brief",
+            "messages": [
+                { "role": "system", "content": "Answer in English" },
+                { "role": "user", "content": "Weather?" },
+                { "role": "user", "content": [
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } },
+                ] },
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "call the tool", "signature": "" },
+                    { "type": "tool_use", "id": "t1", "name": "weather", "input": { "city": "Paris" } },
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "content": [{ "type": "text", "text": "Sunny" }] },
+                ] },
+            ],
+            "tools": [{ "name": "weather", "input_schema": { "type": "object" } }],
+            "tool_choice": { "type": "tool", "name": "weather" },
+            "stop": ["a", "b", "c", "d", "e", "f"],
+            "thinking": { "type": "enabled", "budget_tokens": 1024 },
+        }))
+        .await;
+        assert_eq!(
+            body["system_instruction"]["parts"][0]["text"],
+            "Be brief\n\nAnswer in English"
+        );
+        // consecutive user messages are merged, thinking is dropped
+        let contents = body["contents"].as_array().unwrap();
+        assert_eq!(contents.len(), 3);
+        assert_eq!(contents[0]["parts"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            contents[0]["parts"][1]["inline_data"]["mime_type"],
+            "image/png"
+        );
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(
+            contents[1]["parts"],
+            json!([{ "functionCall": { "name": "weather", "args": { "city": "Paris" } } }])
+        );
+        assert_eq!(
+            contents[2]["parts"][0]["functionResponse"],
+            json!({ "name": "weather", "response": { "content": "Sunny" } })
+        );
+        let config = &body["generation_config"];
+        assert_eq!(config["maxOutputTokens"], 2048);
+        assert_eq!(config["stopSequences"], json!(["a", "b", "c", "d", "e"]));
+        assert_eq!(
+            config["thinkingConfig"],
+            json!({ "includeThoughts": true, "thinkingBudget": 1024 })
+        );
+        assert_eq!(
+            body["tools"][0]["functionDeclarations"][0]["name"],
+            "weather"
+        );
+        assert_eq!(
+            body["tool_config"]["functionCallingConfig"],
+            json!({ "mode": "ANY", "allowedFunctionNames": ["weather"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn converts_extensions() {
+        let body = convert(json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 2048,
+            "messages": [{ "role": "user", "content": "Weather?" }],
+            "response_format": { "type": "json_schema", "json_schema": { "name": "w", "schema": { "type": "object" } } },
+            "web_search_options": {},
+            "thinking": { "type": "enabled" },
+        }))
+        .await;
+        let config = &body["generation_config"];
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert_eq!(config["responseJsonSchema"], json!({ "type": "object" }));
+        assert_eq!(config["thinkingConfig"]["thinkingBudget"], -1);
+        assert_eq!(body["tools"], json!([{ "google_search": {} }]));
+        assert!(body["system_instruction"].is_null());
+        assert!(body["tool_config"].is_null());
+    }
+}
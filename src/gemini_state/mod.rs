@@ -15,20 +15,28 @@ use tokio::spawn;
 use tracing::{Instrument, Level, error, info, span};
 
 use crate::{
+    claude_body::events::events_response,
     config::{CLEWDR_CONFIG, GEMINI_ENDPOINT, KeyStatus},
-    error::{CheckGeminiErr, ClewdrError},
+    error::{CheckGeminiErr, ClewdrError, RetryClass},
     gemini_body::GeminiArgs,
     middleware::gemini::GeminiContext,
     services::{
         cache::{CACHE, GetHashKey},
         key_manager::KeyEventSender,
+        model_list::GEMINI_MODELS,
     },
+    types::claude_message::Thinking,
+    utils::retry::Backoff,
 };
 
+pub mod claude;
+
 #[derive(Clone, Display, PartialEq, Eq)]
 pub enum GeminiApiFormat {
     Gemini,
     OpenAI,
+    /// Gemini API, converted from and to the Claude API
+    Claude,
 }
 
 pub static SAFETY_SETTINGS: LazyLock<Value> = LazyLock::new(|| {
@@ -130,7 +138,7 @@ impl GeminiState {
             .ok_or(ClewdrError::UnexpectedNone)?;
         let bearer = format!("Bearer {}", access_token);
         let res = match self.api_format {
-            GeminiApiFormat::Gemini => {
+            GeminiApiFormat::Gemini | GeminiApiFormat::Claude => {
                 let endpoint = format!(
                     "https://aiplatform.googleapis.com/v1/projects/{}/locations/global/publishers/google/models/{}:{method}",
                     CLEWDR_CONFIG
@@ -179,17 +187,24 @@ impl GeminiState {
     {
         let mut p = serde_json::to_value(p)?;
         match self.api_format {
-            GeminiApiFormat::Gemini => {
+            GeminiApiFormat::Gemini | GeminiApiFormat::Claude => {
                 p["safetySettings"] = SAFETY_SETTINGS.to_owned();
             }
             GeminiApiFormat::OpenAI => {
+                // Claude style thinking, such as the default of a model alias
+                if let Some(thinking) = p.as_object_mut().and_then(|p| p.remove("thinking")) {
+                    let config = match serde_json::from_value::<Thinking>(thinking) {
+                        Ok(t) if !t.is_enabled() => json!({ "thinking_budget": 0 }),
+                        Ok(Thinking { budget_tokens, .. }) if budget_tokens > 0 => {
+                            json!({ "include_thoughts": true, "thinking_budget": budget_tokens })
+                        }
+                        _ => json!({ "include_thoughts": true, "thinking_budget": -1 }),
+                    };
+                    p["extra_body"]["google"]["thinking_config"] = config;
+                }
                 if self.vertex {
                     // Only Vertex OpenAI API supports safety settings
-                    p["extra_body"] = json!({
-                        "google": {
-                            "safety_settings": SAFETY_SETTINGS.to_owned(),
-                        },
-                    });
+                    p["extra_body"]["google"]["safety_settings"] = SAFETY_SETTINGS.to_owned();
                     p["model"] = format!("google/{}", self.model).into();
                 }
             }
@@ -199,14 +214,17 @@ impl GeminiState {
             let stream = res.bytes_stream();
             return Ok(stream);
         }
-        self.request_key().await?;
+        // a retry on the same key keeps it
+        if self.key.is_none() {
+            self.request_key().await?;
+        }
         let Some(key) = self.key.to_owned() else {
             return Err(ClewdrError::UnexpectedNone);
         };
         info!("[KEY] {}", key.key.ellipse().green());
         let key = key.key.to_string();
         let res = match self.api_format {
-            GeminiApiFormat::Gemini => {
+            GeminiApiFormat::Gemini | GeminiApiFormat::Claude => {
                 let mut query_vec = self.query.to_vec();
                 query_vec.push(("key", key.as_str()));
                 self.client
@@ -232,15 +250,89 @@ impl GeminiState {
         Ok(res.bytes_stream())
     }
 
+    /// Counts the tokens of a Gemini request
+    ///
+    /// The request is forwarded to Google with a key from the pool.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the request, such as `models/gemini-2.0-flash:countTokens`
+    /// * `body` - The request body, passed through unchanged
+    ///
+    /// # Returns
+    /// * `Result<Value, ClewdrError>` - The token count returned by Google
+    pub async fn count_tokens(&mut self, path: &str, body: Value) -> Result<Value, ClewdrError> {
+        self.request_key().await?;
+        let Some(key) = self.key.to_owned() else {
+            return Err(ClewdrError::UnexpectedNone);
+        };
+        info!("[KEY] {}", key.key.ellipse().green());
+        let res = self
+            .client
+            .post(format!("{}/v1beta/{}", GEMINI_ENDPOINT, path))
+            .query(&[("key", key.key.to_string())])
+            .json(&body)
+            .send()
+            .await?
+            .check_gemini()
+            .await?
+            .json::<Value>()
+            .await?;
+        Ok(res)
+    }
+
+    /// Lists the Gemini models in OpenAI format
+    ///
+    /// The listing is fetched from Google with a key from the pool
+    /// and cached for all requests.
+    ///
+    /// # Returns
+    /// * `Result<Value, ClewdrError>` - The model listing
+    pub async fn list_models(&mut self) -> Result<Value, ClewdrError> {
+        if let Some(models) = GEMINI_MODELS.get(&()) {
+            return Ok(models);
+        }
+        self.request_key().await?;
+        let Some(key) = self.key.to_owned() else {
+            return Err(ClewdrError::UnexpectedNone);
+        };
+        let models = self
+            .client
+            .get(format!("{}/v1beta/openai/models", GEMINI_ENDPOINT))
+            .header(AUTHORIZATION, format!("Bearer {}", key.key))
+            .send()
+            .await?
+            .check_gemini()
+            .await?
+            .json::<Value>()
+            .await?;
+        GEMINI_MODELS.insert((), models.to_owned());
+        Ok(models)
+    }
+
+    /// Sends the request, retrying failures following `gemini_retry`
+    ///
+    /// Transient errors are retried on the same key, rate limited or invalid keys
+    /// are skipped, and errors caused by the request fail immediately.
+    ///
+    /// # Arguments
+    /// * `p` - The request body
+    ///
+    /// # Returns
+    /// * `Result<Response, ClewdrError>` - The response or the last error
     pub async fn try_chat(
         &mut self,
         p: impl Serialize + GetHashKey + Clone,
     ) -> Result<Response, ClewdrError> {
+        let policy = CLEWDR_CONFIG.load().gemini_retry.to_owned();
+        let mut backoff = Backoff::new(policy.to_owned());
+        // state holding the key to retry on, and the retries made on it
+        let mut same_key: Option<GeminiState> = None;
+        let mut same_retries = 0;
         for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
             if i > 0 {
                 info!("[RETRY] attempt: {}", i.to_string().green());
             }
-            let mut state = self.to_owned();
+            let mut state = same_key.take().unwrap_or_else(|| self.to_owned());
             let p = p.to_owned();
 
             match state.send_chat(p).await {
@@ -254,11 +346,31 @@ impl GeminiState {
                     } else {
                         error!("{}", e);
                     }
-                    match e {
-                        ClewdrError::GeminiHttpError(_, _) => {
-                            continue;
+                    match e.retry_class() {
+                        RetryClass::Fatal => return Err(e),
+                        RetryClass::SameCredential
+                            if same_retries < policy.same_credential_retries =>
+                        {
+                            same_retries += 1;
+                            same_key = Some(state.to_owned());
                         }
-                        e => return Err(e),
+                        _ => {
+                            same_retries = 0;
+                            if let Some(key) = state.key.to_owned() {
+                                let reset_time = e.is_rate_limit().then(|| policy.cooldown_until());
+                                state
+                                    .event_sender
+                                    .return_key(key, reset_time)
+                                    .await
+                                    .unwrap_or_else(|e| {
+                                        error!("Failed to send key: {}", e);
+                                    });
+                            }
+                        }
+                    }
+                    if !backoff.wait().await {
+                        error!("Retry deadline exceeded");
+                        return Err(e);
                     }
                 }
             }
@@ -297,6 +409,10 @@ impl GeminiState {
             return Body::empty().into_response();
         }
         // response is used for returning
+        if self.api_format == GeminiApiFormat::Claude {
+            let events = claude::gemini_to_events(input, self.model.to_owned());
+            return events_response(events, self.stream).await;
+        }
         // not streaming
         // stream the response
         Body::from_stream(input).into_response()
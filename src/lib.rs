@@ -3,6 +3,7 @@ use std::{path::PathBuf, sync::LazyLock};
 use clap::Parser;
 use figlet_rs::FIGfont;
 
+pub mod anthropic_state;
 pub mod api;
 pub mod claude_body;
 pub mod claude_state;
@@ -11,6 +12,7 @@ pub mod error;
 pub mod gemini_state;
 pub mod gemini_body;
 pub mod middleware;
+pub mod openai_state;
 pub mod router;
 pub mod services;
 pub mod types;
@@ -1,4 +1,7 @@
-use axum::extract::FromRequestParts;
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, header::AUTHORIZATION},
+};
 use axum_auth::AuthBearer;
 use tracing::warn;
 
@@ -28,6 +31,26 @@ where
     }
 }
 
+/// Extracts the API key of a request from the x-api-key or Bearer Authorization header
+///
+/// # Arguments
+/// * `headers` - Headers of the request
+///
+/// # Returns
+/// * `Option<&str>` - The API key, if present
+pub fn request_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .map(|k| k.trim())
+}
+
 pub struct RequireQueryKeyAuth;
 impl<S> FromRequestParts<S> for RequireQueryKeyAuth
 where
@@ -1,7 +1,9 @@
+mod regex_rules;
 mod request;
 mod response;
 mod stop_sequences;
 
+pub use regex_rules::apply_regex_rules;
 pub use request::{ClaudeContext, ClaudePreprocess};
 pub use response::to_oai;
 pub use stop_sequences::apply_stop_sequences;
\ No newline at end of file
@@ -0,0 +1,273 @@
+use async_stream::try_stream;
+use axum::{
+    body::{Body, to_bytes},
+    http::header::CONTENT_LENGTH,
+    response::{IntoResponse, Response, Sse, sse::Event},
+};
+use eventsource_stream::{Event as SourceEvent, Eventsource};
+use futures::Stream;
+use serde_json::Value;
+use std::{collections::HashMap, mem};
+
+use crate::{
+    config::{CLEWDR_CONFIG, RegexRule, RuleScope, apply_rules},
+    error::ClewdrError,
+    types::claude_message::{ContentBlockDelta, StreamEvent},
+};
+
+use super::ClaudeContext;
+
+type EventResult<T> = Result<T, eventsource_stream::EventStreamError<axum::Error>>;
+
+/// Buffers streamed text so rules can match across chunk boundaries
+///
+/// The last `window` bytes are held back, and a match overlapping them is held
+/// back as a whole, so matches up to `window` bytes long are rewritten even when
+/// they span chunks or line breaks.
+struct RuleBuffer {
+    buf: String,
+    window: usize,
+}
+
+impl RuleBuffer {
+    fn new(window: usize) -> Self {
+        Self {
+            buf: String::new(),
+            window,
+        }
+    }
+
+    /// Pushes a chunk and returns the rewritten text that can be released
+    fn push(&mut self, text: &str, rules: &[RegexRule]) -> String {
+        self.buf += text;
+        let Some(mut cut) = self.buf.len().checked_sub(self.window) else {
+            return String::new();
+        };
+        while !self.buf.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        // keep matches overlapping the held back text together
+        while let Some(start) = rules
+            .iter()
+            .filter_map(|r| r.overlapping(&self.buf, cut))
+            .min()
+        {
+            cut = start;
+        }
+        let rest = self.buf.split_off(cut);
+        let out = std::mem::replace(&mut self.buf, rest);
+        apply_rules(rules, out)
+    }
+
+    /// Releases all buffered text
+    fn flush(&mut self, rules: &[RegexRule]) -> String {
+        apply_rules(rules, std::mem::take(&mut self.buf))
+    }
+}
+
+/// Builds a text delta SSE event
+fn text_event(index: usize, text: String) -> Event {
+    let e = StreamEvent::ContentBlockDelta {
+        index,
+        delta: ContentBlockDelta::TextDelta { text },
+    };
+    Event::default()
+        .event("content_block_delta")
+        .json_data(e)
+        .unwrap()
+}
+
+fn rule_stream(
+    rules: Vec<RegexRule>,
+    window: usize,
+    stream: impl Stream<Item = EventResult<SourceEvent>>,
+) -> impl Stream<Item = EventResult<Event>> {
+    try_stream!({
+        let mut buffers: HashMap<usize, RuleBuffer> = HashMap::new();
+        for await event in stream {
+            let SourceEvent {
+                event: name, data, ..
+            } = event?;
+            let event = Event::default().event(name).data(&data);
+            match serde_json::from_str::<StreamEvent>(&data) {
+                Ok(StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ContentBlockDelta::TextDelta { text },
+                }) => {
+                    let out = buffers
+                        .entry(index)
+                        .or_insert_with(|| RuleBuffer::new(window))
+                        .push(&text, &rules);
+                    if !out.is_empty() {
+                        yield text_event(index, out);
+                    }
+                }
+                Ok(StreamEvent::ContentBlockStop { index }) => {
+                    // release the rest of the block before it ends
+                    if let Some(mut b) = buffers.remove(&index) {
+                        let out = b.flush(&rules);
+                        if !out.is_empty() {
+                            yield text_event(index, out);
+                        }
+                    }
+                    yield event;
+                }
+                Ok(StreamEvent::MessageDelta { .. }) | Ok(StreamEvent::MessageStop) => {
+                    for (index, mut b) in buffers.drain() {
+                        let out = b.flush(&rules);
+                        if !out.is_empty() {
+                            yield text_event(index, out);
+                        }
+                    }
+                    yield event;
+                }
+                _ => yield event,
+            }
+        }
+    })
+}
+
+/// Rewrites the text of a non-streaming message in place
+///
+/// Only the text is rewritten, so fields such as `id`, `stop_reason` and
+/// `usage` are kept as they are.
+fn rewrite_message(message: &mut Value, rules: &[RegexRule]) {
+    match message["content"] {
+        Value::String(ref mut content) => {
+            *content = apply_rules(rules, mem::take(content));
+        }
+        Value::Array(ref mut blocks) => {
+            for block in blocks.iter_mut().filter(|b| b["type"] == "text") {
+                if let Value::String(ref mut text) = block["text"] {
+                    *text = apply_rules(rules, mem::take(text));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Applies output regex rules to the response
+///
+/// Streaming responses are rewritten event by event, non-streaming
+/// responses have the text of their content rewritten in place, keeping
+/// the other fields and the headers of the upstream.
+pub async fn apply_regex_rules(resp: Response) -> Response {
+    let Some(f) = resp.extensions().get::<ClaudeContext>().cloned() else {
+        return resp;
+    };
+    let config = CLEWDR_CONFIG.load();
+    let rules = config.regex_rules_for(RuleScope::Output, &f.model);
+    if resp.status() != 200 || rules.is_empty() {
+        return resp;
+    }
+
+    let mut resp = if f.stream {
+        let stream = resp.into_body().into_data_stream().eventsource();
+        let stream = rule_stream(rules, config.regex_window, stream);
+        Sse::new(stream)
+            .keep_alive(Default::default())
+            .into_response()
+    } else {
+        let (mut parts, body) = resp.into_parts();
+        let bytes = match to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(e) => return ClewdrError::from(e).into_response(),
+        };
+        let Ok(mut message) = serde_json::from_slice::<Value>(&bytes) else {
+            return Response::from_parts(parts, Body::from(bytes));
+        };
+        rewrite_message(&mut message, &rules);
+        let body = match serde_json::to_vec(&message) {
+            Ok(body) => body,
+            Err(e) => return ClewdrError::from(e).into_response(),
+        };
+        parts.headers.remove(CONTENT_LENGTH);
+        Response::from_parts(parts, Body::from(body))
+    };
+
+    resp.extensions_mut().insert(f);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{RuleBuffer, rewrite_message};
+    use crate::config::RegexRule;
+
+    fn rule(pattern: &str, replacement: &str) -> RegexRule {
+        let mut rule: RegexRule = serde_json::from_value(serde_json::json!({
+            "pattern": pattern,
+            "replacement": replacement,
+        }))
+        .unwrap();
+        rule.compile().unwrap();
+        rule
+    }
+
+    fn run(buf: &mut RuleBuffer, chunks: &[&str], rules: &[RegexRule]) -> String {
+        let mut out = chunks
+            .iter()
+            .map(|c| buf.push(c, rules))
+            .collect::<String>();
+        out += &buf.flush(rules);
+        out
+    }
+
+    #[test]
+    fn matches_across_line_breaks() {
+        let rules = [rule(r"\n\nHuman:", " [cut]")];
+        let mut buf = RuleBuffer::new(16);
+        let out = run(&mut buf, &["Hello there.\n", "\nHu", "man: more"], &rules);
+        assert_eq!(out, "Hello there. [cut] more");
+    }
+
+    #[test]
+    fn releases_text_beyond_window() {
+        let rules = [rule("foo", "bar")];
+        let mut buf = RuleBuffer::new(4);
+        assert_eq!(buf.push("abcdefgh", &rules), "abcd");
+        assert_eq!(buf.push("fo", &rules), "ef");
+        assert_eq!(buf.push("o!", &rules), "gh");
+        assert_eq!(buf.flush(&rules), "bar!");
+    }
+
+    #[test]
+    fn holds_back_whole_overlapping_match() {
+        let rules = [rule("a+", "x")];
+        let mut buf = RuleBuffer::new(2);
+        assert_eq!(buf.push("baaaa", &rules), "b");
+        assert_eq!(buf.flush(&rules), "x");
+    }
+
+    #[test]
+    fn keeps_characters_whole() {
+        let rules = [rule("é", "e")];
+        let mut buf = RuleBuffer::new(1);
+        assert_eq!(run(&mut buf, &["café", "é"], &rules), "cafee");
+    }
+
+    #[test]
+    fn rewrites_message_text_only() {
+        let rules = [rule("foo", "bar")];
+        let mut message = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-0",
+            "content": [
+                { "type": "thinking", "thinking": "foo", "signature": "s" },
+                { "type": "text", "text": "foo foo" },
+            ],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 3, "output_tokens": 2 },
+        });
+        let mut expected = message.to_owned();
+        expected["content"][1]["text"] = json!("bar bar");
+        rewrite_message(&mut message, &rules);
+        assert_eq!(message, expected);
+    }
+}
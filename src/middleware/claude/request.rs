@@ -1,14 +1,20 @@
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
 use axum::{
     Json,
     extract::{FromRequest, Request},
     response::IntoResponse,
 };
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::debug;
 
 use crate::{
     claude_state::{ClaudeApiFormat, ClaudeState},
+    config::{CLEWDR_CONFIG, Upstream, UserKey},
     error::ClewdrError,
+    middleware::request_key,
+    openai_state::OpenAiState,
     types::claude_message::{ContentBlock, CreateMessageParams, Message, Role},
 };
 
@@ -28,7 +34,12 @@ use super::to_oai;
 /// - Identifies test messages and handles them appropriately
 /// - Attempts to retrieve responses from cache before processing requests
 /// - Provides format information via the FormatInfo extension
-pub struct ClaudePreprocess(pub CreateMessageParams, pub ClaudeContext);
+/// - Keeps the raw body for the API key upstream, which passes it through
+pub struct ClaudePreprocess(
+    pub CreateMessageParams,
+    pub ClaudeContext,
+    pub Option<Value>,
+);
 
 /// Contains information about the API format and streaming status
 ///
@@ -43,6 +54,18 @@ pub struct ClaudeContext {
     pub api_format: ClaudeApiFormat,
     /// The stop sequence used for the request
     pub stop_sequences: Vec<String>,
+    /// The user key the request was authenticated with
+    pub user_key: Option<UserKey>,
+    /// The model of the request
+    pub model: String,
+    /// Prompt template set by the model alias
+    pub template: Option<PathBuf>,
+    /// Timezone of the request
+    pub timezone: String,
+    /// Locale of the request
+    pub locale: Option<String>,
+    /// Upstream serving the request
+    pub upstream: Upstream,
 }
 
 /// Predefined test message in Claude format for connection testing
@@ -50,24 +73,58 @@ pub struct ClaudeContext {
 /// This is a standard test message sent by clients like SillyTavern
 /// to verify connectivity. The system detects these messages and
 /// responds with a predefined test response to confirm service availability.
-static TEST_MESSAGE_CLAUDE: LazyLock<Message> = LazyLock::new(|| {
-    Message::new_blocks(
-        Role::User,
-        vec![ContentBlock::Text {
-            text: "Hi".to_string(),
-        }],
-    )
-});
+static TEST_MESSAGE_CLAUDE: LazyLock<Message> =
+    LazyLock::new(|| Message::new_blocks(Role::User, vec![ContentBlock::text("Hi")]));
 
 /// Predefined test message in OpenAI format for connection testing
 static TEST_MESSAGE_OAI: LazyLock<Message> = LazyLock::new(|| Message::new_text(Role::User, "Hi"));
 
+/// Header overriding the timezone of a request, also read from `metadata.timezone`
+const TIMEZONE_HEADER: &str = "x-clewdr-timezone";
+/// Header overriding the locale of a request, also read from `metadata.locale`
+const LOCALE_HEADER: &str = "x-clewdr-locale";
+
 impl FromRequest<ClaudeState> for ClaudePreprocess {
     type Rejection = ClewdrError;
 
     async fn from_request(req: Request, state: &ClaudeState) -> Result<Self, Self::Rejection> {
         let uri = req.uri().to_string();
-        let Json(mut body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+        let user_key =
+            request_key(req.headers()).and_then(|k| CLEWDR_CONFIG.load().user_key(k).cloned());
+        let headers = req.headers();
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.trim().to_string())
+        };
+        let (timezone, locale) = (header(TIMEZONE_HEADER), header(LOCALE_HEADER));
+        let Json(raw) = Json::<Value>::from_request(req, &()).await?;
+        let mut body = CreateMessageParams::deserialize(&raw)
+            .map_err(|e| ClewdrError::BadRequest(format!("Invalid request body: {}", e)))?;
+
+        // Timezone and locale, the headers win over the metadata
+        let metadata = |name: &str| {
+            body.metadata
+                .as_ref()
+                .and_then(|m| m.fields.get(name))
+                .map(String::as_str)
+        };
+        let (timezone, locale) = CLEWDR_CONFIG.load().request_locale(
+            user_key.as_ref(),
+            timezone.as_deref().or(metadata("timezone")),
+            locale.as_deref().or(metadata("locale")),
+        )?;
+
+        // Resolve model aliases before routing and caching
+        let alias = CLEWDR_CONFIG
+            .load()
+            .model_alias(&body.model)
+            .map(|(m, a)| (m, a.to_owned()));
+        if let Some((ref model, _)) = alias {
+            debug!("Model alias: {} -> {}", body.model, model);
+            body.model = model.to_owned();
+        }
 
         // Handle thinking mode by modifying the model name
         if body.model.ends_with("-thinking") {
@@ -75,6 +132,25 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
             body.thinking = Some(Default::default());
         }
 
+        let upstream = CLEWDR_CONFIG
+            .load()
+            .upstream_for(&body.model, user_key.as_ref());
+
+        // Default parameters of the alias
+        let template = alias.and_then(|(_, alias)| {
+            match alias.thinking {
+                Some(true) => {
+                    body.thinking.get_or_insert_default();
+                }
+                Some(false) => body.thinking = None,
+                None => {}
+            }
+            if let Some(max_tokens) = alias.max_tokens {
+                body.max_tokens = max_tokens;
+            }
+            alias.template
+        });
+
         // Check for test messages and respond appropriately
         if !body.stream.unwrap_or_default()
             && (body.messages == vec![TEST_MESSAGE_CLAUDE.to_owned()]
@@ -96,6 +172,10 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
         let mut state = state.to_owned();
         state.api_format = format;
         state.stream = stream;
+        state.user_key = user_key.to_owned();
+        state.template = template.to_owned();
+        state.timezone = timezone.to_owned();
+        state.locale = locale.to_owned();
         let mut stop = body.stop_sequences.to_owned().unwrap_or_default();
         stop.extend_from_slice(body.stop.to_owned().unwrap_or_default().as_slice());
         stop.sort();
@@ -104,15 +184,35 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
             stream,
             api_format: format,
             stop_sequences: stop,
+            user_key,
+            model: body.model.to_owned(),
+            template: template.to_owned(),
+            timezone,
+            locale,
+            upstream,
         };
 
-        // Try to retrieve from cache before processing
-        if let Some(mut r) = state.try_from_cache(&body).await {
+        // Try to retrieve from cache before processing, API keys are not cached,
+        // Gemini requests are cached once converted
+        let cached = match upstream {
+            Upstream::Cookie => state.try_from_cache(&body).await,
+            Upstream::OpenAi => match CLEWDR_CONFIG.load().provider_for(&body.model) {
+                Ok(provider) => {
+                    OpenAiState::new(provider.to_owned(), stream)
+                        .try_from_cache(&body)
+                        .await
+                }
+                Err(_) => None,
+            },
+            Upstream::ApiKey | Upstream::Gemini => None,
+        };
+        if let Some(mut r) = cached {
             r.extensions_mut().insert(info.to_owned());
             let r = to_oai(r).await.into_response();
             return Err(ClewdrError::CacheFound(r));
         }
 
-        Ok(Self(body, info))
+        let raw = (upstream == Upstream::ApiKey).then_some(raw);
+        Ok(Self(body, info, raw))
     }
 }
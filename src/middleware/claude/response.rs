@@ -1,7 +1,9 @@
+use async_stream::stream;
 use axum::response::{IntoResponse, Response, Sse, sse::Event};
 use eventsource_stream::Eventsource;
-use futures::{Stream, StreamExt};
+use futures::{Stream, StreamExt, pin_mut};
 use serde::Serialize;
+use serde_json::Value;
 
 use crate::{
     claude_state::ClaudeApiFormat,
@@ -79,6 +81,51 @@ struct StreamEventDelta {
 pub enum EventContent {
     Content { content: String },
     Reasoning { reasoning_content: String },
+    Annotations { annotations: Vec<Annotation> },
+}
+
+/// URL citation annotation in OpenAI format
+#[derive(Debug, Serialize)]
+pub struct Annotation {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    url_citation: UrlCitation,
+}
+
+/// Location of a cited source within the message content
+#[derive(Debug, Serialize)]
+pub struct UrlCitation {
+    url: String,
+    title: String,
+    start_index: usize,
+    end_index: usize,
+}
+
+impl Annotation {
+    /// Creates an annotation from a Claude web search citation
+    ///
+    /// # Arguments
+    /// * `citation` - The citation of a `citations_delta` event
+    /// * `end_index` - Length of the content streamed so far, in characters
+    ///
+    /// # Returns
+    /// The annotation, None if the citation has no URL
+    fn from_citation(citation: &Value, end_index: usize) -> Option<Self> {
+        let url = citation["url"].as_str()?.to_string();
+        let title = citation["title"].as_str().unwrap_or_default().to_string();
+        let cited = citation["cited_text"]
+            .as_str()
+            .map_or(0, |t| t.chars().count());
+        Some(Self {
+            type_: "url_citation",
+            url_citation: UrlCitation {
+                url,
+                title,
+                start_index: end_index.saturating_sub(cited),
+                end_index,
+            },
+        })
+    }
 }
 
 /// Creates an SSE event with the given content in OpenAI format
@@ -98,7 +145,8 @@ pub fn build_event(content: EventContent) -> Event {
 ///
 /// Extracts content from Claude events and reformats them to match OpenAI's streaming format.
 /// This function processes each event in the stream, identifying the delta content type
-/// (text or thinking), and converting it to the appropriate OpenAI-compatible event format.
+/// (text, thinking or citation), and converting it to the appropriate OpenAI-compatible event format.
+/// Citations become `url_citation` annotations indexing into the streamed content.
 ///
 /// # Arguments
 /// * `s` - The input stream of Claude.ai events
@@ -114,24 +162,43 @@ where
     I: Stream<Item = Result<eventsource_stream::Event, E>> + Send,
     E: Send,
 {
-    s.filter_map(async |event| match event {
-        Ok(eventsource_stream::Event { data, .. }) => {
-            let parsed = serde_json::from_str::<StreamEvent>(&data).ok()?;
-            let StreamEvent::ContentBlockDelta { delta, .. } = parsed else {
-                return None;
+    stream! {
+        // length of the content sent so far, in characters
+        let mut offset = 0;
+        pin_mut!(s);
+        while let Some(event) = s.next().await {
+            let eventsource_stream::Event { data, .. } = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+            let Ok(StreamEvent::ContentBlockDelta { delta, .. }) =
+                serde_json::from_str::<StreamEvent>(&data)
+            else {
+                continue;
             };
             match delta {
                 ContentBlockDelta::TextDelta { text } => {
-                    Some(Ok(build_event(EventContent::Content { content: text })))
+                    offset += text.chars().count();
+                    yield Ok(build_event(EventContent::Content { content: text }));
                 }
                 ContentBlockDelta::ThinkingDelta { thinking } => {
-                    Some(Ok(build_event(EventContent::Reasoning {
+                    yield Ok(build_event(EventContent::Reasoning {
                         reasoning_content: thinking,
-                    })))
+                    }));
                 }
-                _ => None,
+                ContentBlockDelta::CitationsDelta { citation } => {
+                    let Some(annotation) = Annotation::from_citation(&citation, offset) else {
+                        continue;
+                    };
+                    yield Ok(build_event(EventContent::Annotations {
+                        annotations: vec![annotation],
+                    }));
+                }
+                _ => {}
             }
         }
-        Err(e) => Some(Err(e)),
-    })
+    }
 }
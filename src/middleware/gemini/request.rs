@@ -3,12 +3,17 @@ use axum::{
     extract::{FromRequest, Path, Request},
 };
 
+use tracing::debug;
+
 use crate::{
     config::CLEWDR_CONFIG,
     error::ClewdrError,
     gemini_body::GeminiArgs,
     gemini_state::{GeminiApiFormat, GeminiState},
-    types::{claude_message::CreateMessageParams, gemini::request::GeminiRequestBody},
+    types::{
+        claude_message::{CreateMessageParams, Thinking},
+        gemini::request::GeminiRequestBody,
+    },
 };
 
 pub struct GeminiContext {
@@ -26,7 +31,7 @@ impl FromRequest<GeminiState> for GeminiPreprocess {
     type Rejection = ClewdrError;
 
     async fn from_request(mut req: Request, state: &GeminiState) -> Result<Self, Self::Rejection> {
-        let Path(path) = req.extract_parts::<Path<String>>().await?;
+        let Path(mut path) = req.extract_parts::<Path<String>>().await?;
         let vertex = req.uri().to_string().contains("vertex");
         if vertex && !CLEWDR_CONFIG.load().vertex.validate() {
             return Err(ClewdrError::BadRequest(
@@ -40,11 +45,21 @@ impl FromRequest<GeminiState> for GeminiPreprocess {
         if vertex {
             model = CLEWDR_CONFIG.load().vertex.model_id.to_owned().or(model)
         }
-        let Some(model) = model else {
+        let Some(mut model) = model else {
             return Err(ClewdrError::BadRequest(
                 "Model not found in path or vertex config".to_string(),
             ));
         };
+        // Resolve model aliases before routing and caching
+        if let Some((target, _)) = CLEWDR_CONFIG.load().model_alias(&model) {
+            debug!("Model alias: {} -> {}", model, target);
+            path = path.replacen(
+                &format!("models/{}", model),
+                &format!("models/{}", target),
+                1,
+            );
+            model = target;
+        }
         let query = req.extract_parts::<GeminiArgs>().await?;
         let ctx = GeminiContext {
             vertex,
@@ -76,7 +91,27 @@ impl FromRequest<GeminiState> for GeminiOaiPreprocess {
                 "Vertex is not configured".to_string(),
             ));
         }
-        let Json(body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+        let Json(mut body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+        // Resolve model aliases before routing and caching
+        if let Some((target, alias)) = CLEWDR_CONFIG.load().model_alias(&body.model) {
+            debug!("Model alias: {} -> {}", body.model, target);
+            body.model = target;
+            match alias.thinking {
+                Some(true) => {
+                    body.thinking.get_or_insert_default();
+                }
+                Some(false) => {
+                    body.thinking = Some(Thinking {
+                        r#type: "disabled".to_string(),
+                        ..Default::default()
+                    })
+                }
+                None => {}
+            }
+            if let Some(max_tokens) = alias.max_tokens {
+                body.max_tokens = max_tokens;
+            }
+        }
         let model = body.model.to_owned();
         let stream = body.stream.unwrap_or_default();
         let ctx = GeminiContext {
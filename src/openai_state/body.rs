@@ -0,0 +1,172 @@
+use serde_json::{Value, json};
+
+use crate::{
+    error::ClewdrError,
+    types::claude_message::{
+        ContentBlock, CreateMessageParams, DocumentSource, MessageContent, Role, ToolChoice,
+    },
+};
+
+/// Converts a client request into a Chat Completions request
+///
+/// System prompts become system messages, tool calls and results become
+/// `tool_calls` and tool messages, and images become `image_url` parts.
+/// Thinking, server tools and non-text documents have no equivalent and are dropped.
+///
+/// # Arguments
+/// * `p` - The client request
+///
+/// # Returns
+/// * `Result<Value, ClewdrError>` - Body of the Chat Completions request, always streamed
+pub fn openai_body(p: &CreateMessageParams) -> Result<Value, ClewdrError> {
+    let mut messages = vec![];
+    let system = match p.system {
+        Some(Value::String(ref s)) => s.to_owned(),
+        Some(Value::Array(ref blocks)) => blocks
+            .iter()
+            .filter_map(|b| b["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    };
+    if !system.is_empty() {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for m in p.messages.iter() {
+        let role = match m.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        };
+        let blocks = match m.content {
+            MessageContent::Text { ref content } => {
+                messages.push(json!({ "role": role, "content": content }));
+                continue;
+            }
+            MessageContent::Blocks { ref content } => content,
+        };
+        let mut parts = vec![];
+        let mut tool_calls = vec![];
+        for block in blocks {
+            match block {
+                ContentBlock::Text { text, .. } => {
+                    parts.push(json!({ "type": "text", "text": text }))
+                }
+                ContentBlock::Image { source } => {
+                    let url = match source.url {
+                        Some(ref url) if source.type_ == "url" => url.to_owned(),
+                        _ => format!("data:{};base64,{}", source.media_type, source.data),
+                    };
+                    parts.push(json!({ "type": "image_url", "image_url": { "url": url } }))
+                }
+                ContentBlock::ImageUrl { image_url } => {
+                    parts.push(json!({ "type": "image_url", "image_url": image_url }))
+                }
+                ContentBlock::Document {
+                    source: DocumentSource::Text { data, .. },
+                    title,
+                    ..
+                } => {
+                    let text = match title {
+                        Some(title) => format!("{}\n\n{}", title, data),
+                        None => data.to_owned(),
+                    };
+                    parts.push(json!({ "type": "text", "text": text }))
+                }
+                ContentBlock::ToolUse { id, name, input } => tool_calls.push(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": { "name": name, "arguments": input.to_string() },
+                })),
+                // tool results are messages of their own, following the call
+                ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                } => messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": tool_use_id,
+                    "content": content,
+                })),
+                _ => {}
+            }
+        }
+        if parts.is_empty() && tool_calls.is_empty() {
+            continue;
+        }
+        // only user messages may hold images
+        let content = if m.role == Role::User && parts.iter().any(|p| p["type"] != "text") {
+            Value::Array(parts)
+        } else {
+            let text = parts
+                .iter()
+                .filter_map(|p| p["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            json!(text)
+        };
+        let mut message = json!({ "role": role, "content": content });
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = Value::Array(tool_calls);
+        }
+        messages.push(message);
+    }
+
+    let mut body = json!({
+        "model": p.model,
+        "messages": messages,
+        "max_tokens": p.max_tokens,
+        "stream": true,
+        "stream_options": { "include_usage": true },
+    });
+    if let Some(temperature) = p.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = p.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    let stop = p
+        .stop_sequences
+        .iter()
+        .chain(p.stop.iter())
+        .flatten()
+        .collect::<Vec<_>>();
+    if !stop.is_empty() {
+        body["stop"] = json!(stop);
+    }
+    // server tools such as web search only exist on Anthropic
+    let tools = p
+        .tools
+        .iter()
+        .flatten()
+        .filter(|t| t.type_.as_deref().is_none_or(|t| t == "custom"))
+        .map(|t| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": t.name,
+                    "description": t.description,
+                    "parameters": t.input_schema,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    if !tools.is_empty() {
+        body["tools"] = Value::Array(tools);
+        if let Some(ref choice) = p.tool_choice {
+            body["tool_choice"] = match choice {
+                ToolChoice::Auto => json!("auto"),
+                ToolChoice::Any => json!("required"),
+                ToolChoice::Tool { name } => {
+                    json!({ "type": "function", "function": { "name": name } })
+                }
+            };
+        }
+    }
+    if let Some(ref format) = p.response_format {
+        body["response_format"] = serde_json::to_value(format)?;
+    }
+    if let Some(user_id) = p.metadata.as_ref().and_then(|m| m.fields.get("user_id")) {
+        body["user"] = json!(user_id);
+    }
+    Ok(body)
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{LazyLock, Mutex},
+};
+
+use axum::{
+    body::Body,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use colored::Colorize;
+use futures::Stream;
+use rquest::{Client, ClientBuilder, StatusCode, header::AUTHORIZATION};
+use serde_json::Value;
+use tokio::spawn;
+use tracing::{Instrument, Level, error, info, span, warn};
+
+use crate::{
+    claude_body::events::events_response,
+    config::{CLEWDR_CONFIG, OpenAiKey, OpenAiProvider},
+    error::{CheckOpenAIErr, ClewdrError, RetryClass},
+    services::cache::{CACHE, GetHashKey},
+    types::claude_message::CreateMessageParams,
+    utils::{print_out_json, retry::Backoff},
+};
+
+pub mod body;
+pub mod response;
+
+static DUMMY_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+/// Rotation and cooldowns of the provider keys, shared by all requests
+#[derive(Default)]
+struct KeyPool {
+    /// Index of the next key of each provider
+    next: HashMap<String, usize>,
+    /// Time until which a key is skipped, as a Unix timestamp
+    cooldown: HashMap<OpenAiKey, i64>,
+}
+
+static KEY_POOL: LazyLock<Mutex<KeyPool>> = LazyLock::new(Default::default);
+
+/// State of a request served by an OpenAI compatible provider
+#[derive(Clone)]
+pub struct OpenAiState {
+    pub provider: OpenAiProvider,
+    pub key: Option<OpenAiKey>,
+    pub stream: bool,
+    pub client: Client,
+    pub cache_key: Option<(u64, usize)>,
+}
+
+impl OpenAiState {
+    /// Create a new OpenAiState instance
+    ///
+    /// # Arguments
+    /// * `provider` - The provider serving the request
+    /// * `stream` - Whether the client requested a stream
+    pub fn new(provider: OpenAiProvider, stream: bool) -> Self {
+        OpenAiState {
+            provider,
+            key: None,
+            stream,
+            client: DUMMY_CLIENT.to_owned(),
+            cache_key: None,
+        }
+    }
+
+    /// Takes the next key of the provider that is not cooling down
+    /// Builds a new client with the newest proxy configuration
+    pub fn request_key(&mut self) -> Result<(), ClewdrError> {
+        if !self.provider.keys.is_empty() {
+            let now = chrono::Utc::now().timestamp();
+            let mut pool = KEY_POOL.lock().map_err(|_| ClewdrError::UnexpectedNone)?;
+            let keys = &self.provider.keys;
+            let start = pool
+                .next
+                .get(&self.provider.name)
+                .copied()
+                .unwrap_or_default();
+            let index = (0..keys.len())
+                .map(|i| (start + i) % keys.len())
+                .find(|&i| pool.cooldown.get(&keys[i]).is_none_or(|&t| t <= now))
+                .ok_or(ClewdrError::NoKeyAvailable)?;
+            pool.next.insert(self.provider.name.to_owned(), index + 1);
+            self.key = Some(keys[index].to_owned());
+        }
+        let client = ClientBuilder::new();
+        let client = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
+            client.proxy(proxy)
+        } else {
+            client
+        };
+        self.client = client.build()?;
+        Ok(())
+    }
+
+    /// Sends a request to the Chat Completions API of the provider
+    ///
+    /// # Arguments
+    /// * `body` - Body of the Chat Completions request
+    ///
+    /// # Returns
+    /// * `Result<rquest::Response, ClewdrError>` - The successful response
+    pub async fn send_chat(&mut self, body: &Value) -> Result<rquest::Response, ClewdrError> {
+        // a retry on the same key keeps it
+        if self.key.is_none() {
+            self.request_key()?;
+        }
+        let mut req = self.client.post(self.provider.endpoint()).json(body);
+        if let Some(ref key) = self.key {
+            info!("[KEY] {}", key.ellipse().green());
+            req = req.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        let res = req.send().await?.check_openai().await?;
+        Ok(res)
+    }
+
+    /// Sends the request, retrying failures following `openai_retry`
+    ///
+    /// Transient errors are retried on the same key, rate limited or rejected keys
+    /// cool down, and errors caused by the request fail immediately.
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    ///
+    /// # Returns
+    /// * `Result<Response, ClewdrError>` - The response converted to the Claude format
+    pub async fn try_chat(&mut self, p: CreateMessageParams) -> Result<Response, ClewdrError> {
+        let body = body::openai_body(&p)?;
+        print_out_json(&body, "openai_req.json");
+        let policy = CLEWDR_CONFIG.load().openai_retry.to_owned();
+        let mut backoff = Backoff::new(policy.to_owned());
+        // state holding the key to retry on, and the retries made on it
+        let mut same_key: Option<OpenAiState> = None;
+        let mut same_retries = 0;
+        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+            if i > 0 {
+                info!("[RETRY] attempt: {}", i.to_string().green());
+            }
+            let mut state = same_key.take().unwrap_or_else(|| self.to_owned());
+
+            match state.send_chat(&body).await {
+                Ok(res) => return Ok(state.transform_response(res.bytes_stream(), p.model).await),
+                Err(e) => {
+                    if let Some(ref key) = state.key {
+                        error!("[{}] {}", key.ellipse().green(), e);
+                    } else {
+                        error!("[{}] {}", state.provider.name.green(), e);
+                    }
+                    match e.retry_class() {
+                        RetryClass::Fatal => return Err(e),
+                        RetryClass::SameCredential
+                            if same_retries < policy.same_credential_retries =>
+                        {
+                            same_retries += 1;
+                            same_key = Some(state);
+                        }
+                        _ => {
+                            same_retries = 0;
+                            if let Some(key) = state.key.take() {
+                                // the key stays in the config, rejected keys only cool down
+                                if matches!(
+                                    e,
+                                    ClewdrError::ClaudeHttpError(StatusCode::UNAUTHORIZED, _)
+                                ) {
+                                    warn!(
+                                        "Key rejected by {}: {}",
+                                        state.provider.name,
+                                        key.ellipse()
+                                    );
+                                }
+                                if let Ok(mut pool) = KEY_POOL.lock() {
+                                    pool.cooldown.insert(key, policy.cooldown_until());
+                                }
+                            }
+                        }
+                    }
+                    if !backoff.wait().await {
+                        error!("Retry deadline exceeded");
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        error!("Max retries exceeded");
+        Err(ClewdrError::TooManyRetries)
+    }
+
+    /// Tries to answer the request from the cache
+    /// On a miss, `cache_response` requests are sent in the background to fill it
+    ///
+    /// # Arguments
+    /// * `p` - The client request
+    ///
+    /// # Returns
+    /// * `Option<Response>` - The cached response, if any
+    pub async fn try_from_cache(&self, p: &CreateMessageParams) -> Option<Response> {
+        let mut hasher = DefaultHasher::new();
+        p.get_hash().hash(&mut hasher);
+        self.provider.name.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(stream) = CACHE.pop(key) {
+            info!("[CACHE] found response for key: {}", key);
+            return Some(self.transform_response(stream, p.model.to_owned()).await);
+        }
+        for id in 0..CLEWDR_CONFIG.load().cache_response {
+            let mut state = self.to_owned();
+            state.cache_key = Some((key, id));
+            let p = p.to_owned();
+            let cache_span = span!(Level::ERROR, "cache");
+            spawn(async move { state.try_chat(p).instrument(cache_span).await });
+        }
+        None
+    }
+
+    /// Converts the stream of the provider into a Claude API response
+    ///
+    /// # Arguments
+    /// * `input` - The Chat Completions stream
+    /// * `model` - Model name reported in the message
+    ///
+    /// # Returns
+    /// * `Response` - An SSE stream of Claude events, or a JSON message if not streaming
+    pub async fn transform_response(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+        model: String,
+    ) -> Response {
+        // response is used for caching, the raw stream is converted when popped
+        if let Some((key, id)) = self.cache_key {
+            CACHE.push(input, key, id);
+            // return whatever, not used
+            return Body::empty().into_response();
+        }
+        let events = response::openai_to_events(input, model);
+        events_response(events, self.stream).await
+    }
+}
@@ -0,0 +1,117 @@
+use async_stream::stream;
+use bytes::Bytes;
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, pin_mut};
+use serde_json::Value;
+
+use crate::{
+    claude_body::events::EventBuilder,
+    types::claude_message::{StopReason, StreamError, StreamEvent},
+};
+
+/// Converts a Chat Completions stream into Claude API stream events
+///
+/// # Arguments
+/// * `input` - The SSE stream of the provider
+/// * `model` - Model name reported in the message start event
+///
+/// # Returns
+/// The events of the message, ending with `message_stop` or an error event
+pub fn openai_to_events(
+    input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    model: String,
+) -> impl Stream<Item = StreamEvent> + Send + 'static {
+    stream! {
+        let mut builder = EventBuilder::new(model);
+        let input = input.eventsource();
+        pin_mut!(input);
+        let mut error = None;
+        while let Some(event) = input.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+            };
+            if event.data.trim() == "[DONE]" {
+                break;
+            }
+            let Ok(chunk) = serde_json::from_str::<Value>(&event.data) else {
+                continue;
+            };
+            if !chunk["error"].is_null() {
+                error = Some(
+                    chunk["error"]["message"]
+                        .as_str()
+                        .map(String::from)
+                        .unwrap_or_else(|| chunk["error"].to_string()),
+                );
+                break;
+            }
+            for e in chunk_events(&mut builder, &chunk) {
+                yield e;
+            }
+        }
+        match error {
+            Some(message) => yield StreamEvent::Error {
+                error: StreamError {
+                    type_: "api_error".to_string(),
+                    message,
+                },
+            },
+            None => {
+                for e in builder.finish() {
+                    yield e;
+                }
+            }
+        }
+    }
+}
+
+/// Converts a Chat Completions chunk into Claude API stream events
+///
+/// # Arguments
+/// * `builder` - Builder of the message
+/// * `chunk` - Data of the chunk
+///
+/// # Returns
+/// The events of the deltas in the chunk
+fn chunk_events(builder: &mut EventBuilder, chunk: &Value) -> Vec<StreamEvent> {
+    let mut events = vec![];
+    if let Some(usage) = chunk["usage"].as_object() {
+        let tokens = |k: &str| usage.get(k).and_then(|v| v.as_u64()).unwrap_or_default() as u32;
+        builder.usage(tokens("prompt_tokens"), tokens("completion_tokens"));
+    }
+    let choice = &chunk["choices"][0];
+    let delta = &choice["delta"];
+    // reasoning models of vLLM and DeepSeek report thinking apart from the content
+    if let Some(thinking) = delta["reasoning_content"]
+        .as_str()
+        .or(delta["reasoning"].as_str())
+    {
+        events.extend(builder.thinking(thinking));
+    }
+    if let Some(text) = delta["content"].as_str() {
+        events.extend(builder.text(text));
+    }
+    for call in delta["tool_calls"].as_array().into_iter().flatten() {
+        let key = call["index"].as_u64().unwrap_or_default().to_string();
+        if let Some(name) = call["function"]["name"].as_str() {
+            events.extend(builder.tool_use(&key, call["id"].as_str(), name));
+        }
+        if let Some(args) = call["function"]["arguments"].as_str() {
+            events.extend(builder.tool_input(&key, args));
+        }
+    }
+    let stop = match choice["finish_reason"].as_str() {
+        Some("stop") => Some(StopReason::EndTurn),
+        Some("length") => Some(StopReason::MaxTokens),
+        Some("tool_calls") | Some("function_call") => Some(StopReason::ToolUse),
+        _ => None,
+    };
+    if let Some(stop) = stop {
+        builder.stop(stop);
+    }
+    events
+}
@@ -1,5 +1,5 @@
 use axum::{
-    Router,
+    Extension, Router,
     http::Method,
     middleware::{from_extractor, map_response},
     routing::{delete, get, post},
@@ -10,17 +10,18 @@ use tower_http::{cors::CorsLayer, services::ServeDir};
 
 use crate::{
     IS_DEBUG,
+    anthropic_state::AnthropicState,
     api::{
-        api_auth, api_claude, api_delete_cookie, api_delete_key, api_get_config, api_get_cookies,
-        api_get_keys, api_get_models, api_post_config, api_post_cookie, api_post_gemini,
-        api_post_gemini_oai, api_post_key, api_version,
+        api_auth, api_claude, api_count_tokens, api_delete_cookie, api_delete_key, api_get_config,
+        api_get_cookies, api_get_gemini_models, api_get_keys, api_get_models, api_post_config,
+        api_post_cookie, api_post_gemini, api_post_gemini_oai, api_post_key, api_version,
     },
     claude_state::ClaudeState,
-    config::CLEWDR_CONFIG,
+    config::{AnthropicKey, CLEWDR_CONFIG, GeminiKey},
     gemini_state::GeminiState,
     middleware::{
         RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth,
-        claude::{apply_stop_sequences, to_oai},
+        claude::{apply_regex_rules, apply_stop_sequences, to_oai},
     },
     services::{
         cookie_manager::{CookieEventSender, CookieManager},
@@ -34,6 +35,8 @@ pub struct RouterBuilder {
     cookie_event_sender: CookieEventSender,
     key_event_sender: KeyEventSender,
     gemini_state: GeminiState,
+    anthropic_key_event_sender: KeyEventSender<AnthropicKey>,
+    anthropic_state: AnthropicState,
     inner: Router,
 }
 
@@ -52,13 +55,18 @@ impl RouterBuilder {
     pub fn new() -> Self {
         let cookie_tx = CookieManager::start();
         let claude_state = ClaudeState::new(cookie_tx.to_owned());
-        let key_tx = KeyManager::start();
+        claude_state.clean_evicted_conversations();
+        let key_tx = KeyManager::<GeminiKey>::start();
         let gemini_state = GeminiState::new(key_tx.to_owned());
+        let anthropic_key_tx = KeyManager::<AnthropicKey>::start();
+        let anthropic_state = AnthropicState::new(anthropic_key_tx.to_owned());
         RouterBuilder {
             claude_state,
             cookie_event_sender: cookie_tx,
             key_event_sender: key_tx,
             gemini_state,
+            anthropic_key_event_sender: anthropic_key_tx,
+            anthropic_state,
             inner: Router::new(),
         }
     }
@@ -82,7 +90,7 @@ impl RouterBuilder {
             .layer(from_extractor::<RequireQueryKeyAuth>())
             .with_state(self.gemini_state.to_owned());
         let router_oai = Router::new()
-            .route("/gemini/models", get(api_get_models))
+            .route("/gemini/models", get(api_get_gemini_models))
             .route("/gemini/chat/completions", post(api_post_gemini_oai))
             .route("/gemini/vertex/chat/completions", post(api_post_gemini_oai))
             .layer(from_extractor::<RequireBearerAuth>())
@@ -96,9 +104,13 @@ impl RouterBuilder {
     fn route_claude_endpoints(mut self) -> Self {
         let router = Router::new()
             .route("/v1/messages", post(api_claude))
+            .route("/v1/messages/count_tokens", post(api_count_tokens))
             .layer(
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireXApiKeyAuth>())
+                    .layer(Extension(self.anthropic_state.to_owned()))
+                    .layer(Extension(self.gemini_state.to_owned()))
+                    .layer(map_response(apply_regex_rules))
                     .layer(map_response(apply_stop_sequences)),
             )
             .with_state(self.claude_state.to_owned().with_claude_format());
@@ -113,9 +125,19 @@ impl RouterBuilder {
             .route("/cookie", delete(api_delete_cookie).post(api_post_cookie))
             .with_state(self.cookie_event_sender.to_owned());
         let key_router = Router::new()
-            .route("/key", post(api_post_key).delete(api_delete_key))
-            .route("/keys", get(api_get_keys))
+            .route(
+                "/key",
+                post(api_post_key::<GeminiKey>).delete(api_delete_key::<GeminiKey>),
+            )
+            .route("/keys", get(api_get_keys::<GeminiKey>))
             .with_state(self.key_event_sender.to_owned());
+        let anthropic_key_router = Router::new()
+            .route(
+                "/anthropic_key",
+                post(api_post_key::<AnthropicKey>).delete(api_delete_key::<AnthropicKey>),
+            )
+            .route("/anthropic_keys", get(api_get_keys::<AnthropicKey>))
+            .with_state(self.anthropic_key_event_sender.to_owned());
         let admin_router = Router::new()
             .route("/auth", get(api_auth))
             .route("/config", get(api_get_config).put(api_post_config));
@@ -124,6 +146,7 @@ impl RouterBuilder {
                 "/api",
                 cookie_router
                     .merge(key_router)
+                    .merge(anthropic_key_router)
                     .merge(admin_router)
                     .layer(from_extractor::<RequireAdminAuth>()),
             )
@@ -141,7 +164,10 @@ impl RouterBuilder {
                 .layer(
                     ServiceBuilder::new()
                         .layer(from_extractor::<RequireBearerAuth>())
+                        .layer(Extension(self.anthropic_state.to_owned()))
+                        .layer(Extension(self.gemini_state.to_owned()))
                         .layer(map_response(to_oai))
+                        .layer(map_response(apply_regex_rules))
                         .layer(map_response(apply_stop_sequences)),
                 )
                 .with_state(self.claude_state.to_owned().with_openai_format());
@@ -205,6 +205,8 @@ struct ClaudeRequestKeys<'a> {
     pub thinking: bool,
     /// Top-k sampling
     pub top_k: Option<u32>,
+    /// Whether web search is requested
+    pub web_search: bool,
 }
 
 #[derive(Hash, Debug)]
@@ -329,8 +331,10 @@ impl<'a> From<&'a CreateMessageParams> for ClaudeRequestKeys<'a> {
             model: params.model.to_owned(),
             system: params.system.as_ref(),
             stop_sequences: params.stop_sequences.to_owned(),
-            thinking: params.thinking.is_some(),
+            thinking: params.thinking.as_ref().is_some_and(|t| t.is_enabled()),
             top_k: params.top_k,
+            web_search: params.tools.iter().flatten().any(|t| t.is_web_search())
+                || params.web_search_options.is_some(),
         }
     }
 }
@@ -0,0 +1,144 @@
+use moka::sync::Cache;
+use serde_json::Value;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{LazyLock, Mutex},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+use crate::{
+    config::CookieStatus,
+    types::claude_message::{ContentBlock, Message, MessageContent, Role},
+};
+
+/// Global store mapping client conversations to claude.ai conversations
+pub static CONVERSATIONS: LazyLock<ConversationStore> = LazyLock::new(ConversationStore::default);
+
+/// A claude.ai conversation that can be continued
+#[derive(Clone, Debug)]
+pub struct ConversationState {
+    /// Cookie that owns the conversation
+    pub cookie: CookieStatus,
+    /// Organization of the conversation
+    pub org_uuid: String,
+    /// UUID of the conversation
+    pub conv_uuid: String,
+    /// UUID of the last assistant message, used as parent of the next turn
+    pub parent_message_uuid: String,
+}
+
+/// Store of reusable conversations, keyed by a hash of the client history
+pub struct ConversationStore {
+    moka: Cache<u64, ConversationState>,
+    /// Receiver of the conversations evicted from the store
+    evicted: Mutex<Option<UnboundedReceiver<ConversationState>>>,
+}
+
+impl Default for ConversationStore {
+    /// Creates a store holding at most 1000 conversations for one hour since last use
+    fn default() -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            moka: Cache::builder()
+                .max_capacity(1000)
+                .time_to_idle(std::time::Duration::from_secs(60 * 60))
+                .eviction_listener(move |_, state, cause| {
+                    if cause.was_evicted() {
+                        let _ = tx.send(state);
+                    }
+                })
+                .build(),
+            evicted: Mutex::new(Some(rx)),
+        }
+    }
+}
+
+impl ConversationStore {
+    /// Finds the conversation whose history matches the given key
+    pub fn get(&self, key: u64) -> Option<ConversationState> {
+        self.moka.get(&key)
+    }
+
+    /// Records a conversation under the key of its history
+    pub fn insert(&self, key: u64, state: ConversationState) {
+        self.moka.insert(key, state);
+    }
+
+    /// Checks if a claude.ai conversation is still recorded under any history
+    pub fn contains(&self, conv_uuid: &str) -> bool {
+        self.moka.iter().any(|(_, s)| s.conv_uuid == conv_uuid)
+    }
+
+    /// Takes the receiver of evicted conversations, which can only be taken once
+    pub fn take_evicted(&self) -> Option<UnboundedReceiver<ConversationState>> {
+        self.evicted.lock().ok()?.take()
+    }
+}
+
+/// Normalized form of a message used for hashing
+/// Text blocks are trimmed and joined, so the same history hashes the same
+/// regardless of whether the client sends plain text or content blocks
+#[derive(Hash)]
+struct NormalizedMessage<'a> {
+    role: Role,
+    text: String,
+    others: Vec<&'a ContentBlock>,
+}
+
+impl<'a> From<&'a Message> for NormalizedMessage<'a> {
+    fn from(msg: &'a Message) -> Self {
+        match msg.content {
+            MessageContent::Text { ref content } => NormalizedMessage {
+                role: msg.role,
+                text: content.trim().to_string(),
+                others: vec![],
+            },
+            MessageContent::Blocks { ref content } => {
+                let text = content
+                    .iter()
+                    .filter_map(|b| match b {
+                        ContentBlock::Text { text, .. } => Some(text.trim()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                // thinking echoed back by clients is not part of the recorded history
+                let others = content
+                    .iter()
+                    .filter(|b| {
+                        !matches!(
+                            b,
+                            ContentBlock::Text { .. }
+                                | ContentBlock::Thinking { .. }
+                                | ContentBlock::RedactedThinking { .. }
+                        )
+                    })
+                    .collect();
+                NormalizedMessage {
+                    role: msg.role,
+                    text,
+                    others,
+                }
+            }
+        }
+    }
+}
+
+/// Computes the key of a client conversation history
+///
+/// # Arguments
+/// * `model` - Model of the request
+/// * `system` - System prompt of the request
+/// * `msgs` - Messages of the history
+///
+/// # Returns
+/// * `u64` - The hash of the history
+pub fn conversation_key(model: &str, system: Option<&Value>, msgs: &[Message]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    model.hash(&mut hasher);
+    system.hash(&mut hasher);
+    for m in msgs {
+        NormalizedMessage::from(m).hash(&mut hasher);
+    }
+    hasher.finish()
+}
@@ -33,6 +33,11 @@ pub enum CookieEvent {
     CheckReset,
     /// Request to get a Cookie
     Request(oneshot::Sender<Result<CookieStatus, ClewdrError>>),
+    /// Request a specific Cookie, if it is still valid
+    RequestExact(
+        CookieStatus,
+        oneshot::Sender<Result<CookieStatus, ClewdrError>>,
+    ),
     /// Get all Cookie status information
     GetStatus(oneshot::Sender<CookieStatusInfo>),
     /// Delete a Cookie
@@ -63,6 +68,22 @@ impl CookieEventSender {
         rx.await?
     }
 
+    /// Request a specific cookie from the cookie manager
+    /// Used to continue a conversation owned by that cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to request
+    ///
+    /// # Returns
+    /// * `Result<CookieStatus, ClewdrError>` - Cookie if it is still valid, error otherwise
+    pub async fn request_exact(&self, cookie: CookieStatus) -> Result<CookieStatus, ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(CookieEvent::RequestExact(cookie, tx))
+            .await?;
+        rx.await?
+    }
+
     /// Return a cookie to the cookie manager with optional reason
     ///
     /// # Arguments
@@ -236,6 +257,23 @@ impl CookieManager {
         Ok(cookie)
     }
 
+    /// Dispatches a specific cookie for use
+    /// Only succeeds if the cookie is in the valid collection
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to dispatch
+    ///
+    /// # Returns
+    /// * `Result<CookieStatus, ClewdrError>` - The cookie if valid, error otherwise
+    fn dispatch_exact(&mut self, cookie: CookieStatus) -> Result<CookieStatus, ClewdrError> {
+        self.reset();
+        self.valid
+            .iter()
+            .find(|c| **c == cookie)
+            .cloned()
+            .ok_or(ClewdrError::NoCookieAvailable)
+    }
+
     /// Collects a returned cookie and processes it based on the return reason
     ///
     /// # Arguments
@@ -400,6 +438,12 @@ impl CookieManager {
                         error!("Failed to send cookie");
                     });
                 }
+                CookieEvent::RequestExact(cookie, sender) => {
+                    let cookie = self.dispatch_exact(cookie);
+                    sender.send(cookie).unwrap_or_else(|_| {
+                        error!("Failed to send cookie");
+                    });
+                }
                 CookieEvent::GetStatus(sender) => {
                     let status_info = self.report();
                     sender.send(status_info).unwrap_or_else(|_| {
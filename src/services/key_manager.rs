@@ -1,6 +1,6 @@
 use colored::Colorize;
 use serde::Serialize;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use tokio::{
     spawn,
     sync::{mpsc, oneshot},
@@ -8,48 +8,50 @@ use tokio::{
 use tracing::{error, info};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, KeyStatus},
+    config::{CLEWDR_CONFIG, ClewdrConfig, GeminiKey, KeyStatus, PoolKey},
     error::ClewdrError,
 };
 
 #[derive(Debug, Serialize, Clone)]
-pub struct KeyStatusInfo {
-    pub valid: Vec<KeyStatus>,
+pub struct KeyStatusInfo<K: PoolKey = GeminiKey> {
+    pub valid: Vec<KeyStatus<K>>,
 }
 
 /// Unified event enum for key management
 #[derive(Debug)]
-pub enum KeyEvent {
-    /// Return a Key
-    Return(KeyStatus),
+pub enum KeyEvent<K: PoolKey = GeminiKey> {
+    /// Return a Key, with the time until which it cools down
+    Return(KeyStatus<K>, Option<i64>),
     /// Submit a new Key
-    Submit(KeyStatus),
+    Submit(KeyStatus<K>),
     /// Request to get a Key
-    Request(oneshot::Sender<Result<KeyStatus, ClewdrError>>),
+    Request(oneshot::Sender<Result<KeyStatus<K>, ClewdrError>>),
     /// Get all Key status information
-    GetStatus(oneshot::Sender<KeyStatusInfo>),
+    GetStatus(oneshot::Sender<KeyStatusInfo<K>>),
     /// Delete a Key
-    Delete(KeyStatus, oneshot::Sender<Result<(), ClewdrError>>),
+    Delete(KeyStatus<K>, oneshot::Sender<Result<(), ClewdrError>>),
 }
 
 /// Key manager that handles key distribution and status tracking
-pub struct KeyManager {
-    valid: VecDeque<KeyStatus>,
-    event_rx: mpsc::Receiver<KeyEvent>, // Event receiver for incoming events
+/// One manager runs for each pool of keys
+pub struct KeyManager<K: PoolKey = GeminiKey> {
+    valid: VecDeque<KeyStatus<K>>,
+    cooldowns: HashMap<KeyStatus<K>, i64>, // Rate limited keys and their reset times
+    event_rx: mpsc::Receiver<KeyEvent<K>>, // Event receiver for incoming events
 }
 
 /// Event sender interface provided for external components to interact with the key manager
 #[derive(Clone)]
-pub struct KeyEventSender {
-    sender: mpsc::Sender<KeyEvent>,
+pub struct KeyEventSender<K: PoolKey = GeminiKey> {
+    sender: mpsc::Sender<KeyEvent<K>>,
 }
 
-impl KeyEventSender {
+impl<K: PoolKey> KeyEventSender<K> {
     /// Request a key from the key manager
     ///
     /// # Returns
     /// * `Result<KeyStatus, ClewdrError>` - Key if available, error otherwise
-    pub async fn request(&self) -> Result<KeyStatus, ClewdrError> {
+    pub async fn request(&self) -> Result<KeyStatus<K>, ClewdrError> {
         let (tx, rx) = oneshot::channel();
         self.sender.send(KeyEvent::Request(tx)).await?;
         rx.await?
@@ -59,11 +61,16 @@ impl KeyEventSender {
     ///
     /// # Arguments
     /// * `key` - The key to return
+    /// * `reset_time` - Unix timestamp until which the key is not dispatched, if rate limited
     ///
     /// # Returns
     /// Result indicating success or send error
-    pub async fn return_key(&self, key: KeyStatus) -> Result<(), mpsc::error::SendError<KeyEvent>> {
-        self.sender.send(KeyEvent::Return(key)).await
+    pub async fn return_key(
+        &self,
+        key: KeyStatus<K>,
+        reset_time: Option<i64>,
+    ) -> Result<(), mpsc::error::SendError<KeyEvent<K>>> {
+        self.sender.send(KeyEvent::Return(key, reset_time)).await
     }
 
     /// Submit a new key to the key manager
@@ -73,7 +80,10 @@ impl KeyEventSender {
     ///
     /// # Returns
     /// Result indicating success or send error
-    pub async fn submit(&self, key: KeyStatus) -> Result<(), mpsc::error::SendError<KeyEvent>> {
+    pub async fn submit(
+        &self,
+        key: KeyStatus<K>,
+    ) -> Result<(), mpsc::error::SendError<KeyEvent<K>>> {
         self.sender.send(KeyEvent::Submit(key)).await
     }
 
@@ -81,7 +91,7 @@ impl KeyEventSender {
     ///
     /// # Returns
     /// * `Result<KeyStatusInfo, ClewdrError>` - Status information about all keys
-    pub async fn get_status(&self) -> Result<KeyStatusInfo, ClewdrError> {
+    pub async fn get_status(&self) -> Result<KeyStatusInfo<K>, ClewdrError> {
         let (tx, rx) = oneshot::channel();
         self.sender.send(KeyEvent::GetStatus(tx)).await?;
         Ok(rx.await?)
@@ -94,14 +104,14 @@ impl KeyEventSender {
     ///
     /// # Returns
     /// * `Result<(), ClewdrError>` - Success or error
-    pub async fn delete_key(&self, key: KeyStatus) -> Result<(), ClewdrError> {
+    pub async fn delete_key(&self, key: KeyStatus<K>) -> Result<(), ClewdrError> {
         let (tx, rx) = oneshot::channel();
         self.sender.send(KeyEvent::Delete(key, tx)).await?;
         rx.await?
     }
 }
 
-impl KeyManager {
+impl<K: PoolKey> KeyManager<K> {
     /// Starts the key manager and returns an event sender
     ///
     /// Initializes key collections, creates event channels and queues,
@@ -109,15 +119,19 @@ impl KeyManager {
     ///
     /// # Returns
     /// * `KeyEventSender` - Event sender for interacting with the key manager
-    pub fn start() -> KeyEventSender {
-        let valid = VecDeque::from_iter(CLEWDR_CONFIG.load().gemini_keys.iter().cloned());
+    pub fn start() -> KeyEventSender<K> {
+        let valid = VecDeque::from_iter(K::pool(&CLEWDR_CONFIG.load()).iter().cloned());
 
         // Create event channel
         let (event_tx, event_rx) = mpsc::channel(100);
 
         let sender = KeyEventSender { sender: event_tx };
 
-        let manager = Self { valid, event_rx };
+        let manager = Self {
+            valid,
+            cooldowns: HashMap::new(),
+            event_rx,
+        };
         // Start event processor
         spawn(manager.run());
 
@@ -127,7 +141,11 @@ impl KeyManager {
     /// Logs the current state of key collections
     /// Displays count of valid keys
     fn log(&self) {
-        info!("Valid Keys: {}", self.valid.len().to_string().green());
+        info!(
+            "Valid {} Keys: {}",
+            K::POOL,
+            self.valid.len().to_string().green()
+        );
     }
 
     /// Saves the current state of keys to the configuration
@@ -135,7 +153,7 @@ impl KeyManager {
     fn save(&mut self) {
         CLEWDR_CONFIG.rcu(|config| {
             let mut config = ClewdrConfig::clone(config);
-            config.gemini_keys = self.valid.iter().cloned().collect();
+            *K::pool_mut(&mut config) = self.valid.iter().cloned().collect();
             config
         });
         CLEWDR_CONFIG.load().save().unwrap_or_else(|e| {
@@ -148,10 +166,18 @@ impl KeyManager {
     ///
     /// # Returns
     /// * `Result<KeyStatus, ClewdrError>` - A key if available, error otherwise
-    fn dispatch(&mut self) -> Result<KeyStatus, ClewdrError> {
-        let key = self.valid.pop_front().ok_or(ClewdrError::NoKeyAvailable)?;
-        self.valid.push_back(key.to_owned());
-        Ok(key)
+    fn dispatch(&mut self) -> Result<KeyStatus<K>, ClewdrError> {
+        let now = chrono::Utc::now().timestamp();
+        self.cooldowns.retain(|_, reset| *reset > now);
+        // rotate past the keys cooling down
+        for _ in 0..self.valid.len() {
+            let key = self.valid.pop_front().ok_or(ClewdrError::NoKeyAvailable)?;
+            self.valid.push_back(key.to_owned());
+            if !self.cooldowns.contains_key(&key) {
+                return Ok(key);
+            }
+        }
+        Err(ClewdrError::NoKeyAvailable)
     }
 
     /// Accepts a new key into the valid collection
@@ -159,8 +185,8 @@ impl KeyManager {
     ///
     /// # Arguments
     /// * `key` - The new key to accept
-    fn accept(&mut self, key: KeyStatus) {
-        if CLEWDR_CONFIG.load().gemini_keys.contains(&key) {
+    fn accept(&mut self, key: KeyStatus<K>) {
+        if K::pool(&CLEWDR_CONFIG.load()).contains(&key) {
             info!("Key already exists");
             return;
         }
@@ -173,7 +199,7 @@ impl KeyManager {
     ///
     /// # Returns
     /// * `KeyStatusInfo` - Information about all key collections
-    fn report(&self) -> KeyStatusInfo {
+    fn report(&self) -> KeyStatusInfo<K> {
         KeyStatusInfo {
             valid: self.valid.iter().cloned().collect(),
         }
@@ -186,7 +212,7 @@ impl KeyManager {
     ///
     /// # Returns
     /// * `Result<(), ClewdrError>` - Success if found and deleted, error otherwise
-    fn delete(&mut self, key: KeyStatus) -> Result<(), ClewdrError> {
+    fn delete(&mut self, key: KeyStatus<K>) -> Result<(), ClewdrError> {
         let size_before = self.valid.len();
         self.valid.retain(|k| *k != key);
 
@@ -210,11 +236,13 @@ impl KeyManager {
         self.log();
         while let Some(event) = self.event_rx.recv().await {
             match event {
-                KeyEvent::Return(_key) => {
-                    // Key is automatically rotated in dispatch, no action needed for return
-                    // This is simplified compared to cookie manager as requested
-                    // info!("Key returned: {}", key.key);
-                    // TODO: Handle error if key is not in valid keys
+                KeyEvent::Return(key, reset_time) => {
+                    // Key is automatically rotated in dispatch,
+                    // only rate limited keys need to be recorded
+                    if let Some(reset_time) = reset_time {
+                        info!("Key cooling down: {}", key.key.ellipse().yellow());
+                        self.cooldowns.insert(key, reset_time);
+                    }
                 }
                 KeyEvent::Submit(key) => {
                     // Process submitted new key
@@ -1,4 +1,6 @@
 pub mod cache;
+pub mod conversation;
 pub mod cookie_manager;
+pub mod model_list;
 pub mod update;
 pub mod key_manager;
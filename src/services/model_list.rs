@@ -0,0 +1,151 @@
+use moka::sync::Cache;
+use serde_json::{Value, json};
+use std::{sync::LazyLock, time::Duration};
+
+use crate::config::CLEWDR_CONFIG;
+
+/// How long discovered models and model listings are kept
+const MODEL_LIST_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Models served when no account has reported its models yet
+const DEFAULT_CLAUDE_MODELS: [&str; 1] = ["claude-3-7-sonnet-20250219"];
+
+/// Models usable by Claude.ai accounts, collected during bootstrap
+/// A model expires if no account has reported it within the TTL
+pub static CLAUDE_MODELS: LazyLock<Cache<String, ()>> =
+    LazyLock::new(|| Cache::builder().time_to_live(MODEL_LIST_TTL).build());
+
+/// Model listing fetched from Google, shared by all requests
+pub static GEMINI_MODELS: LazyLock<Cache<(), Value>> =
+    LazyLock::new(|| Cache::builder().time_to_live(MODEL_LIST_TTL).build());
+
+/// Feature stores of the bootstrap response and the field holding each feature value
+const FEATURE_STORES: [(&str, &str); 2] = [
+    ("/statsig/values/dynamic_configs", "value"),
+    ("/growthbook/features", "defaultValue"),
+];
+
+/// Collects the models available to an account from its bootstrap response
+///
+/// Claude.ai configures the model selector with a feature of the bootstrap
+/// response, whose value has a `models` list of entries with a `model` field.
+/// The features are keyed by hashes, so every feature with such a list is read.
+///
+/// # Arguments
+/// * `bootstrap` - The bootstrap response of Claude.ai
+///
+/// # Returns
+/// The ids of the models found
+pub fn collect_claude_models(bootstrap: &Value) -> Vec<String> {
+    let mut models = FEATURE_STORES
+        .iter()
+        .filter_map(|(store, field)| Some((bootstrap.pointer(store)?.as_object()?, field)))
+        .flat_map(|(features, field)| features.values().map(move |f| &f[field]["models"]))
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(|m| m["model"].as_str())
+        .filter(|m| m.starts_with("claude-"))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    models.sort();
+    models.dedup();
+    models
+}
+
+/// Lists the Claude models that can be served, with `-thinking` variants
+/// Model aliases with an exact name and a Claude target are included,
+/// as are the models listed by OpenAI compatible providers
+///
+/// # Returns
+/// The sorted model ids
+pub fn claude_models() -> Vec<String> {
+    let mut models = CLAUDE_MODELS
+        .iter()
+        .map(|(m, _)| m.as_ref().to_owned())
+        .collect::<Vec<_>>();
+    if models.is_empty() {
+        models = DEFAULT_CLAUDE_MODELS.map(String::from).to_vec();
+    }
+    let thinking = models
+        .iter()
+        .map(|m| format!("{}-thinking", m))
+        .collect::<Vec<_>>();
+    models.extend(thinking);
+    let config = CLEWDR_CONFIG.load();
+    models.extend(
+        config
+            .model_aliases
+            .iter()
+            .filter(|a| !a.is_pattern() && a.target.starts_with("claude-"))
+            .map(|a| a.pattern.to_owned()),
+    );
+    // models of OpenAI compatible providers are served on the same endpoints
+    models.extend(
+        config
+            .openai_providers
+            .iter()
+            .flat_map(|p| p.models.iter().cloned()),
+    );
+    models.sort();
+    models.dedup();
+    models
+}
+
+/// Adds model aliases to a Gemini model listing in OpenAI format
+/// Aliases with an exact name are listed if their target is listed
+///
+/// # Arguments
+/// * `listing` - The model listing of Google
+///
+/// # Returns
+/// The listing with the aliases appended
+pub fn with_gemini_aliases(mut listing: Value) -> Value {
+    let Some(data) = listing["data"].as_array_mut() else {
+        return listing;
+    };
+    let listed = data
+        .iter()
+        .filter_map(|m| m["id"].as_str())
+        .map(|id| id.trim_start_matches("models/").to_string())
+        .collect::<Vec<_>>();
+    for alias in CLEWDR_CONFIG.load().model_aliases.iter() {
+        if alias.is_pattern() || !listed.contains(&alias.target) {
+            continue;
+        }
+        data.push(json!({
+            "id": alias.pattern,
+            "object": "model",
+            "owned_by": "clewdr",
+        }));
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::collect_claude_models;
+
+    #[test]
+    fn reads_models_of_the_selector_feature() {
+        let bootstrap = json!({
+            "account": {"memberships": [{"organization": {"model": "claude-not-a-model"}}]},
+            "statsig": {"values": {"dynamic_configs": {
+                "1234": {"value": {"models": [
+                    {"model": "claude-sonnet-4-20250514", "name": "Claude Sonnet 4"},
+                    {"model": "claude-opus-4-20250514", "name": "Claude Opus 4"},
+                ]}},
+                "5678": {"value": {"model": "claude-ignored"}},
+            }}},
+            "growthbook": {"features": {
+                "9abc": {"defaultValue": {"models": [{"model": "claude-sonnet-4-20250514"}]}},
+            }},
+        });
+        assert_eq!(
+            collect_claude_models(&bootstrap),
+            vec!["claude-opus-4-20250514", "claude-sonnet-4-20250514"]
+        );
+        assert!(collect_claude_models(&json!({})).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug)]
 pub struct RequiredMessageParams {
@@ -53,13 +53,71 @@ pub struct CreateMessageParams {
     /// Request metadata
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
+    /// Structured output format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// OpenAI style web search options, enables web search if present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_search_options: Option<serde_json::Value>,
+}
+
+/// OpenAI style `response_format` for structured output
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Plain text, no structured output
+    Text,
+    /// Any valid JSON object
+    JsonObject,
+    /// JSON validated against a schema
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+/// Schema definition of a `json_schema` response format
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JsonSchemaFormat {
+    /// Name of the schema
+    #[serde(default)]
+    pub name: String,
+    /// Description of the expected output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the expected output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    /// Whether the schema should be strictly followed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// Thinking mode in Claude API Request
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct Thinking {
-    budget_tokens: u64,
-    r#type: String,
+    /// Maximum number of tokens used for thinking, 0 for no limit
+    #[serde(default)]
+    pub budget_tokens: u64,
+    /// Either `enabled` or `disabled`
+    #[serde(default)]
+    pub r#type: String,
+}
+
+impl Thinking {
+    /// Whether thinking is requested
+    pub fn is_enabled(&self) -> bool {
+        self.r#type != "disabled"
+    }
+
+    /// Instruction appended to the prompt to keep thinking within the budget
+    /// Claude.ai has no budget setting, so the budget is passed as guidance
+    pub fn instruction(&self) -> Option<String> {
+        if !self.is_enabled() || self.budget_tokens == 0 {
+            return None;
+        }
+        Some(format!(
+            "Keep your thinking within about {} tokens before answering.",
+            self.budget_tokens
+        ))
+    }
 }
 
 impl From<RequiredMessageParams> for CreateMessageParams {
@@ -162,12 +220,49 @@ pub enum MessageContent {
 pub enum ContentBlock {
     /// Text content
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        /// Sources cited by the text, such as web search results
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        citations: Option<Vec<serde_json::Value>>,
+    },
     /// Image content
     #[serde(rename = "image")]
     Image { source: ImageSource },
     #[serde(rename = "image_url")]
     ImageUrl { image_url: ImageUrl },
+    /// Document content, such as a PDF or plain text
+    #[serde(rename = "document")]
+    Document {
+        source: DocumentSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        context: Option<String>,
+    },
+    /// Extended thinking content
+    #[serde(rename = "thinking")]
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    /// Thinking content redacted by the safety system
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
+    /// Server tool call made by Claude, such as a web search
+    #[serde(rename = "server_tool_use")]
+    ServerToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// Results of a web search
+    #[serde(rename = "web_search_tool_result")]
+    WebSearchToolResult {
+        tool_use_id: String,
+        content: serde_json::Value,
+    },
     /// Tool use content
     #[serde(rename = "tool_use")]
     ToolUse {
@@ -179,10 +274,27 @@ pub enum ContentBlock {
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
+        #[serde(default, deserialize_with = "tool_result_content")]
         content: String,
     },
 }
 
+/// Reads the content of a tool result, given as text or as content blocks
+fn tool_result_content<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(text) => text,
+        serde_json::Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| b["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    })
+}
+
 /// Source of an image
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct ImageSource {
@@ -190,9 +302,34 @@ pub struct ImageSource {
     #[serde(rename = "type")]
     pub type_: String,
     /// Media type of the image
+    #[serde(default)]
     pub media_type: String,
     /// Base64-encoded image data
+    #[serde(default)]
     pub data: String,
+    /// URL of the image, for `url` sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// Source of a document
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DocumentSource {
+    /// Base64-encoded file, such as a PDF
+    Base64 { media_type: String, data: String },
+    /// Plain text document
+    Text {
+        #[serde(default = "default_text_media_type")]
+        media_type: String,
+        data: String,
+    },
+    /// Document hosted at a URL
+    Url { url: String },
+}
+
+fn default_text_media_type() -> String {
+    "text/plain".to_string()
 }
 
 // oai image
@@ -204,13 +341,35 @@ pub struct ImageUrl {
 /// Tool definition
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Tool {
+    /// Type of a server tool, such as `web_search_20250305`
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
     /// Name of the tool
     pub name: String,
     /// Description of the tool
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    /// JSON schema for tool input
+    /// JSON schema for tool input, absent for server tools
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
     pub input_schema: serde_json::Value,
+    /// Maximum number of searches of the web search tool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    /// Domains the web search tool is limited to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_domains: Option<Vec<String>>,
+    /// Domains the web search tool never uses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocked_domains: Option<Vec<String>>,
+}
+
+impl Tool {
+    /// Whether the tool is the Anthropic `web_search` server tool
+    pub fn is_web_search(&self) -> bool {
+        self.type_
+            .as_deref()
+            .is_some_and(|t| t.starts_with("web_search"))
+    }
 }
 
 /// Tool choice configuration
@@ -237,7 +396,7 @@ pub struct Metadata {
 }
 
 /// Response from creating a message
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateMessageResponse {
     /// Content blocks in the response
     pub content: Vec<ContentBlock>,
@@ -310,7 +469,10 @@ impl Message {
 impl ContentBlock {
     /// Create a new text block
     pub fn text(text: impl Into<String>) -> Self {
-        Self::Text { text: text.into() }
+        Self::Text {
+            text: text.into(),
+            citations: None,
+        }
     }
 
     /// Create a new image block
@@ -324,18 +486,38 @@ impl ContentBlock {
                 type_: type_.into(),
                 media_type: media_type.into(),
                 data: data.into(),
+                url: None,
             },
         }
     }
 }
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct CountMessageTokensParams {
     pub model: String,
     pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
+}
+
+impl From<CountMessageTokensParams> for CreateMessageParams {
+    fn from(p: CountMessageTokensParams) -> Self {
+        Self {
+            model: p.model,
+            messages: p.messages,
+            system: p.system,
+            thinking: p.thinking,
+            tools: p.tools,
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CountMessageTokensResponse {
     pub input_tokens: u32,
 }
@@ -394,6 +576,8 @@ pub enum ContentBlockDelta {
     ThinkingDelta { thinking: String },
     #[serde(rename = "signature_delta")]
     SignatureDelta { signature: String },
+    #[serde(rename = "citations_delta")]
+    CitationsDelta { citation: serde_json::Value },
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
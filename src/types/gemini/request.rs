@@ -10,8 +10,8 @@ pub enum Role {
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
 pub struct InlineData {
-    mime_type: String,
-    data: String,
+    pub mime_type: String,
+    pub data: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
@@ -32,18 +32,18 @@ pub struct ExecutableCode {
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
 pub struct FunctionCall {
     #[serde(skip_serializing_if = "Option::is_none")]
-    id: Option<String>,
-    name: String,
+    pub id: Option<String>,
+    pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    args: Option<Value>,
+    pub args: Option<Value>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
 pub struct FunctionResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
-    id: Option<String>,
-    name: String,
-    response: Value,
+    pub id: Option<String>,
+    pub name: String,
+    pub response: Value,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
@@ -92,8 +92,8 @@ pub enum Part {
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct Chat {
-    role: Role,
-    parts: Vec<Part>,
+    pub role: Role,
+    pub parts: Vec<Part>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
@@ -116,6 +116,8 @@ pub struct GeminiRequestBody {
     pub contents: Vec<Chat>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub generation_config: Option<Value>,
+    #[serde(default, alias = "toolConfig", skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
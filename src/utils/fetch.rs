@@ -0,0 +1,240 @@
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use rquest::{ClientBuilder, Url, header::CONTENT_TYPE, header::LOCATION, redirect::Policy};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tracing::debug;
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError};
+
+/// Whether a host is the domain or one of its subdomains
+///
+/// # Arguments
+/// * `host` - Lowercase host name
+/// * `domain` - Domain to match, case insensitive
+pub fn host_matches(host: &str, domain: &str) -> bool {
+    let domain = domain.trim().to_lowercase();
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// Maximum number of redirects followed when fetching a remote file
+const MAX_REDIRECTS: usize = 5;
+
+/// A file downloaded from a remote URL
+#[derive(Debug)]
+pub struct Fetched {
+    pub bytes: Bytes,
+    pub media_type: String,
+}
+
+/// Downloads a remote file with size and time limits
+///
+/// Only http(s) URLs are accepted. Every hop, including redirects, must resolve to
+/// public addresses only, and the resolved addresses are pinned for the connection
+/// so the check cannot be bypassed by DNS rebinding. If `fetch_allowlist` is set,
+/// only the listed hosts and their subdomains may be fetched.
+///
+/// When `rquest_proxy` is set, the proxy resolves host names, so the addresses
+/// cannot be checked or pinned. Fetches then go through the proxy only for hosts
+/// in `fetch_allowlist`, and every other host is refused.
+///
+/// # Arguments
+/// * `url` - URL of the file
+///
+/// # Returns
+/// * `Result<Fetched, ClewdrError>` - Content and media type of the file
+pub async fn fetch_remote(url: &str) -> Result<Fetched, ClewdrError> {
+    let config = CLEWDR_CONFIG.load();
+    let max_size = config.fetch_max_size * 1024 * 1024;
+    let timeout = Duration::from_secs(config.fetch_timeout);
+    let mut url = Url::parse(url).map_err(|e| ClewdrError::BadRequest(e.to_string()))?;
+    for _ in 0..=MAX_REDIRECTS {
+        let addrs = check_url(&url).await?;
+        let host = url.host_str().unwrap_or_default().to_string();
+        let client = ClientBuilder::new()
+            .timeout(timeout)
+            .redirect(Policy::none());
+        let client = match config.rquest_proxy {
+            // the host was checked against the allowlist
+            Some(ref proxy) => client.proxy(proxy.to_owned()),
+            None => client.resolve_to_addrs(&host, &addrs),
+        };
+        let res = client.build()?.get(url.to_owned()).send().await?;
+        if res.status().is_redirection() {
+            let location = res
+                .headers()
+                .get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .ok_or(ClewdrError::BadRequest(
+                    "Redirect without location".to_string(),
+                ))?;
+            url = url
+                .join(location)
+                .map_err(|e| ClewdrError::BadRequest(e.to_string()))?;
+            debug!("Following redirect to {}", url);
+            continue;
+        }
+        let res = res.error_for_status()?;
+        if res.content_length().is_some_and(|l| l as usize > max_size) {
+            return Err(ClewdrError::BadRequest("Remote file too large".to_string()));
+        }
+        let header_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_lowercase());
+        // read the body without exceeding the size limit
+        let mut body = BytesMut::new();
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk?);
+            if body.len() > max_size {
+                return Err(ClewdrError::BadRequest("Remote file too large".to_string()));
+            }
+        }
+        let bytes = body.freeze();
+        let media_type = sniff_media_type(&bytes)
+            .map(|m| m.to_string())
+            .or(header_type)
+            .unwrap_or("application/octet-stream".to_string());
+        return Ok(Fetched { bytes, media_type });
+    }
+    Err(ClewdrError::BadRequest("Too many redirects".to_string()))
+}
+
+/// Checks whether a URL may be fetched and resolves its host
+///
+/// # Arguments
+/// * `url` - URL to check
+///
+/// # Returns
+/// * `Result<Vec<SocketAddr>, ClewdrError>` - Public addresses of the host,
+///   empty if the host is resolved by the proxy
+async fn check_url(url: &Url) -> Result<Vec<SocketAddr>, ClewdrError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ClewdrError::BadRequest(format!(
+            "Unsupported URL scheme: {}",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or(ClewdrError::BadRequest("URL without host".to_string()))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    let config = CLEWDR_CONFIG.load();
+    let allowed = config
+        .fetch_allowlist
+        .iter()
+        .any(|a| host_matches(&host, a));
+    let proxied = config.rquest_proxy.is_some();
+    if (proxied || !config.fetch_allowlist.is_empty()) && !allowed {
+        return Err(ClewdrError::BadRequest(format!(
+            "Host not in fetch allowlist: {}",
+            host
+        )));
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        // the proxy resolves the allowed host
+        Err(_) if proxied => return Ok(vec![]),
+        Err(_) => tokio::net::lookup_host((host.as_str(), port))
+            .await?
+            .collect::<Vec<_>>(),
+    };
+    if addrs.is_empty() {
+        return Err(ClewdrError::BadRequest(format!(
+            "Failed to resolve host: {}",
+            host
+        )));
+    }
+    if let Some(addr) = addrs.iter().find(|a| !is_public(a.ip())) {
+        return Err(ClewdrError::BadRequest(format!(
+            "Refusing to fetch non-public address: {}",
+            addr.ip()
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Whether an address is publicly routable
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_v4(v4);
+            }
+            let seg = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                // documentation 2001:db8::/32
+                || (seg[0] == 0x2001 && seg[1] == 0x0db8)
+                // ipv4-compatible and nat64 addresses
+                || ip.octets()[..12] == Ipv6Addr::UNSPECIFIED.octets()[..12]
+                || (seg[0] == 0x64 && seg[1] == 0xff9b))
+        }
+    }
+}
+
+/// Whether an IPv4 address is publicly routable
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // 0.0.0.0/8
+        || a == 0
+        // shared address space 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // ietf protocol assignments 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+/// Detects the media type of a file from its magic bytes
+///
+/// # Arguments
+/// * `bytes` - Content of the file
+///
+/// # Returns
+/// * `Option<&'static str>` - The media type if recognized
+pub fn sniff_media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [
+            b'R',
+            b'I',
+            b'F',
+            b'F',
+            _,
+            _,
+            _,
+            _,
+            b'W',
+            b'E',
+            b'B',
+            b'P',
+            ..,
+        ] => Some("image/webp"),
+        [b'%', b'P', b'D', b'F', ..] => Some("application/pdf"),
+        _ => None,
+    }
+}
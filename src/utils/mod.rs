@@ -1,5 +1,9 @@
+pub mod fetch;
+pub mod retry;
+
 use colored::{ColoredString, Colorize};
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{fs, path::PathBuf, str::FromStr, sync::LazyLock};
+use tiktoken_rs::{CoreBPE, o200k_base};
 use tracing::error;
 
 use crate::{IS_DEV, config::LOG_DIR, error::ClewdrError};
@@ -88,5 +92,6 @@ pub fn print_out_text(text: &str, file_name: &str) {
     }
 }
 
-/// Timezone for the API
-pub const TIME_ZONE: &str = "America/New_York";
+/// Tokenizer used to estimate token counts
+pub static TOKENIZER: LazyLock<CoreBPE> =
+    LazyLock::new(|| o200k_base().expect("Failed to load tokenizer"));
@@ -0,0 +1,38 @@
+use std::time::Instant;
+use tokio::time::sleep;
+
+use crate::config::RetryPolicy;
+
+/// Waits between the retries of a request, following a retry policy
+pub struct Backoff {
+    policy: RetryPolicy,
+    start: Instant,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Starts the backoff of a request
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            start: Instant::now(),
+            attempt: 0,
+        }
+    }
+
+    /// Sleeps before the next retry
+    ///
+    /// # Returns
+    /// * `bool` - False if the retry would start after the deadline
+    pub async fn wait(&mut self) -> bool {
+        let delay = self.policy.delay(self.attempt);
+        if let Some(deadline) = self.policy.deadline()
+            && self.start.elapsed() + delay > deadline
+        {
+            return false;
+        }
+        self.attempt += 1;
+        sleep(delay).await;
+        true
+    }
+}